@@ -0,0 +1,130 @@
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, LitStr};
+
+/// !```
+/// #[derive(Serialize, Deserialize, Collection)]
+/// #[moedb(name = "people")]
+/// struct Person {
+///     #[moedb(key)]
+///     ids: String,
+///     hello: Vec<String>,
+///     age: Option<i64>,                 // `_optional`
+///     #[moedb(rename = "nickName")]
+///     #[serde(rename = "nickName")]
+///     nick_name: String,
+///     #[moedb(skip)]
+///     cached: String,                   // not part of the schema
+/// }
+/// !```
+#[proc_macro_derive(Collection, attributes(moedb))]
+pub fn derive_collection(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(ts) => ts.into(),
+        Err(er) => er.to_compile_error().into()
+    }
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let ident = &input.ident;
+    let mut name = ident.to_string().to_lowercase();
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("moedb")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("name") {
+                let v: LitStr = meta.value()?.parse()?;
+                name = v.value();
+                return Ok(());
+            }
+            Err(meta.error("supported collection attributes: name"))
+        })?;
+    }
+    check_name(name.as_str(), ident.span())?;
+
+    let fields = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(named) => &named.named,
+            _ => return Err(Error::new(ident.span(), "Collection needs a struct with named fields"))
+        },
+        _ => return Err(Error::new(ident.span(), "Collection can only be derived for structs"))
+    };
+
+    let mut key = None;
+    let mut decls = vec![];
+    for f in fields {
+        let mut field = f.ident.as_ref().unwrap().to_string();
+        let mut is_key = false;
+        let mut skip = false;
+        for attr in f.attrs.iter().filter(|a| a.path().is_ident("moedb")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("key") {
+                    is_key = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    return Ok(());
+                }
+                if meta.path.is_ident("rename") {
+                    let v: LitStr = meta.value()?.parse()?;
+                    field = v.value();
+                    return Ok(());
+                }
+                Err(meta.error("supported field attributes: key, skip, rename"))
+            })?;
+        }
+        if skip {
+            continue;
+        }
+        check_name(field.as_str(), f.span())?;
+        if is_key {
+            if key.is_some() {
+                return Err(Error::new(f.span(), "only one field can be #[moedb(key)]"));
+            }
+            key = Some(field.clone());
+        }
+        let ty = &f.ty;
+        decls.push(quote! { moedb::mdl::field::<#ty>(#field) });
+    }
+    if key.is_none() {
+        return Err(Error::new(ident.span(), "mark the primary key field with #[moedb(key)]"));
+    }
+    let key = key.unwrap();
+
+    // plain `moedb::` rather than `::moedb::` so the crate's own tests can alias `use crate as moedb;`
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics moedb::mdl::Collection for #ident #ty_generics #where_clause {
+            fn name() -> &'static str {
+                #name
+            }
+
+            fn key_field() -> &'static str {
+                #key
+            }
+
+            fn fields() -> ::std::vec::Vec<moedb::hdrs::JqlSchemaFields> {
+                ::std::vec![#(#decls),*]
+            }
+        }
+    })
+}
+
+///
+// same rule as `Jql::naming_regx`, caught here instead of on `create-collection`
+///
+fn check_name(name: &str, span: Span) -> syn::Result<()> {
+    let mut chars = name.chars();
+    let first = chars.next().map_or(false, |c| c.is_ascii_alphabetic());
+    let rest = chars.all(|c| c.is_ascii_alphanumeric() || c == '-');
+    let len = (3..=21).contains(&name.len());
+    if first && rest && len {
+        return Ok(());
+    }
+    Err(Error::new(span, format!(
+        "`{}` is not a valid moedb name, it needs a letter followed by 2 to 20 letters, digits or dashes (see #[moedb(rename = \"..\")])",
+        name
+    )))
+}
@@ -18,6 +18,17 @@ impl From<&str> for ActionType {
             "drop-collection" => ActionType::Drop,
             "drop-db" => ActionType::DropDb,
             "truncate" => ActionType::Truncate,
+            "alter-collection" => ActionType::Alter,
+            "put-object" => ActionType::PutObject,
+            "get-object" => ActionType::GetObject,
+            "delete-object" => ActionType::DeleteObject,
+            "list-objects" => ActionType::ListObjects,
+            "begin-upload" => ActionType::BeginUpload,
+            "put-part" => ActionType::PutPart,
+            "complete-upload" => ActionType::CompleteUpload,
+            "abort-upload" => ActionType::AbortUpload,
+            "list-parts" => ActionType::ListParts,
+            "backup" => ActionType::Backup,
             "db-*" => ActionType::DbList,
             "col-*" => ActionType::ColList,
             _ => ActionType::Unknown
@@ -38,6 +49,17 @@ impl ActionType {
             ActionType::DbList => "db-*".to_string(),
             ActionType::Truncate => "truncate".to_string(),
             ActionType::ColList => "col-*".to_string(),
+            ActionType::Alter => "alter-collection".to_string(),
+            ActionType::PutObject => "put-object".to_string(),
+            ActionType::GetObject => "get-object".to_string(),
+            ActionType::DeleteObject => "delete-object".to_string(),
+            ActionType::ListObjects => "list-objects".to_string(),
+            ActionType::BeginUpload => "begin-upload".to_string(),
+            ActionType::PutPart => "put-part".to_string(),
+            ActionType::CompleteUpload => "complete-upload".to_string(),
+            ActionType::AbortUpload => "abort-upload".to_string(),
+            ActionType::ListParts => "list-parts".to_string(),
+            ActionType::Backup => "backup".to_string(),
             _ => "".to_string()
         }
     }
@@ -0,0 +1,203 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use crate::err::MoeDbError;
+use crate::hdrs::{AsyncMoeDb, JqlCommand, JqlSchema, MoeDb, Query, WireClient};
+use crate::mdl::Collection;
+
+///
+/// Typed access to MoeDb. Implementors only provide `run`, everything else is built on it,
+/// so the same calls work in-process (`AsyncMoeDb`) and over the wire (`WireClient`)
+///
+#[async_trait]
+pub trait MoeDbAsync: Sync {
+    async fn run(&self, cmd: JqlCommand) -> Result<Option<Vec<Value>>, MoeDbError>;
+
+    async fn create_db(&self, db: &str) -> Result<(), MoeDbError> {
+        self.run(Query::database(db).create()).await.map(|_| ())
+    }
+
+    async fn drop_db(&self, db: &str) -> Result<(), MoeDbError> {
+        self.run(Query::database(db).drop()).await.map(|_| ())
+    }
+
+    async fn create_collection(&self, db: &str, schema: &JqlSchema) -> Result<(), MoeDbError> {
+        self.run(Query::database(db).create_collection(schema)).await.map(|_| ())
+    }
+
+    async fn drop_collection(&self, db: &str, col: &str) -> Result<(), MoeDbError> {
+        self.run(Query::collection(db, col).drop()).await.map(|_| ())
+    }
+
+    async fn get<T: DeserializeOwned + Send>(&self, db: &str, col: &str, id: &str) -> Result<Option<T>, MoeDbError> {
+        let data = self.run(Query::collection(db, col).id(id).get()).await?;
+        let found = data.unwrap_or_default().into_iter().next();
+        match found {
+            None => Ok(None),
+            Some(doc) => decode::<T>(doc).map(Some)
+        }
+    }
+
+    ///
+    // anything `Query` can express (filter, sort, skip, limit) for the given collection
+    ///
+    async fn find<T: DeserializeOwned + Send>(&self, query: Query) -> Result<Vec<T>, MoeDbError> {
+        let data = self.run(query.get()).await?;
+        data.unwrap_or_default()
+            .into_iter()
+            .map(decode::<T>)
+            .collect()
+    }
+
+    ///
+    // returns the key the document was stored under
+    ///
+    async fn upsert<T: Serialize + Sync>(&self, db: &str, col: &str, doc: &T) -> Result<String, MoeDbError> {
+        let cmd = Query::collection(db, col).upsert(doc)?;
+        let data = self.run(cmd).await?;
+        let key = data.unwrap_or_default().into_iter().next();
+        match key {
+            Some(Value::String(key)) => Ok(key),
+            _ => Err(MoeDbError::DocumentError("no key returned for upserted document".to_string()))
+        }
+    }
+
+    ///
+    // `true` when a document was actually removed
+    ///
+    async fn delete(&self, db: &str, col: &str, id: &str) -> Result<bool, MoeDbError> {
+        let data = self.run(Query::collection(db, col).id(id).delete()).await?;
+        Ok(!data.unwrap_or_default().is_empty())
+    }
+
+    async fn create_collection_of<T: Collection>(&self, db: &str) -> Result<(), MoeDbError> {
+        self.create_collection(db, &T::schema()).await
+    }
+
+    async fn get_of<T: Collection + Send>(&self, db: &str, id: &str) -> Result<Option<T>, MoeDbError> {
+        self.get::<T>(db, T::name(), id).await
+    }
+
+    async fn upsert_of<T: Collection + Sync>(&self, db: &str, doc: &T) -> Result<String, MoeDbError> {
+        self.upsert(db, T::name(), doc).await
+    }
+
+    async fn delete_of<T: Collection>(&self, db: &str, id: &str) -> Result<bool, MoeDbError> {
+        self.delete(db, T::name(), id).await
+    }
+}
+
+pub fn decode<T: DeserializeOwned>(doc: Value) -> Result<T, MoeDbError> {
+    let decoded = serde_json::from_value::<T>(doc);
+    if decoded.is_err() {
+        return Err(MoeDbError::DocumentError(decoded.err().unwrap().to_string()));
+    }
+    Ok(decoded.unwrap())
+}
+
+impl AsyncMoeDb {
+    pub fn new(db: MoeDb) -> Self {
+        Self {
+            db: Arc::new(db)
+        }
+    }
+}
+
+#[async_trait]
+impl MoeDbAsync for AsyncMoeDb {
+    ///
+    // RocksDB calls block, so the statement runs on tokio's blocking pool
+    ///
+    async fn run(&self, cmd: JqlCommand) -> Result<Option<Vec<Value>>, MoeDbError> {
+        let db = Arc::clone(&self.db);
+        let stmt = cmd.to_jql();
+        let res = tokio::task::spawn_blocking(move || db.execute_with_error(stmt.as_str())).await;
+        if res.is_err() {
+            return Err(MoeDbError::RuntimeError(res.err().unwrap().to_string()));
+        }
+        match res.unwrap() {
+            (_, Some(err)) => Err(err),
+            (res, None) => Ok(res.data)
+        }
+    }
+}
+
+#[async_trait]
+impl MoeDbAsync for WireClient {
+    async fn run(&self, cmd: JqlCommand) -> Result<Option<Vec<Value>>, MoeDbError> {
+        let stmt = cmd.to_jql();
+        let res = self.execute(stmt.as_str()).await?;
+        if res.error {
+            return Err(MoeDbError::RemoteError(res.message));
+        }
+        Ok(res.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use serde_derive::Deserialize;
+    use serde_json::json;
+    use crate::hdrs::Jql;
+    use super::*;
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Op {
+        ids: String,
+        age: u32,
+    }
+
+    ///
+    // answers every command with the next canned result and keeps what it was sent
+    ///
+    struct Scripted {
+        sent: Mutex<Vec<JqlCommand>>,
+        answers: Mutex<Vec<Result<Option<Vec<Value>>, MoeDbError>>>,
+    }
+
+    #[async_trait]
+    impl MoeDbAsync for Scripted {
+        async fn run(&self, cmd: JqlCommand) -> Result<Option<Vec<Value>>, MoeDbError> {
+            assert!(Jql::parse(cmd.to_jql().as_str()).is_ok());
+            self.sent.lock().unwrap().push(cmd);
+            self.answers.lock().unwrap().remove(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_calls() {
+        let db = Scripted {
+            sent: Mutex::new(vec![]),
+            answers: Mutex::new(vec![
+                Ok(Some(vec![json!("a1")])),
+                Ok(Some(vec![json!({ "ids": "a1", "age": 3, "_version": 1 })])),
+                Ok(Some(vec![])),
+                Ok(Some(vec![json!({ "ids": "a1", "age": "three" })])),
+                Ok(Some(vec![json!({ "ids": "a1", "age": 3 }), json!({ "ids": "b2", "age": 4 })])),
+                Ok(Some(vec![json!("a1")])),
+                Ok(Some(vec![])),
+                Ok(None),
+                Err(MoeDbError::RemoteError("collection does not exist `ops`".to_string())),
+            ]),
+        };
+        assert_eq!(db.upsert("moss", "ops", &json!({ "ids": "a1", "age": 3 })).await.unwrap(), "a1");
+        assert_eq!(db.get::<Op>("moss", "ops", "a1").await.unwrap(), Some(Op { ids: "a1".to_string(), age: 3 }));
+        assert_eq!(db.get::<Op>("moss", "ops", "zz").await.unwrap(), None);
+        assert!(matches!(db.get::<Op>("moss", "ops", "a1").await, Err(MoeDbError::DocumentError(_))));
+        let found = db.find::<Op>(Query::collection("moss", "ops").filter("age", 3)).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(db.delete("moss", "ops", "a1").await.unwrap());
+        assert!(!db.delete("moss", "ops", "a1").await.unwrap());
+        assert!(db.upsert("moss", "ops", &json!({ "ids": "c3", "age": 5 })).await.is_err());
+        assert!(matches!(db.drop_collection("moss", "ops").await, Err(MoeDbError::RemoteError(_))));
+
+        let sent = db.sent.lock().unwrap();
+        let actions = sent.iter().map(|c| c._action.as_str()).collect::<Vec<&str>>();
+        assert_eq!(actions, vec!["upsert", "get", "get", "get", "get", "delete", "delete", "upsert", "drop-collection"]);
+        assert_eq!(sent[1]._body, Some(json!({ "_id": "a1" })));
+        assert_eq!(sent[4]._body, Some(json!({ "_filter": { "age": 3 } })));
+    }
+}
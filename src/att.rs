@@ -0,0 +1,225 @@
+use std::collections::BTreeMap;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{error, trace};
+use serde_json::Value;
+use crate::err::TrxError;
+use crate::hdrs::{Attachment, DataTypes, Exec, JqlSchema, TKey, Types};
+use crate::util::key_merger;
+use crate::var::INLINE_LIMIT;
+
+/// !```
+/// { "_name": "avatar", "_declare": "blob" }                   in `_fields`
+/// { "avatar": { "_bucket": "avatars", "_key": "users/1.png" } } in the document
+/// { "_id": "u1", "_inline": true }                             in a `get`, or a byte limit instead of `true`
+/// !```
+/// the object has to exist when the document is upserted, and the document claims it in the object's
+/// `_owner` before it is written. An object belongs to one document at a time, a document referencing
+/// one held by another is refused. Replacing an attachment, deleting or expiring the document, or
+/// truncating or dropping its collection deletes the objects the document held, objects it didn't
+/// claim are left alone. `_inline` swaps each attachment of a result for its meta, with the base64
+/// `_data` of those up to the limit, never more than `INLINE_LIMIT`
+
+impl JqlSchema {
+    pub fn blob_fields(&self) -> Vec<&str> {
+        self._fields
+            .iter()
+            .filter(|f| DataTypes::from(f._declare.clone()) == DataTypes::Blob)
+            .map(|f| f._name.as_str())
+            .collect()
+    }
+
+    pub fn attachments(&self, doc: &Value) -> Vec<Attachment> {
+        self.blob_fields()
+            .into_iter()
+            .filter_map(|f| doc.get(f))
+            .filter_map(|v| serde_json::from_value::<Attachment>(v.clone()).ok())
+            .collect()
+    }
+}
+
+///
+// `true` inlines up to `INLINE_LIMIT` bytes, a number sets a lower limit, anything else leaves attachments as they are
+///
+pub fn inline_limit(body: &Value) -> Option<u64> {
+    match body.get(Types::Inline.as_str()) {
+        Some(Value::Bool(true)) => Some(INLINE_LIMIT),
+        Some(Value::Number(n)) => n.as_u64()
+            .or_else(|| n.as_f64().filter(|f| *f >= 0.0).map(|f| f as u64))
+            .map(|n| n.min(INLINE_LIMIT)),
+        _ => None
+    }
+}
+
+///
+// who an attachment belongs to, the collection and the key of the document as `upsert` returns it
+///
+pub fn attachment_owner(col: &str, key: &str) -> String {
+    key_merger(vec![col.to_string(), key.to_string()]).unwrap()
+}
+
+impl Exec {
+    ///
+    // every attachment has to exist and be free or already this document's, and a batch can't hand the same
+    // object to two documents
+    ///
+    pub fn check_attachments(&self, db: &str, col: &str, schema: &JqlSchema, keyed: &[(TKey, Value)]) -> Result<(), TrxError> {
+        let mut claimed: BTreeMap<(String, String), String> = BTreeMap::new();
+        for (key, doc) in keyed {
+            let owner = attachment_owner(col, schema.decode_key(key).as_str());
+            for att in schema.attachments(doc) {
+                let meta = self.object_meta(db, att._bucket.as_str(), att._key.as_str());
+                if meta.is_none() {
+                    return Err(TrxError::UpsertDocumentError(format!("attachment `{}` does not exist in bucket `{}`", att._key, att._bucket)));
+                }
+                let held = meta.unwrap()._owner;
+                let taken = claimed.insert((att._bucket.clone(), att._key.clone()), owner.clone());
+                if held.as_ref().map_or(false, |h| !h.eq(&owner)) || taken.map_or(false, |t| !t.eq(&owner)) {
+                    return Err(TrxError::UpsertDocumentError(format!("attachment `{}` in bucket `{}` belongs to another document", att._key, att._bucket)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    // claims the attachments of a batch before it is written, the swap lock of each claim keeps anybody else
+    // from taking the object between the check and the write. When one is taken, or the write fails later,
+    // `release_attachments` lets go of what was claimed here
+    ///
+    pub fn claim_attachments(&self, db: &str, col: &str, schema: &JqlSchema, keyed: &[(TKey, Value)]) -> Result<Vec<(String, Attachment)>, TrxError> {
+        let mut claimed = vec![];
+        for (key, doc) in keyed {
+            let owner = attachment_owner(col, schema.decode_key(key).as_str());
+            for att in schema.attachments(doc) {
+                let taken = self.claim_object(db, att._bucket.as_str(), att._key.as_str(), owner.as_str());
+                if taken.is_err() {
+                    self.release_attachments(db, claimed);
+                    return Err(TrxError::UpsertDocumentError(format!("attachment `{}` in bucket `{}` {}", att._key, att._bucket, taken.err().unwrap())));
+                }
+                if taken.unwrap() {
+                    claimed.push((owner.clone(), att));
+                }
+            }
+        }
+        Ok(claimed)
+    }
+
+    pub fn release_attachments(&self, db: &str, claimed: Vec<(String, Attachment)>) {
+        for (owner, att) in claimed {
+            let released = self.release_object(db, att._bucket.as_str(), att._key.as_str(), owner.as_str());
+            if released.is_err() {
+                error!("releasing attachment `{}` {}", att._key, released.err().unwrap());
+            }
+        }
+    }
+
+    ///
+    // after a document is written, the attachments it no longer references go away
+    ///
+    pub fn settle_attachments(&self, db: &str, col: &str, schema: &JqlSchema, key: &TKey, doc: &Value, previous: Option<&Value>) {
+        if previous.is_none() {
+            return;
+        }
+        let owner = attachment_owner(col, schema.decode_key(key).as_str());
+        let current = schema.attachments(doc);
+        let dropped = schema.attachments(previous.unwrap())
+            .into_iter()
+            .filter(|att| !current.contains(att))
+            .collect::<Vec<Attachment>>();
+        self.drop_owned(db, owner.as_str(), dropped);
+    }
+
+    ///
+    // the owner is gone by now, only objects it holds are removed and failures are only logged
+    ///
+    pub fn drop_attachments(&self, db: &str, col: &str, schema: &JqlSchema, key: &TKey, doc: &Value) {
+        let owner = attachment_owner(col, schema.decode_key(key).as_str());
+        self.drop_owned(db, owner.as_str(), schema.attachments(doc));
+    }
+
+    fn drop_owned(&self, db: &str, owner: &str, atts: Vec<Attachment>) {
+        for att in atts {
+            let meta = self.object_meta(db, att._bucket.as_str(), att._key.as_str());
+            if meta.map_or(true, |m| m._owner.as_deref() != Some(owner)) {
+                trace!("attachment `{}` isn't held by `{}`, left in place", att._key, owner);
+                continue;
+            }
+            let dlt = self.remove_held_object(db, att._bucket.as_str(), att._key.as_str(), Some(owner));
+            if dlt.is_err() {
+                error!("dropping attachment `{}` {}", att._key, dlt.err().unwrap());
+            }
+        }
+    }
+
+    ///
+    // before a collection's column family goes away, every attachment its documents hold goes with it
+    ///
+    pub fn drop_col_attachments(&self, db: &str, col: &str, name: &str) {
+        let schema = self.schema(db, col);
+        if schema.as_ref().map_or(true, |s| s.blob_fields().is_empty()) || !self.trx.has_cf(name) {
+            return;
+        }
+        let schema = schema.unwrap();
+        let docs = self.trx.entries(name, vec![]);
+        trace!("dropping attachments of {} documents in `{}`", docs.len(), name);
+        for (key, doc) in docs {
+            self.drop_attachments(db, col, &schema, &key, &doc);
+        }
+    }
+
+    pub fn inline_attachments(&self, db: &str, schema: &JqlSchema, doc: Value, limit: u64) -> Value {
+        let mut doc = doc;
+        for field in schema.blob_fields() {
+            let att = doc.get(field).and_then(|v| serde_json::from_value::<Attachment>(v.clone()).ok());
+            if att.is_none() {
+                continue;
+            }
+            let att = att.unwrap();
+            let meta = self.object_meta(db, att._bucket.as_str(), att._key.as_str());
+            if meta.is_none() {
+                continue;
+            }
+            let meta = meta.unwrap();
+            let mut shown = meta.to_response();
+            shown.as_object_mut().unwrap().insert(Types::Bucket.as_str().to_string(), Value::from(att._bucket.as_str()));
+            if meta._size <= limit {
+                let read = self.read_object(db, att._bucket.as_str(), att._key.as_str());
+                if read.is_err() {
+                    error!("inlining attachment `{}` {}", att._key, read.err().unwrap());
+                    continue;
+                }
+                shown.as_object_mut().unwrap().insert(Types::Data.as_str().to_string(), Value::from(STANDARD.encode(read.unwrap().1)));
+            }
+            doc.as_object_mut().unwrap().insert(field.to_string(), shown);
+        }
+        doc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn blob_references() {
+        let users = JqlSchema::builder("users", "ids")
+            .field("avatar", DataTypes::Blob)
+            .optional("resume", DataTypes::Blob)
+            .field("name", DataTypes::String)
+            .build();
+        assert_eq!(users.blob_fields(), vec!["avatar", "resume"]);
+        let doc = json!({ "ids": "u1", "name": "moe", "avatar": { "_bucket": "avatars", "_key": "u1.png" } });
+        assert_eq!(users.attachments(&doc), vec![Attachment { _bucket: "avatars".to_string(), _key: "u1.png".to_string() }]);
+        assert!(users.attachments(&json!({ "ids": "u2" })).is_empty());
+
+        assert_eq!(inline_limit(&json!({ "_inline": true })), Some(INLINE_LIMIT));
+        assert_eq!(inline_limit(&json!({ "_inline": 10 })), Some(10));
+        assert_eq!(inline_limit(&json!({ "_inline": 1e12 })), Some(INLINE_LIMIT));
+        assert_eq!(inline_limit(&json!({ "_inline": 1000000000000u64 })), Some(INLINE_LIMIT));
+        assert_eq!(inline_limit(&json!({ "_inline": false })), None);
+        assert_eq!(inline_limit(&json!({})), None);
+        assert_eq!(attachment_owner("users", "u1"), "users#u1");
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use log::{info, warn};
+use rocksdb::Env;
+use rocksdb::backup::{BackupEngine, BackupEngineOptions, RestoreOptions};
+use rocksdb::checkpoint::Checkpoint;
+use serde_json::Value;
+use crate::env;
+use crate::err::{MoeDbError, TrxError};
+use crate::hdrs::{BackupFile, BackupManifest, BackupMode, DbRes, Exec, JqlCommand, MoeDb, Trx, Types};
+use crate::ttl::now_secs;
+use crate::util::query_log_cf_path;
+use crate::var::{BACKUP_LOG, BACKUP_MAIN, BACKUP_MANIFEST, KEY_FORMAT};
+
+/// !```
+/// { "_action": "backup", "_body": { "_target": "nightly", "_mode": "checkpoint|incremental", "_keep": 7 } }
+/// !```
+/// `_target` is a directory under `backup_path` of the config, backups are refused when it isn't set.
+/// `checkpoint` (default) hard links a consistent copy of the main db and the query log into a new
+/// directory and records the size and crc32 of every file. `incremental` keeps a backup engine per db
+/// in the target, every run only copies what changed since the last one and `_keep` purges the older
+/// runs. The manifest records the crc32 of every file the engines keep, files already summed by the
+/// previous run are taken from its manifest. Each db is consistent on its own, the query log may be a
+/// few entries apart from the main db. `MoeDb::restore` checks the backup before anything is written
+/// into an empty `db_path`
+
+impl Display for BackupMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for BackupMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "incremental" => BackupMode::Incremental,
+            _ => BackupMode::Checkpoint
+        }
+    }
+}
+
+impl BackupMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            BackupMode::Checkpoint => "checkpoint",
+            BackupMode::Incremental => "incremental"
+        }
+    }
+}
+
+impl PartialEq for BackupMode {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
+
+///
+// a single directory name, nothing that climbs out of `backup_path`
+///
+pub fn is_target_ok(target: &str) -> bool {
+    !target.is_empty()
+        && !target.starts_with('.')
+        && target.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+fn file_checksum(path: &Path) -> Result<(u64, String), std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        size += read as u64;
+    }
+    Ok((size, format!("{:08x}", hasher.finalize())))
+}
+
+///
+// every file under `dir`, relative to `root` and sorted
+///
+fn files_under(root: &Path, dir: &Path, out: &mut Vec<String>) -> Result<(), std::io::Error> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files_under(root, path.as_path(), out)?;
+        } else {
+            out.push(path.strip_prefix(root).unwrap().to_string_lossy().to_string());
+        }
+    }
+    out.sort();
+    Ok(())
+}
+
+///
+// size and crc32 of every file under `dir`, relative to `root`. Backup engines never rewrite a file,
+// the ones `previous` lists with the same size keep their sum
+///
+fn checksums(root: &Path, dir: &Path, previous: &[BackupFile]) -> Result<Vec<BackupFile>, TrxError> {
+    let mut paths = vec![];
+    let listed = files_under(root, dir, &mut paths);
+    if listed.is_err() {
+        return Err(TrxError::BackupError(listed.err().unwrap().to_string()));
+    }
+    let mut files = vec![];
+    for path in paths {
+        let size = fs::metadata(root.join(path.as_str())).map(|m| m.len()).unwrap_or_default();
+        let known = previous.iter().find(|f| f._path == path && f._size == size);
+        if known.is_some() {
+            files.push(known.unwrap().clone());
+            continue;
+        }
+        let sum = file_checksum(root.join(path.as_str()).as_path());
+        if sum.is_err() {
+            return Err(TrxError::BackupError(format!("`{}` {}", path, sum.err().unwrap())));
+        }
+        let (size, sum) = sum.unwrap();
+        files.push(BackupFile { _path: path, _size: size, _checksum: sum });
+    }
+    Ok(files)
+}
+
+fn read_manifest(dir: &Path) -> Option<BackupManifest> {
+    fs::read(dir.join(BACKUP_MANIFEST))
+        .ok()
+        .and_then(|m| serde_json::from_slice::<BackupManifest>(&m).ok())
+}
+
+fn write_manifest(dir: &Path, manifest: &BackupManifest) -> Result<(), TrxError> {
+    fs::write(dir.join(BACKUP_MANIFEST), serde_json::to_vec_pretty(manifest).unwrap())
+        .map_err(|e| TrxError::BackupError(e.to_string()))
+}
+
+fn engine(dir: &Path) -> Result<BackupEngine, TrxError> {
+    let opts = BackupEngineOptions::new(dir);
+    if opts.is_err() {
+        return Err(TrxError::BackupError(opts.err().unwrap().to_string()));
+    }
+    let env = Env::new();
+    if env.is_err() {
+        return Err(TrxError::BackupError(env.err().unwrap().to_string()));
+    }
+    BackupEngine::open(&opts.unwrap(), &env.unwrap()).map_err(|e| TrxError::BackupError(e.to_string()))
+}
+
+///
+// the manifest has to be there, written by a binary that reads its key format, and every file has to
+// match what it recorded. Incremental backups are checked by their engine as well
+///
+pub fn verify(dir: &Path) -> Result<BackupManifest, TrxError> {
+    let manifest = read_manifest(dir);
+    if manifest.is_none() {
+        return Err(TrxError::BackupError(format!("no readable {} in `{}`, the backup did not complete", BACKUP_MANIFEST, dir.display())));
+    }
+    let manifest = manifest.unwrap();
+    if manifest._key_format > KEY_FORMAT {
+        return Err(TrxError::BackupError(format!("backup uses key format {}, this build reads up to {}", manifest._key_format, KEY_FORMAT)));
+    }
+    if BackupMode::from(manifest._mode.as_str()) == BackupMode::Incremental {
+        for (name, id) in manifest._backup_ids.iter() {
+            let checked = engine(dir.join(name).as_path()).and_then(|e| e.verify_backup(*id).map_err(|e| TrxError::BackupError(e.to_string())));
+            if checked.is_err() {
+                return Err(TrxError::BackupError(format!("backup {} of `{}` {}", id, name, checked.err().unwrap())));
+            }
+        }
+    }
+    if manifest._files.is_empty() {
+        return Err(TrxError::BackupError("manifest lists no files".to_string()));
+    }
+    for file in manifest._files.iter() {
+        let found = file_checksum(dir.join(file._path.as_str()).as_path());
+        match found {
+            Ok((size, sum)) if size == file._size && sum == file._checksum => {}
+            Ok(_) => return Err(TrxError::BackupError(format!("`{}` does not match the manifest", file._path))),
+            Err(err) => return Err(TrxError::BackupError(format!("`{}` {}", file._path, err)))
+        }
+    }
+    Ok(manifest)
+}
+
+fn is_db_dir(path: &Path) -> bool {
+    path.join("CURRENT").exists()
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let dest = to.join(path.file_name().unwrap());
+        if path.is_dir() {
+            copy_dir(path.as_path(), dest.as_path())?;
+        } else {
+            fs::copy(path.as_path(), dest.as_path())?;
+        }
+    }
+    Ok(())
+}
+
+///
+// restores into `db_path` of the config and the query log beneath it. Both have to be empty, the main
+// db goes first since restoring it clears the files of its directory
+///
+pub fn restore(dir: &Path, cfg: &env::MoeDb) -> Result<BackupManifest, TrxError> {
+    let manifest = verify(dir);
+    if manifest.is_err() {
+        return manifest;
+    }
+    let manifest = manifest.unwrap();
+    let db_path = PathBuf::from(cfg.db_path.as_str());
+    let log_path = PathBuf::from(query_log_cf_path(cfg.db_path.as_str()));
+    if is_db_dir(db_path.as_path()) || is_db_dir(log_path.as_path()) {
+        return Err(TrxError::BackupError(format!("`{}` already holds a db, restore into an empty path", db_path.display())));
+    }
+    for (name, target) in [(BACKUP_MAIN, db_path.as_path()), (BACKUP_LOG, log_path.as_path())] {
+        let source = dir.join(name);
+        if !source.exists() {
+            continue;
+        }
+        let restored = match BackupMode::from(manifest._mode.as_str()) {
+            BackupMode::Checkpoint => copy_dir(source.as_path(), target).map_err(|e| TrxError::BackupError(e.to_string())),
+            BackupMode::Incremental => {
+                let id = manifest._backup_ids.get(name).copied().unwrap_or_default();
+                engine(source.as_path()).and_then(|mut e| {
+                    e.restore_from_backup(target, target, &RestoreOptions::default(), id)
+                        .map_err(|e| TrxError::BackupError(e.to_string()))
+                })
+            }
+        };
+        if restored.is_err() {
+            return Err(restored.err().unwrap());
+        }
+        info!("restored `{}` into {}", name, target.display());
+    }
+    Ok(manifest)
+}
+
+impl Exec {
+    ///
+    // `dir` is taken as is, the `backup` action only hands in directories under `backup_path`
+    ///
+    pub fn backup(&self, dir: &Path, mode: BackupMode, keep: Option<usize>) -> Result<BackupManifest, TrxError> {
+        let _lock = self.backups.lock().unwrap();
+        let mut dbs = vec![(BACKUP_MAIN, &self.trx)];
+        match self.log.as_ref() {
+            Some(log) => dbs.push((BACKUP_LOG, log)),
+            None => warn!("no query log to back up")
+        }
+        let created = match mode {
+            BackupMode::Checkpoint => Self::checkpoint(dir, &dbs),
+            BackupMode::Incremental => Self::incremental(dir, &dbs, keep)
+        };
+        if created.is_err() {
+            return created;
+        }
+        let manifest = created.unwrap();
+        let written = write_manifest(dir, &manifest);
+        if written.is_err() {
+            return Err(written.err().unwrap());
+        }
+        info!("{} backup written to {}", mode, dir.display());
+        Ok(manifest)
+    }
+
+    fn checkpoint(dir: &Path, dbs: &[(&str, &Arc<Trx>)]) -> Result<BackupManifest, TrxError> {
+        if dir.exists() {
+            return Err(TrxError::BackupError(format!("`{}` already exists, checkpoints go into a new directory", dir.display())));
+        }
+        let created = fs::create_dir_all(dir)
+            .map_err(|e| TrxError::BackupError(e.to_string()))
+            .and_then(|_| {
+                for (name, trx) in dbs {
+                    let taken = Checkpoint::new(trx.db.as_ref()).and_then(|c| c.create_checkpoint(dir.join(name)));
+                    if taken.is_err() {
+                        return Err(TrxError::BackupError(format!("`{}` {}", name, taken.err().unwrap())));
+                    }
+                }
+                checksums(dir, dir, &[])
+            });
+        if created.is_err() {
+            // a half written checkpoint is of no use to anyone
+            let _ = fs::remove_dir_all(dir);
+            return Err(created.err().unwrap());
+        }
+        Ok(BackupManifest {
+            _mode: BackupMode::Checkpoint.to_string(),
+            _created_at: now_secs(),
+            _key_format: KEY_FORMAT,
+            _files: created.unwrap(),
+            _backup_ids: BTreeMap::new(),
+        })
+    }
+
+    fn incremental(dir: &Path, dbs: &[(&str, &Arc<Trx>)], keep: Option<usize>) -> Result<BackupManifest, TrxError> {
+        let previous = read_manifest(dir);
+        if previous.as_ref().map_or(false, |m| BackupMode::from(m._mode.as_str()) != BackupMode::Incremental) {
+            return Err(TrxError::BackupError(format!("`{}` holds a checkpoint, incremental backups need their own directory", dir.display())));
+        }
+        let previous = previous.map(|m| m._files).unwrap_or_default();
+        let mut ids = BTreeMap::new();
+        let mut files = vec![];
+        for (name, trx) in dbs {
+            let engine = engine(dir.join(name).as_path());
+            if engine.is_err() {
+                return Err(engine.err().unwrap());
+            }
+            let mut engine = engine.unwrap();
+            let taken = engine.create_new_backup_flush(trx.db.as_ref(), true);
+            if taken.is_err() {
+                return Err(TrxError::BackupError(format!("`{}` {}", name, taken.err().unwrap())));
+            }
+            if keep.is_some() {
+                let purged = engine.purge_old_backups(keep.unwrap());
+                if purged.is_err() {
+                    warn!("purging old backups of `{}` {}", name, purged.err().unwrap());
+                }
+            }
+            let latest = engine.get_backup_info().into_iter().map(|b| b.backup_id).max();
+            if latest.is_none() {
+                return Err(TrxError::BackupError(format!("no backup of `{}` after creating one", name)));
+            }
+            ids.insert(name.to_string(), latest.unwrap());
+            // summed after the purge, so only what the engine still keeps is listed
+            let summed = checksums(dir, dir.join(name).as_path(), &previous);
+            if summed.is_err() {
+                return Err(summed.err().unwrap());
+            }
+            files.extend(summed.unwrap());
+        }
+        Ok(BackupManifest {
+            _mode: BackupMode::Incremental.to_string(),
+            _created_at: now_secs(),
+            _key_format: KEY_FORMAT,
+            _files: files,
+            _backup_ids: ids,
+        })
+    }
+
+    pub fn backup_db(&self, cmd: &JqlCommand) -> DbRes {
+        let body = cmd._body.clone().unwrap_or_default();
+        let target = body.get(Types::Target.as_str()).and_then(|t| t.as_str()).unwrap_or_default();
+        let mode = BackupMode::from(body.get(Types::Mode.as_str()).and_then(|m| m.as_str()).unwrap_or_default());
+        let keep = body.get(Types::Keep.as_str()).and_then(|k| k.as_u64()).map(|k| k as usize);
+        if self.env.backup_path.is_none() {
+            return DbRes { data: None, error: Some(TrxError::BackupError("set `backup_path` in the config to take backups".to_string())) };
+        }
+        let dir = Path::new(self.env.backup_path.as_ref().unwrap()).join(target);
+        match self.backup(dir.as_path(), mode, keep) {
+            Ok(manifest) => {
+                let mut shown = serde_json::to_value(&manifest).unwrap();
+                shown.as_object_mut().unwrap().insert(Types::Target.as_str().to_string(), Value::from(dir.to_string_lossy().to_string()));
+                DbRes { data: Some(vec![shown]), error: None }
+            }
+            Err(err) => DbRes { data: None, error: Some(err) }
+        }
+    }
+}
+
+impl MoeDb {
+    pub fn backup<P: AsRef<Path>>(&self, dir: P, mode: BackupMode, keep: Option<usize>) -> Result<BackupManifest, MoeDbError> {
+        self.exec.backup(dir.as_ref(), mode, keep)
+            .map_err(|e| MoeDbError::BackupError(e.to_string()))
+    }
+
+    pub fn verify_backup<P: AsRef<Path>>(dir: P) -> Result<BackupManifest, MoeDbError> {
+        verify(dir.as_ref()).map_err(|e| MoeDbError::BackupError(e.to_string()))
+    }
+
+    ///
+    // run before `MoeDb::new`, the restored db is opened like any other afterwards
+    ///
+    pub fn restore<P: AsRef<Path>>(dir: P, cfg: &env::MoeDb) -> Result<BackupManifest, MoeDbError> {
+        restore(dir.as_ref(), cfg).map_err(|e| MoeDbError::BackupError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checkpoint_manifest() {
+        assert!(is_target_ok("nightly-2023.10.01"));
+        assert!(!is_target_ok("../etc"));
+        assert!(!is_target_ok("a/b"));
+        assert!(!is_target_ok(".hidden"));
+        assert!(!is_target_ok(""));
+        assert_eq!(BackupMode::from("incremental"), BackupMode::Incremental);
+        assert_eq!(BackupMode::from("anything"), BackupMode::Checkpoint);
+
+        let dir = std::env::temp_dir().join(format!("moedb-bak-{}", crate::func::unique_id()));
+        fs::create_dir_all(dir.join(BACKUP_MAIN)).unwrap();
+        fs::write(dir.join(BACKUP_MAIN).join("000001.sst"), b"sorted").unwrap();
+        fs::write(dir.join(BACKUP_MAIN).join("CURRENT"), b"MANIFEST-000001").unwrap();
+        assert!(verify(dir.as_path()).is_err(), "no manifest yet");
+
+        let mut paths = vec![];
+        files_under(dir.as_path(), dir.as_path(), &mut paths).unwrap();
+        let files = paths.into_iter().map(|p| {
+            let (size, sum) = file_checksum(dir.join(p.as_str()).as_path()).unwrap();
+            BackupFile { _path: p, _size: size, _checksum: sum }
+        }).collect::<Vec<BackupFile>>();
+        assert_eq!(files.len(), 2);
+        let manifest = BackupManifest {
+            _mode: BackupMode::Checkpoint.to_string(),
+            _created_at: now_secs(),
+            _key_format: KEY_FORMAT,
+            _files: files,
+            _backup_ids: BTreeMap::new(),
+        };
+        write_manifest(dir.as_path(), &manifest).unwrap();
+        assert_eq!(verify(dir.as_path()).unwrap()._files, manifest._files);
+
+        fs::write(dir.join(BACKUP_MAIN).join("000001.sst"), b"sortex").unwrap();
+        assert!(verify(dir.as_path()).is_err(), "same size, different bytes");
+
+        let mut newer = manifest.clone();
+        newer._key_format = KEY_FORMAT + 1;
+        write_manifest(dir.as_path(), &newer).unwrap();
+        assert!(verify(dir.as_path()).is_err(), "written by a newer build");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backup_restore_read() {
+        let root = std::env::temp_dir().join(format!("moedb-bak-{}", crate::func::unique_id()));
+        let source = env::at(root.join("source").to_str().unwrap());
+        let db = MoeDb::new(source).unwrap();
+        for stmt in [
+            r#"{ "_action":"create-db", "_database":"kept", "_body":{} }"#,
+            r#"{ "_action":"create-collection", "_database":"kept", "_body":{ "_name":"notes", "_key":"ids", "_fields":[ { "_name":"text", "_declare":"string" } ] } }"#,
+            r#"{ "_action":"upsert", "_database":"kept", "_collection":"notes", "_body":[ { "ids":"n1", "text":"first" } ] }"#,
+        ] {
+            let res = db.execute(stmt);
+            assert!(!res.error, "{}", res.message);
+        }
+        db.backup(root.join("full"), BackupMode::Checkpoint, None).unwrap();
+        db.backup(root.join("runs"), BackupMode::Incremental, None).unwrap();
+        let res = db.execute(r#"{ "_action":"upsert", "_database":"kept", "_collection":"notes", "_body":[ { "ids":"n2", "text":"second" } ] }"#);
+        assert!(!res.error, "{}", res.message);
+        let manifest = db.backup(root.join("runs"), BackupMode::Incremental, Some(2)).unwrap();
+        assert!(!manifest._files.is_empty(), "incremental runs are summed");
+        drop(db);
+
+        for (backup, left) in [("full", 1), ("runs", 2)] {
+            let target = env::at(root.join(format!("restored-{}", backup)).to_str().unwrap());
+            MoeDb::restore(root.join(backup), &target).unwrap();
+            assert!(MoeDb::restore(root.join(backup), &target).is_err(), "only into an empty path");
+            let restored = MoeDb::new(target).unwrap();
+            let res = restored.execute(r#"{ "_action":"get", "_database":"kept", "_collection":"notes", "_body":{} }"#);
+            assert!(!res.error, "{}", res.message);
+            assert_eq!(res.data.unwrap().len(), left, "documents restored from {}", backup);
+        }
+
+        let shared = manifest._files.iter().find(|f| f._path.ends_with(".sst")).unwrap();
+        let path = root.join("runs").join(shared._path.as_str());
+        let mut bytes = fs::read(path.as_path()).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(path.as_path(), bytes).unwrap();
+        assert!(verify(root.join("runs").as_path()).is_err(), "a flipped byte in an incremental backup");
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::path::Path;
+use std::process;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use serde_json::Value;
+use tokio::runtime::Runtime;
+use moedb::env;
+use moedb::hdrs::{MoeDb, Query, Response, ShellCommand, WireClient};
+use moedb::sh::{HELP, history_path, is_complete, parse_shell, render};
+
+const USAGE: &str = "usage: moedb [<db_dir> | --config <file> | --connect <host:port>]
+    without arguments the db of $MOEDB_CONFIG or ./moedb.toml is opened";
+
+enum Target {
+    Local(MoeDb),
+    Remote(Runtime, WireClient),
+}
+
+impl Target {
+    fn open(args: &[String]) -> Result<Self, String> {
+        match args {
+            [] => {
+                let cfg = env::env().map_err(|e| e.to_string())?;
+                MoeDb::new(cfg).map(Target::Local).map_err(|e| e.to_string())
+            }
+            [flag, file] if flag == "--config" => {
+                let cfg = env::from_path(file.as_str()).map_err(|e| e.to_string())?;
+                MoeDb::new(cfg).map(Target::Local).map_err(|e| e.to_string())
+            }
+            [flag, addr] if flag == "--connect" => {
+                let rt = Runtime::new().map_err(|e| e.to_string())?;
+                let client = rt.block_on(WireClient::connect(addr.as_str())).map_err(|e| e.to_string())?;
+                Ok(Target::Remote(rt, client))
+            }
+            [dir] if !dir.starts_with('-') => {
+                if !Path::new(dir).join("CURRENT").exists() {
+                    return Err(format!("no db in `{}`", dir));
+                }
+                MoeDb::new(env::at(dir.as_str()))
+                    .map(Target::Local)
+                    .map_err(|e| format!("{} (a running server holds the lock, use --connect)", e))
+            }
+            _ => Err(USAGE.to_string())
+        }
+    }
+
+    fn execute(&self, stmt: &str) -> Response {
+        match self {
+            Target::Local(db) => db.execute(stmt),
+            Target::Remote(rt, client) => match rt.block_on(client.execute(stmt)) {
+                Ok(res) => res,
+                Err(err) => Response { time_taken: "".to_string(), error: true, message: err.to_string(), data: None }
+            }
+        }
+    }
+
+    fn local(&self) -> Result<&MoeDb, String> {
+        match self {
+            Target::Local(db) => Ok(db),
+            Target::Remote(..) => Err("file commands need the db opened locally".to_string())
+        }
+    }
+}
+
+fn run_file_command(target: &Target, cmd: ShellCommand, db: &str) -> Result<String, String> {
+    let local = target.local()?;
+    let report = match cmd {
+        ShellCommand::ImportJsonl(file) => {
+            let input = File::open(file).map_err(|e| e.to_string())?;
+            serde_json::to_value(local.import_jsonl(input, None).map_err(|e| e.to_string())?)
+        }
+        ShellCommand::ExportJsonl(file, col) => {
+            let out = File::create(file.as_str()).map_err(|e| e.to_string())?;
+            let compress = file.ends_with(".gz");
+            serde_json::to_value(local.export_jsonl(out, db, col.as_deref(), &Value::Null, compress).map_err(|e| e.to_string())?)
+        }
+        ShellCommand::ImportCsv(col, file, opts) => {
+            let input = File::open(file).map_err(|e| e.to_string())?;
+            serde_json::to_value(local.import_csv(input, db, col.as_str(), &opts).map_err(|e| e.to_string())?)
+        }
+        ShellCommand::ExportCsv(col, file, columns, opts) => {
+            let out = File::create(file).map_err(|e| e.to_string())?;
+            let columns = columns.iter().map(|c| c.as_str()).collect::<Vec<&str>>();
+            let query = Query::collection(db, col.as_str()).get();
+            serde_json::to_value(local.export_csv(out, &query, &columns, &opts).map_err(|e| e.to_string())?)
+        }
+        _ => return Err(HELP.to_string())
+    };
+    Ok(serde_json::to_string_pretty(&report.unwrap()).unwrap())
+}
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    let target = Target::open(&args);
+    if target.is_err() {
+        eprintln!("{}", target.err().unwrap());
+        process::exit(1);
+    }
+    let target = target.unwrap();
+    let editor = DefaultEditor::new();
+    if editor.is_err() {
+        eprintln!("{}", editor.err().unwrap());
+        process::exit(1);
+    }
+    let mut editor = editor.unwrap();
+    let history = history_path();
+    if history.is_some() {
+        let _ = editor.load_history(history.as_ref().unwrap());
+    }
+
+    let mut current: Option<String> = None;
+    let mut buffer = String::new();
+    loop {
+        let prompt = match (buffer.is_empty(), current.as_ref()) {
+            (false, _) => "...> ".to_string(),
+            (true, Some(db)) => format!("moedb:{}> ", db),
+            (true, None) => "moedb> ".to_string()
+        };
+        let line = editor.readline(prompt.as_str());
+        let line = match line {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+        if buffer.is_empty() && line.trim().is_empty() {
+            continue;
+        }
+        buffer.push_str(line.as_str());
+        buffer.push('\n');
+        if !is_complete(buffer.as_str()) {
+            continue;
+        }
+        let entered = std::mem::take(&mut buffer);
+        let _ = editor.add_history_entry(entered.trim());
+
+        match parse_shell(entered.as_str(), current.as_deref()) {
+            ShellCommand::Exit => break,
+            ShellCommand::Help => println!("{}", HELP),
+            ShellCommand::Invalid(reason) => eprintln!("{}", reason),
+            ShellCommand::Use(db) => current = Some(db),
+            ShellCommand::Jql(stmt) => println!("{}", render(&target.execute(stmt.as_str()))),
+            cmd => match run_file_command(&target, cmd, current.as_deref().unwrap_or_default()) {
+                Ok(report) => println!("{}", report),
+                Err(err) => eprintln!("error: {}", err)
+            }
+        }
+    }
+    if history.is_some() {
+        let _ = editor.save_history(history.as_ref().unwrap());
+    }
+}
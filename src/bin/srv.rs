@@ -0,0 +1,18 @@
+use std::process;
+use moedb::hdrs::Server;
+use moedb::start_moedb;
+
+#[tokio::main]
+async fn main() {
+    let db = start_moedb();
+    if db.is_err() {
+        eprintln!("failed to start moedb {}", db.err().unwrap());
+        process::exit(1);
+    }
+    let server = Server::new(db.unwrap());
+    let served = tokio::try_join!(server.serve(), server.serve_wire());
+    if served.is_err() {
+        eprintln!("moedb server stopped {}", served.err().unwrap());
+        process::exit(1);
+    }
+}
@@ -0,0 +1,540 @@
+use serde::Serialize;
+use serde_json::{Map, Value};
+use crate::err::MoeDbError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crate::hdrs::{ActionType, CfStorage, DataTypes, DbQuery, JqlCommand, JqlSchema, JqlSchemaFields, KeyStrategy, ObjectQuery, Query, SchemaBuilder, SchemaKey, SchemaMigration, SchemaMode, SortOrder, Types};
+
+/// !```
+/// let cmd = Query::collection("shop", "orders")
+///     .filter("state", "open")
+///     .sort("placed", SortOrder::Desc)
+///     .limit(20)
+///     .get();
+/// let schema = JqlSchema::builder("orders", "ids")
+///     .field("state", DataTypes::String)
+///     .field("placed", DataTypes::DateTime)
+///     .build();
+/// let create = Query::database("shop").create_collection(&schema);
+/// !```
+
+impl JqlCommand {
+    fn new(action: ActionType, db: &str, col: Option<&str>, body: Option<Value>) -> Self {
+        Self {
+            _action: action.to_string(),
+            _body: body,
+            _database: Some(db.to_string()),
+            _collection: col.map(|c| c.to_string()),
+            _live: None,
+            _bucket: None,
+        }
+    }
+
+    pub fn to_jql(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+}
+
+impl DbQuery {
+    pub fn create(&self) -> JqlCommand {
+        JqlCommand::new(ActionType::CreateDb, self.database.as_str(), None, Some(Value::Object(Map::new())))
+    }
+
+    pub fn drop(&self) -> JqlCommand {
+        JqlCommand::new(ActionType::DropDb, self.database.as_str(), None, None)
+    }
+
+    pub fn collections(&self) -> JqlCommand {
+        JqlCommand::new(ActionType::ColList, self.database.as_str(), None, None)
+    }
+
+    pub fn create_collection(&self, schema: &JqlSchema) -> JqlCommand {
+        let body = serde_json::to_value(schema).unwrap();
+        JqlCommand::new(ActionType::Create, self.database.as_str(), None, Some(body))
+    }
+}
+
+impl Query {
+    pub fn databases() -> JqlCommand {
+        JqlCommand {
+            _action: ActionType::DbList.to_string(),
+            _body: None,
+            _database: None,
+            _collection: None,
+            _live: None,
+            _bucket: None,
+        }
+    }
+
+    pub fn database(db: &str) -> DbQuery {
+        DbQuery {
+            database: db.to_string()
+        }
+    }
+
+    pub fn bucket(db: &str, bucket: &str) -> ObjectQuery {
+        ObjectQuery {
+            database: db.to_string(),
+            bucket: bucket.to_string(),
+        }
+    }
+
+    pub fn collection(db: &str, col: &str) -> Self {
+        Self {
+            database: db.to_string(),
+            collection: col.to_string(),
+            id: None,
+            from: None,
+            to: None,
+            filter: Map::new(),
+            sort: vec![],
+            limit: None,
+            skip: None,
+            live: false,
+            inline: None,
+            project: vec![],
+        }
+    }
+
+    pub fn id<V: Into<Value>>(mut self, id: V) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    ///
+    // keys from `from` (inclusive) up to `to` (exclusive), for compound keys either can be a leading part of the key
+    ///
+    pub fn range<V: Into<Value>>(mut self, from: Option<V>, to: Option<V>) -> Self {
+        self.from = from.map(|f| f.into());
+        self.to = to.map(|t| t.into());
+        self
+    }
+
+    pub fn filter<V: Into<Value>>(mut self, field: &str, value: V) -> Self {
+        self.filter.insert(field.to_string(), value.into());
+        self
+    }
+
+    pub fn sort(mut self, field: &str, order: SortOrder) -> Self {
+        self.sort.push((field.to_string(), order));
+        self
+    }
+
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn skip(mut self, skip: u64) -> Self {
+        self.skip = Some(skip);
+        self
+    }
+
+    ///
+    // attachments up to `max_bytes` come back with their data
+    ///
+    pub fn inline(mut self, max_bytes: u64) -> Self {
+        self.inline = Some(max_bytes);
+        self
+    }
+
+    ///
+    // only these (dotted) paths of each document come back
+    ///
+    pub fn project(mut self, paths: &[&str]) -> Self {
+        self.project = paths.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    pub fn live(mut self) -> Self {
+        self.live = true;
+        self
+    }
+
+    pub fn get(&self) -> JqlCommand {
+        let mut body = self.selector();
+        if !self.sort.is_empty() {
+            let sorts = self.sort
+                .iter()
+                .map(|(field, order)| {
+                    let mut sort = Map::new();
+                    sort.insert(Types::Field.as_str().to_string(), Value::from(field.as_str()));
+                    sort.insert(Types::Order.as_str().to_string(), Value::from(order.as_str()));
+                    Value::Object(sort)
+                })
+                .collect::<Vec<Value>>();
+            body.insert(Types::Sort.as_str().to_string(), Value::Array(sorts));
+        }
+        if self.skip.is_some() {
+            body.insert(Types::Skip.as_str().to_string(), Value::from(self.skip.unwrap()));
+        }
+        if self.limit.is_some() {
+            body.insert(Types::Limit.as_str().to_string(), Value::from(self.limit.unwrap()));
+        }
+        if self.inline.is_some() {
+            body.insert(Types::Inline.as_str().to_string(), Value::from(self.inline.unwrap()));
+        }
+        if !self.project.is_empty() {
+            body.insert(Types::Project.as_str().to_string(), Value::from(self.project.clone()));
+        }
+        let mut cmd = self.command(ActionType::Get, Some(Value::Object(body)));
+        if self.live {
+            cmd._live = Some(true);
+        }
+        cmd
+    }
+
+    pub fn delete(&self) -> JqlCommand {
+        self.command(ActionType::Delete, Some(Value::Object(self.selector())))
+    }
+
+    pub fn upsert<T: Serialize>(&self, doc: &T) -> Result<JqlCommand, MoeDbError> {
+        let body = serde_json::to_value(doc);
+        if body.is_err() {
+            return Err(MoeDbError::DocumentError(body.err().unwrap().to_string()));
+        }
+        Ok(self.command(ActionType::Upsert, Some(body.unwrap())))
+    }
+
+    pub fn alter(&self, migration: &SchemaMigration) -> JqlCommand {
+        let body = serde_json::to_value(migration).unwrap();
+        self.command(ActionType::Alter, Some(body))
+    }
+
+    pub fn truncate(&self) -> JqlCommand {
+        self.command(ActionType::Truncate, None)
+    }
+
+    pub fn drop(&self) -> JqlCommand {
+        self.command(ActionType::Drop, None)
+    }
+
+    fn command(&self, action: ActionType, body: Option<Value>) -> JqlCommand {
+        JqlCommand::new(action, self.database.as_str(), Some(self.collection.as_str()), body)
+    }
+
+    fn selector(&self) -> Map<String, Value> {
+        let mut body = Map::new();
+        if self.id.is_some() {
+            body.insert(Types::Id.as_str().to_string(), self.id.clone().unwrap());
+        }
+        if self.from.is_some() {
+            body.insert(Types::From.as_str().to_string(), self.from.clone().unwrap());
+        }
+        if self.to.is_some() {
+            body.insert(Types::To.as_str().to_string(), self.to.clone().unwrap());
+        }
+        if !self.filter.is_empty() {
+            body.insert(Types::Filter.as_str().to_string(), Value::Object(self.filter.clone()));
+        }
+        body
+    }
+}
+
+impl ObjectQuery {
+    pub fn put(&self, key: &str, data: &[u8], content_type: Option<&str>, metadata: Map<String, Value>) -> JqlCommand {
+        let mut body = self.keyed(key);
+        body.insert(Types::Data.as_str().to_string(), Value::from(STANDARD.encode(data)));
+        if content_type.is_some() {
+            body.insert(Types::ContentType.as_str().to_string(), Value::from(content_type.unwrap()));
+        }
+        if !metadata.is_empty() {
+            body.insert(Types::Metadata.as_str().to_string(), Value::Object(metadata));
+        }
+        self.command(ActionType::PutObject, body)
+    }
+
+    pub fn get(&self, key: &str) -> JqlCommand {
+        self.command(ActionType::GetObject, self.keyed(key))
+    }
+
+    ///
+    // bytes `start..end` of the object, an open end reads to the last byte
+    ///
+    pub fn range(&self, key: &str, start: u64, end: Option<u64>) -> JqlCommand {
+        let mut body = self.keyed(key);
+        let mut range = vec![Value::from(start)];
+        if end.is_some() {
+            range.push(Value::from(end.unwrap()));
+        }
+        body.insert(Types::Range.as_str().to_string(), Value::Array(range));
+        self.command(ActionType::GetObject, body)
+    }
+
+    pub fn delete(&self, key: &str) -> JqlCommand {
+        self.command(ActionType::DeleteObject, self.keyed(key))
+    }
+
+    pub fn begin_upload(&self, key: &str, content_type: Option<&str>) -> JqlCommand {
+        let mut body = self.keyed(key);
+        if content_type.is_some() {
+            body.insert(Types::ContentType.as_str().to_string(), Value::from(content_type.unwrap()));
+        }
+        self.command(ActionType::BeginUpload, body)
+    }
+
+    pub fn put_part(&self, upload_id: &str, part: u32, data: &[u8]) -> JqlCommand {
+        let mut body = self.upload(upload_id);
+        body.insert(Types::Part.as_str().to_string(), Value::from(part));
+        body.insert(Types::Data.as_str().to_string(), Value::from(STANDARD.encode(data)));
+        self.command(ActionType::PutPart, body)
+    }
+
+    pub fn parts(&self, upload_id: &str) -> JqlCommand {
+        self.command(ActionType::ListParts, self.upload(upload_id))
+    }
+
+    pub fn complete_upload(&self, upload_id: &str) -> JqlCommand {
+        self.command(ActionType::CompleteUpload, self.upload(upload_id))
+    }
+
+    pub fn abort_upload(&self, upload_id: &str) -> JqlCommand {
+        self.command(ActionType::AbortUpload, self.upload(upload_id))
+    }
+
+    pub fn list(&self, prefix: &str, limit: Option<u64>) -> JqlCommand {
+        let mut body = Map::new();
+        body.insert(Types::Prefix.as_str().to_string(), Value::from(prefix));
+        if limit.is_some() {
+            body.insert(Types::Limit.as_str().to_string(), Value::from(limit.unwrap()));
+        }
+        self.command(ActionType::ListObjects, body)
+    }
+
+    fn upload(&self, upload_id: &str) -> Map<String, Value> {
+        let mut body = Map::new();
+        body.insert(Types::UploadId.as_str().to_string(), Value::from(upload_id));
+        body
+    }
+
+    fn keyed(&self, key: &str) -> Map<String, Value> {
+        let mut body = Map::new();
+        body.insert(Types::Key.as_str().to_string(), Value::from(key));
+        body
+    }
+
+    fn command(&self, action: ActionType, body: Map<String, Value>) -> JqlCommand {
+        let mut cmd = JqlCommand::new(action, self.database.as_str(), None, Some(Value::Object(body)));
+        cmd._bucket = Some(self.bucket.clone());
+        cmd
+    }
+}
+
+impl JqlSchemaFields {
+    pub fn new(name: &str, declare: DataTypes) -> Self {
+        Self {
+            _name: name.to_string(),
+            _declare: declare.to_string(),
+            _optional: None,
+            _default: None,
+            _min: None,
+            _max: None,
+            _min_len: None,
+            _max_len: None,
+            _pattern: None,
+            _enum: None,
+            _fields: None,
+        }
+    }
+
+    pub fn optional(mut self) -> Self {
+        self._optional = Some(true);
+        self
+    }
+
+    pub fn default<V: Into<Value>>(mut self, value: V) -> Self {
+        self._default = Some(value.into());
+        self
+    }
+
+    pub fn min(mut self, min: f64) -> Self {
+        self._min = Some(min);
+        self
+    }
+
+    pub fn max(mut self, max: f64) -> Self {
+        self._max = Some(max);
+        self
+    }
+
+    pub fn min_len(mut self, min_len: u64) -> Self {
+        self._min_len = Some(min_len);
+        self
+    }
+
+    pub fn max_len(mut self, max_len: u64) -> Self {
+        self._max_len = Some(max_len);
+        self
+    }
+
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self._pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn nested(mut self, fields: Vec<JqlSchemaFields>) -> Self {
+        self._fields = Some(fields);
+        self
+    }
+
+    pub fn one_of<V: Into<Value>>(mut self, values: Vec<V>) -> Self {
+        self._enum = Some(values.into_iter().map(|v| v.into()).collect());
+        self
+    }
+}
+
+impl JqlSchema {
+    pub fn builder(name: &str, key: &str) -> SchemaBuilder {
+        SchemaBuilder {
+            schema: JqlSchema {
+                _name: name.to_string(),
+                _key: SchemaKey::Field(key.to_string()),
+                _fields: vec![],
+                _in_memory: None,
+                _version: None,
+                _migrations: vec![],
+                _mode: None,
+                _key_strategy: None,
+                _ttl: None,
+                _expire_field: None,
+                _storage: None,
+            }
+        }
+    }
+}
+
+impl SchemaBuilder {
+    pub fn field(mut self, name: &str, declare: DataTypes) -> Self {
+        self.schema._fields.push(JqlSchemaFields::new(name, declare));
+        self
+    }
+
+    pub fn optional(mut self, name: &str, declare: DataTypes) -> Self {
+        self.schema._fields.push(JqlSchemaFields::new(name, declare).optional());
+        self
+    }
+
+    ///
+    // for fields that need more than a declaration, e.g. `JqlSchemaFields::new("age", DataTypes::Int).min(0.0)`
+    ///
+    pub fn with(mut self, field: JqlSchemaFields) -> Self {
+        self.schema._fields.push(field);
+        self
+    }
+
+    pub fn mode(mut self, mode: SchemaMode) -> Self {
+        self.schema._mode = Some(mode.to_string());
+        self
+    }
+
+    ///
+    // replaces the key given to `builder` with a compound one, fields in key order
+    ///
+    pub fn compound_key(mut self, fields: &[&str]) -> Self {
+        self.schema._key = SchemaKey::Fields(fields.iter().map(|f| f.to_string()).collect());
+        self
+    }
+
+    pub fn key_strategy(mut self, strategy: KeyStrategy) -> Self {
+        self.schema._key_strategy = Some(strategy.to_string());
+        self
+    }
+
+    pub fn ttl(mut self, secs: u64) -> Self {
+        self.schema._ttl = Some(secs);
+        self
+    }
+
+    pub fn expire_field(mut self, name: &str) -> Self {
+        self.schema._expire_field = Some(name.to_string());
+        self
+    }
+
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.schema._in_memory = Some(in_memory);
+        self
+    }
+
+    ///
+    // column family options of this collection over `[storage]`, e.g. zstd for a large archive
+    ///
+    pub fn storage(mut self, storage: CfStorage) -> Self {
+        self.schema._storage = Some(storage);
+        self
+    }
+
+    pub fn build(self) -> JqlSchema {
+        self.schema
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::hdrs::Jql;
+    use crate::jqls::JqlSchemaParser;
+    use super::*;
+
+    #[test]
+    fn query_builder() {
+        let cmd = Query::collection("moss", "ops")
+            .filter("state", "open")
+            .sort("age", SortOrder::Desc)
+            .skip(5)
+            .limit(10)
+            .project(&["state", "owner.name"])
+            .get();
+        assert_eq!(cmd._body.clone().unwrap(), json!({
+            "_filter": { "state": "open" },
+            "_sort": [{ "_field": "age", "_order": "desc" }],
+            "_skip": 5,
+            "_limit": 10,
+            "_project": ["state", "owner.name"]
+        }));
+        assert!(Jql::parse(cmd.to_jql().as_str()).is_ok());
+
+        let delete = Query::collection("moss", "ops").id("a1").delete();
+        assert!(Jql::parse(delete.to_jql().as_str()).is_ok());
+
+        let upsert = Query::collection("moss", "ops").upsert(&json!({ "ids": "a1" })).unwrap();
+        assert!(Jql::parse(upsert.to_jql().as_str()).is_ok());
+
+        assert!(Jql::parse(Query::database("moss").create().to_jql().as_str()).is_ok());
+        assert!(Jql::parse(Query::databases().to_jql().as_str()).is_ok());
+    }
+
+    #[test]
+    fn object_builder() {
+        let images = Query::bucket("moss", "images");
+        let put = images.put("a/b.png", b"png", Some("image/png"), Map::new());
+        assert_eq!(put._bucket.as_deref(), Some("images"));
+        assert_eq!(put._body.clone().unwrap(), json!({ "_key": "a/b.png", "_data": "cG5n", "_content_type": "image/png" }));
+        assert!(Jql::parse(put.to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.get("a/b.png").to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.delete("a/b.png").to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.list("a/", Some(10)).to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.get("").to_jql().as_str()).is_err());
+        assert_eq!(images.range("a/b.png", 10, None)._body.unwrap()["_range"], json!([10]));
+        assert!(Jql::parse(images.range("a/b.png", 10, Some(20)).to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.begin_upload("a/b.png", None).to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.put_part("u1", 1, b"png").to_jql().as_str()).is_ok());
+        assert!(Jql::parse(images.put_part("u1", 0, b"png").to_jql().as_str()).is_err());
+        assert!(Jql::parse(images.complete_upload("u1").to_jql().as_str()).is_ok());
+        assert!(Jql::parse(Query::bucket("moss", "#").list("", None).to_jql().as_str()).is_err());
+    }
+
+    #[test]
+    fn schema_builder() {
+        let schema = JqlSchema::builder("person", "ids")
+            .field("hello", DataTypes::ArrayOfString)
+            .field("born", DataTypes::Date)
+            .in_memory(false)
+            .build();
+        let jql = Jql::new_schema_parser();
+        let res = jql.parse_schema(serde_json::to_string(&schema).unwrap());
+        assert!(res.is_ok(), "{}", res.err().unwrap());
+
+        let cmd = Query::database("moss").create_collection(&schema);
+        assert!(Jql::parse(cmd.to_jql().as_str()).is_ok());
+    }
+}
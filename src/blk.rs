@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::Path;
+use log::{info, warn};
+use rocksdb::{IngestExternalFileOptions, SstFileWriter};
+use serde_json::Value;
+use crate::err::{MoeDbError, TrxError};
+use crate::func::unique_id;
+use crate::hdrs::{Exec, ImportReport, Jql, JqlSchema, MoeDb, TKey, TValue};
+use crate::jqlv::JqlValueParser;
+use crate::ttl::now_secs;
+use crate::util::{cfg_cf, key_merger};
+use crate::var::BULK_RUN_BYTES;
+
+/// !```
+/// let report = db.bulk_load("shop", "users", docs)?;
+/// !```
+/// documents are checked like an `upsert`, then gathered into runs of `BULK_RUN_BYTES`. Each run is
+/// sorted by storage key and written to an SST file in `<db_path>.bulk-<id>`, beside the db on the same
+/// file system and removed once the load is done. Once every run is written they are ingested into
+/// the collection's column family in one go, so nothing goes through the memtable or the WAL and a load
+/// that fails leaves the collection as it was. Later documents win over earlier ones with
+/// the same key, within a load and over what was stored before. Bulk loads skip the change
+/// feed, live queries only see them on their next read. Attachments are claimed like on `upsert`, but
+/// those of documents a load replaces are not removed
+
+///
+// sorted by key, of several documents with the same key the one that came last stays
+///
+pub fn sort_run(run: &mut Vec<(TKey, TValue)>) {
+    run.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut kept: Vec<(TKey, TValue)> = Vec::with_capacity(run.len());
+    for entry in run.drain(..) {
+        if kept.last().map_or(false, |last| last.0 == entry.0) {
+            kept.pop();
+        }
+        kept.push(entry);
+    }
+    *run = kept;
+}
+
+impl Exec {
+    pub fn bulk_load<I: IntoIterator<Item = Value>>(&self, db: &str, col: &str, docs: I) -> Result<ImportReport, TrxError> {
+        self.bulk_load_runs(db, col, docs, BULK_RUN_BYTES)
+    }
+
+    fn bulk_load_runs<I: IntoIterator<Item = Value>>(&self, db: &str, col: &str, docs: I, run_limit: usize) -> Result<ImportReport, TrxError> {
+        let schema = self.schema(db, col);
+        if schema.is_none() {
+            return Err(TrxError::ImportError(format!("collection does not exist `{}`", col)));
+        }
+        let schema = schema.unwrap();
+        let name = key_merger(vec![db.to_string(), col.to_string()]).unwrap();
+        let cf_created = self.open_col_cf(name.as_str(), &schema);
+        if cf_created.is_err() {
+            return Err(TrxError::ImportError(cf_created.err().unwrap().to_string()));
+        }
+        // on the db's own file system so ingesting moves the files instead of copying them, but outside of
+        // the directory rocksdb owns
+        let db_path = Path::new(self.env.db_path.as_str());
+        let dir_name = db_path.file_name().map_or("moedb".to_string(), |n| n.to_string_lossy().to_string());
+        let dir = db_path.with_file_name(format!("{}.bulk-{}", dir_name, unique_id()));
+        let created = fs::create_dir_all(dir.as_path());
+        if created.is_err() {
+            return Err(TrxError::ImportError(created.err().unwrap().to_string()));
+        }
+        let loaded = self.load_staged(&schema, db, col, name.as_str(), dir.as_path(), docs, run_limit);
+        // whatever happened, the files are either in the collection or not needed anymore
+        let removed = fs::remove_dir_all(dir.as_path());
+        if removed.is_err() {
+            warn!("removing {} {}", dir.display(), removed.err().unwrap());
+        }
+        loaded
+    }
+
+    fn load_staged<I: IntoIterator<Item = Value>>(&self, schema: &JqlSchema, db: &str, col: &str, name: &str, dir: &Path, docs: I, run_limit: usize) -> Result<ImportReport, TrxError> {
+        let parser = Jql::new_value_parser(serde_json::to_string(schema).unwrap());
+        let mut report = ImportReport::default();
+        report._collections.push(format!("{}.{}", db, col));
+        let mut run = vec![];
+        let mut run_bytes = 0usize;
+        let mut staged = vec![];
+        let mut count = 0u64;
+        let now = now_secs();
+        // documents with attachments claim them before they are ingested
+        let attaches = !schema.blob_fields().is_empty();
+        let mut attached = vec![];
+        for (idx, doc) in docs.into_iter().enumerate() {
+            let prepared = self.prepare_doc(schema, &parser, db, col, doc);
+            if prepared.is_err() {
+                report.reject(idx as u64 + 1, prepared.err().unwrap().to_string());
+                continue;
+            }
+            let (key, doc) = prepared.unwrap();
+            if attaches && !schema.attachments(&doc).is_empty() {
+                let keyed = (key.clone(), doc.clone());
+                let checked = self.check_attachments(db, col, schema, std::slice::from_ref(&keyed));
+                if checked.is_err() {
+                    report.reject(idx as u64 + 1, checked.err().unwrap().to_string());
+                    continue;
+                }
+                attached.push(keyed);
+            }
+            let value = serde_json::to_vec(&schema.stamp(&schema.stamp_expiry(&doc, now))).unwrap();
+            run_bytes += key.len() + value.len();
+            run.push((key, value));
+            if run_bytes >= run_limit {
+                let path = dir.join(format!("{}.sst", staged.len()));
+                let written = self.write_run(name, path.as_path(), &mut run);
+                if written.is_err() {
+                    return Err(written.err().unwrap());
+                }
+                count += written.unwrap();
+                staged.push(path);
+                run_bytes = 0;
+            }
+        }
+        if !run.is_empty() {
+            let path = dir.join(format!("{}.sst", staged.len()));
+            let written = self.write_run(name, path.as_path(), &mut run);
+            if written.is_err() {
+                return Err(written.err().unwrap());
+            }
+            count += written.unwrap();
+            staged.push(path);
+        }
+        // upserts, deletes and the sweeper wait for the ingestion like for any other writer
+        let lock = self.col_lock(name);
+        let _guard = lock.lock().unwrap();
+        let claimed = self.claim_attachments(db, col, schema, &attached);
+        if claimed.is_err() {
+            return Err(TrxError::ImportError(claimed.err().unwrap().to_string()));
+        }
+        let claimed = claimed.unwrap();
+        if !staged.is_empty() {
+            // one ingestion, the load lands whole or not at all. Runs overlap, a later file wins over an earlier one
+            let mut ingest = IngestExternalFileOptions::default();
+            ingest.set_move_files(true);
+            let files = staged.len();
+            let ingested = self.trx.db.ingest_external_file_cf_opts(&self.trx.cf(name), &ingest, staged);
+            if ingested.is_err() {
+                self.release_attachments(db, claimed);
+                return Err(TrxError::ImportError(ingested.err().unwrap().to_string()));
+            }
+            info!("bulk loaded {} documents into `{}` from {} files, {} rejected", count, name, files, report._rejected);
+        }
+        report._imported = count;
+        Ok(report)
+    }
+
+    ///
+    // writes one sorted run to an SST file, answers how many documents it held
+    ///
+    fn write_run(&self, name: &str, path: &Path, run: &mut Vec<(TKey, TValue)>) -> Result<u64, TrxError> {
+        sort_run(run);
+        let opts = cfg_cf(name, self.env.log_path.as_str(), &self.trx.tuning);
+        let mut writer = SstFileWriter::create(&opts);
+        let opened = writer.open(path);
+        if opened.is_err() {
+            return Err(TrxError::ImportError(opened.err().unwrap().to_string()));
+        }
+        for (key, value) in run.iter() {
+            let put = writer.put(key, value);
+            if put.is_err() {
+                return Err(TrxError::ImportError(put.err().unwrap().to_string()));
+            }
+        }
+        let finished = writer.finish();
+        if finished.is_err() {
+            return Err(TrxError::ImportError(finished.err().unwrap().to_string()));
+        }
+        let count = run.len() as u64;
+        run.clear();
+        Ok(count)
+    }
+}
+
+impl MoeDb {
+    pub fn bulk_load<I: IntoIterator<Item = Value>>(&self, db: &str, col: &str, docs: I) -> Result<ImportReport, MoeDbError> {
+        self.exec.bulk_load(db, col, docs)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_runs() {
+        let mut run = vec![
+            (b"c".to_vec(), b"1".to_vec()),
+            (b"a".to_vec(), b"2".to_vec()),
+            (b"c".to_vec(), b"3".to_vec()),
+            (b"b".to_vec(), b"4".to_vec()),
+            (b"a".to_vec(), b"5".to_vec()),
+        ];
+        sort_run(&mut run);
+        assert_eq!(run, vec![
+            (b"a".to_vec(), b"5".to_vec()),
+            (b"b".to_vec(), b"4".to_vec()),
+            (b"c".to_vec(), b"3".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn later_runs_win() {
+        let root = std::env::temp_dir().join(format!("moedb-blk-{}", unique_id()));
+        let db = MoeDb::new(crate::env::at(root.join("db").to_str().unwrap())).unwrap();
+        for stmt in [
+            r#"{ "_action":"create-db", "_database":"shop", "_body":{} }"#,
+            r#"{ "_action":"create-collection", "_database":"shop", "_body":{ "_name":"items", "_key":"ids", "_fields":[ { "_name":"name", "_declare":"string" } ] } }"#,
+            r#"{ "_action":"upsert", "_database":"shop", "_collection":"items", "_body":[ { "ids":"i1", "name":"stored" } ] }"#,
+        ] {
+            let res = db.execute(stmt);
+            assert!(!res.error, "{}", res.message);
+        }
+        // every document is a run of its own, `i1` is in the first and the last of three files
+        let docs = vec![
+            serde_json::json!({ "ids": "i1", "name": "first" }),
+            serde_json::json!({ "ids": "i2", "name": "second" }),
+            serde_json::json!({ "ids": "i1", "name": "third" }),
+        ];
+        let report = db.exec.bulk_load_runs("shop", "items", docs, 1).unwrap();
+        assert_eq!(report._imported, 3);
+        let res = db.execute(r#"{ "_action":"get", "_database":"shop", "_collection":"items", "_body":{} }"#);
+        assert!(!res.error, "{}", res.message);
+        let names = res.data.unwrap().iter().map(|d| (d["ids"].to_string(), d["name"].to_string())).collect::<Vec<(String, String)>>();
+        assert_eq!(names, vec![
+            (r#""i1""#.to_string(), r#""third""#.to_string()),
+            (r#""i2""#.to_string(), r#""second""#.to_string()),
+        ]);
+        let left = fs::read_dir(root.as_path()).unwrap().count();
+        assert_eq!(left, 1, "the staging directory is gone");
+        drop(db);
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -14,6 +14,7 @@ impl From<String> for CommandType {
             "_body" => CommandType::Body,
             "_database" => CommandType::Database,
             "_collection" => CommandType::Store,
+            "_live" => CommandType::Live,
             _ => CommandType::Action
         }
     }
@@ -25,7 +26,8 @@ impl CommandType {
             CommandType::Action => "_action",
             CommandType::Body => "_body",
             CommandType::Database => "_database",
-            CommandType::Store => "_collection"
+            CommandType::Store => "_collection",
+            CommandType::Live => "_live"
         }
     }
 }
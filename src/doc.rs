@@ -0,0 +1,735 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use serde_json::{Map, Value};
+use log::{trace};
+use crate::err::TrxError;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use crate::hdrs::{Change, ChangeKind, DataTypes, DbRes, Exec, Jql, JqlCommand, JqlSchema, JqlSchemaFields, KeyScope, SortOrder, TKey, Types};
+use crate::att::inline_limit;
+use crate::jqlv::JqlValueParser;
+use crate::ttl::{is_expired, now_secs, without_expiry};
+use crate::var::DB_SYS;
+
+impl JqlSchema {
+    ///
+    // key parts of `_id`, `_from` or `_to`, `None` when the body doesn't have it. An `_id` that holds no part,
+    // `null` or `[]`, comes back empty so it selects nothing instead of falling through to every document
+    ///
+    pub fn key_selector(&self, body: &Value, t: Types) -> Result<Option<Vec<Value>>, String> {
+        let is_id = matches!(t, Types::Id);
+        match body.get(t.as_str()) {
+            None => Ok(None),
+            Some(Value::Null) if is_id => Ok(Some(vec![])),
+            Some(Value::Null) => Ok(None),
+            Some(Value::Array(parts)) if self._key.is_compound() => Ok(Some(parts.clone())),
+            Some(Value::String(shown)) if self._key.is_compound() && shown.starts_with('[') => {
+                serde_json::from_str::<Vec<Value>>(shown)
+                    .map(Some)
+                    .map_err(|e| format!("`{}` is not a key of `{}`, {}", t.as_str(), self._key, e))
+            }
+            Some(part) => Ok(Some(vec![part.clone()]))
+        }
+    }
+
+    ///
+    // what `_id` or `_from`/`_to` narrow the collection to. For compound keys any of them can be a leading
+    // part of the key (or the key as `upsert` returned it) and becomes a prefix
+    ///
+    pub fn key_scope(&self, body: &Value) -> Result<KeyScope, String> {
+        let id = self.key_selector(body, Types::Id)?;
+        if id.is_some() {
+            let id = id.unwrap();
+            let key = self.encode_key(&id);
+            if id.is_empty() || key.is_none() {
+                return Ok(KeyScope::Nothing);
+            }
+            return match id.len() < self._key.fields().len() {
+                true => Ok(KeyScope::Prefix(key.unwrap())),
+                false => Ok(KeyScope::Key(key.unwrap()))
+            };
+        }
+        let from = self.key_selector(body, Types::From)?.and_then(|p| self.encode_key(&p));
+        let to = self.key_selector(body, Types::To)?.and_then(|p| self.encode_key(&p));
+        if from.is_some() || to.is_some() {
+            return Ok(KeyScope::Range(from, to));
+        }
+        Ok(KeyScope::All)
+    }
+
+    ///
+    // declaration of a (dotted) field, array indexes in the path are skipped
+    ///
+    pub fn declare_of(&self, path: &str) -> Option<DataTypes> {
+        let mut fields = &self._fields;
+        let mut found = None;
+        for part in path.split('.').filter(|p| p.parse::<usize>().is_err()) {
+            let field = fields.iter().find(|f| f._name.eq(part));
+            if field.is_none() {
+                return None;
+            }
+            let field = field.unwrap();
+            found = Some(DataTypes::from(field._declare.clone()));
+            if field._fields.is_some() {
+                fields = field._fields.as_ref().unwrap();
+            }
+        }
+        found
+    }
+
+    pub fn key_of(&self, doc: &Value) -> Option<String> {
+        let parts = self.key_parts(doc);
+        if parts.is_none() {
+            return None;
+        }
+        let mut parts = parts.unwrap();
+        match self._key.is_compound() {
+            true => Some(Value::Array(parts).to_string()),
+            false => key_to_string(&parts.remove(0))
+        }
+    }
+
+    ///
+    // missing fields that declare a `_default` get it, anything the document already has is kept
+    ///
+    pub fn with_defaults(&self, doc: Value) -> Value {
+        fill_defaults(&self._fields, doc)
+    }
+}
+
+impl KeyScope {
+    ///
+    // `_to` is exclusive, like the scan
+    ///
+    pub fn contains(&self, key: &[u8]) -> bool {
+        match self {
+            KeyScope::All => true,
+            KeyScope::Nothing => false,
+            KeyScope::Key(k) => k.as_slice().eq(key),
+            KeyScope::Prefix(p) => key.starts_with(p.as_slice()),
+            KeyScope::Range(from, to) => {
+                from.as_ref().map_or(true, |f| key >= f.as_slice()) && to.as_ref().map_or(true, |t| key < t.as_slice())
+            }
+        }
+    }
+}
+
+fn fill_defaults(declared: &Vec<JqlSchemaFields>, doc: Value) -> Value {
+    let mut fields = match doc {
+        Value::Object(fields) => fields,
+        other => return other
+    };
+    for field in declared.iter() {
+        if !fields.contains_key(field._name.as_str()) && field._default.is_some() {
+            fields.insert(field._name.clone(), field._default.clone().unwrap());
+        }
+        if field._fields.is_some() && fields.contains_key(field._name.as_str()) {
+            let nested = field._fields.as_ref().unwrap();
+            let value = fields.remove(field._name.as_str()).unwrap();
+            let filled = match value {
+                Value::Array(items) => Value::Array(items.into_iter().map(|i| fill_defaults(nested, i)).collect()),
+                other => fill_defaults(nested, other)
+            };
+            fields.insert(field._name.clone(), filled);
+        }
+    }
+    Value::Object(fields)
+}
+
+pub fn key_to_string(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None
+    }
+}
+
+///
+/// `address.city` walks into objects, `items.0.sku` into arrays
+///
+pub fn get_path<'a>(doc: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = doc;
+    for part in path.split('.') {
+        let next = match current {
+            Value::Object(fields) => fields.get(part),
+            Value::Array(items) => part.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => None
+        };
+        if next.is_none() {
+            return None;
+        }
+        current = next.unwrap();
+    }
+    Some(current)
+}
+
+///
+/// `address.city` creates the objects on the way, whatever isn't an object there is replaced
+///
+pub fn set_path(doc: &mut Map<String, Value>, path: &str, value: Value) {
+    match path.split_once('.') {
+        None => {
+            doc.insert(path.to_string(), value);
+        }
+        Some((head, rest)) => {
+            let inner = doc.entry(head.to_string()).or_insert_with(|| Value::Object(Map::new()));
+            if !inner.is_object() {
+                *inner = Value::Object(Map::new());
+            }
+            set_path(inner.as_object_mut().unwrap(), rest, value);
+        }
+    }
+}
+
+///
+/// only the (dotted) paths asked for, at the place they have in the document. Paths the document
+/// doesn't have are left out
+///
+pub fn project(doc: &Value, paths: &[&str]) -> Value {
+    let mut projected = Map::new();
+    for path in paths.iter() {
+        let value = get_path(doc, path);
+        if value.is_some() {
+            set_path(&mut projected, path, value.unwrap().clone());
+        }
+    }
+    Value::Object(projected)
+}
+
+///
+/// every field (or dotted path) of the filter has to be equal to the same field of the document
+///
+pub fn is_match(filter: &Value, doc: &Value) -> bool {
+    match filter.as_object() {
+        None => true,
+        Some(fields) => fields
+            .iter()
+            .all(|(k, v)| get_path(doc, k.as_str()).map_or(false, |d| d.eq(v)))
+    }
+}
+
+fn sort_docs(schema: &JqlSchema, sorts: &Vec<Value>, a: &Value, b: &Value) -> Ordering {
+    for sort in sorts {
+        let field = sort.get(Types::Field.as_str()).and_then(|f| f.as_str()).unwrap_or_default();
+        let order = SortOrder::from(sort.get(Types::Order.as_str()).and_then(|o| o.as_str()).unwrap_or_default());
+        let mut ord = cmp_declared(
+            schema.declare_of(field),
+            get_path(a, field).unwrap_or(&Value::Null),
+            get_path(b, field).unwrap_or(&Value::Null)
+        );
+        if order == SortOrder::Desc {
+            ord = ord.reverse();
+        }
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+///
+/// types whose JSON form doesn't sort like the value it carries, everything else goes to `cmp_values`
+///
+pub fn cmp_declared(declare: Option<DataTypes>, a: &Value, b: &Value) -> Ordering {
+    match (declare, a, b) {
+        (Some(DataTypes::Decimal), Value::String(x), Value::String(y)) => cmp_decimal(x, y),
+        (Some(DataTypes::Uuid), Value::String(x), Value::String(y)) => x.to_lowercase().cmp(&y.to_lowercase()),
+        (Some(DataTypes::Bytes), Value::String(x), Value::String(y)) => {
+            STANDARD.decode(x).unwrap_or_default().cmp(&STANDARD.decode(y).unwrap_or_default())
+        }
+        (Some(DataTypes::GeoPoint), Value::Object(_), Value::Object(_)) => {
+            let lat = cmp_values(a.get("lat").unwrap_or(&Value::Null), b.get("lat").unwrap_or(&Value::Null));
+            lat.then_with(|| cmp_values(a.get("lon").unwrap_or(&Value::Null), b.get("lon").unwrap_or(&Value::Null)))
+        }
+        _ => cmp_values(a, b)
+    }
+}
+
+///
+/// `-?\d+(\.\d+)?` compared digit by digit, so `"10.5" > "9.75"` and `"1.50" == "1.5"`
+///
+pub fn cmp_decimal(a: &str, b: &str) -> Ordering {
+    fn split(v: &str) -> (bool, &str, &str) {
+        let (neg, v) = match v.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, v)
+        };
+        let (int, frac) = v.split_once('.').unwrap_or((v, ""));
+        let int = int.trim_start_matches('0');
+        let frac = frac.trim_end_matches('0');
+        // -0 and -0.00 are just 0
+        (neg && !(int.is_empty() && frac.is_empty()), int, frac)
+    }
+    let (a_neg, a_int, a_frac) = split(a);
+    let (b_neg, b_int, b_frac) = split(b);
+    if a_neg != b_neg {
+        return if a_neg { Ordering::Less } else { Ordering::Greater };
+    }
+    let magnitude = a_int.len()
+        .cmp(&b_int.len())
+        .then_with(|| a_int.cmp(b_int))
+        .then_with(|| a_frac.cmp(b_frac));
+    if a_neg { magnitude.reverse() } else { magnitude }
+}
+
+///
+/// null < boolean < number < string < array < object, values of the same kind compare naturally
+///
+pub fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+            Value::Array(_) => 4,
+            Value::Object(_) => 5
+        }
+    }
+    match (a, b) {
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.as_f64().unwrap_or(f64::NAN), y.as_f64().unwrap_or(f64::NAN));
+            x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+        }
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => {
+            for (i, j) in x.iter().zip(y.iter()) {
+                let ord = cmp_values(i, j);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            x.len().cmp(&y.len())
+        }
+        _ => rank(a).cmp(&rank(b))
+    }
+}
+
+impl Exec {
+    pub fn schema(&self, db: &str, col: &str) -> Option<JqlSchema> {
+        let exi = self.trx.get(DB_SYS, Self::col_key(db, col));
+        if exi.is_none() {
+            return None;
+        }
+        serde_json::from_value::<JqlSchema>(exi.unwrap()).ok()
+    }
+
+    pub fn get_doc(&self, cmd: &JqlCommand) -> DbRes {
+        let db = cmd._database.as_ref().unwrap();
+        let col = cmd._collection.as_ref().unwrap();
+        let wrp_schema = self.schema(db, col);
+        if wrp_schema.is_none() {
+            return DbRes { data: None, error: Some(TrxError::GetDocumentError(format!("collection does not exist `{}`", col))) };
+        }
+        let schema = wrp_schema.unwrap();
+        let name = Self::db_col_merged(cmd).unwrap();
+        if !self.trx.has_cf(name.as_str()) {
+            return DbRes { data: Some(vec![]), error: None };
+        }
+
+        let body = cmd._body.clone().unwrap_or(Value::Null);
+        let filter = body.get(Types::Filter.as_str()).cloned().unwrap_or(Value::Null);
+        let found = self.select(name.as_str(), &schema, &body);
+        if found.is_err() {
+            return DbRes { data: None, error: Some(TrxError::GetDocumentError(found.err().unwrap())) };
+        }
+        let mut items = found
+            .unwrap()
+            .into_iter()
+            .map(|(_, doc)| schema.upgrade(doc))
+            .filter(|doc| is_match(&filter, doc))
+            .collect::<Vec<Value>>();
+
+        let sorts = body.get(Types::Sort.as_str())
+            .and_then(|s| s.as_array().cloned())
+            .unwrap_or_default();
+        if !sorts.is_empty() {
+            items.sort_by(|a, b| sort_docs(&schema, &sorts, a, b));
+        }
+        let skip = body.get(Types::Skip.as_str()).and_then(|s| s.as_u64()).unwrap_or(0) as usize;
+        let limit = body.get(Types::Limit.as_str()).and_then(|l| l.as_u64()).map_or(usize::MAX, |l| l as usize);
+        let inline = inline_limit(&body);
+        let paths = body.get(Types::Project.as_str())
+            .and_then(|p| p.as_array().cloned())
+            .unwrap_or_default();
+        let paths = paths.iter().filter_map(|p| p.as_str()).collect::<Vec<&str>>();
+        let items = items
+            .into_iter()
+            .skip(skip)
+            .take(limit)
+            .map(without_expiry)
+            .map(|doc| if paths.is_empty() { doc } else { project(&doc, &paths) })
+            .map(|doc| match inline {
+                Some(max) => self.inline_attachments(db, &schema, doc, max),
+                None => doc
+            })
+            .collect::<Vec<Value>>();
+        DbRes { data: Some(items), error: None }
+    }
+
+    pub fn upsert_doc(&self, cmd: &JqlCommand) -> DbRes {
+        let db = cmd._database.as_ref().unwrap();
+        let col = cmd._collection.as_ref().unwrap();
+        let wrp_schema = self.schema(db, col);
+        if wrp_schema.is_none() {
+            return DbRes { data: None, error: Some(TrxError::UpsertDocumentError(format!("collection does not exist `{}`", col))) };
+        }
+        let schema = wrp_schema.unwrap();
+        let docs = match cmd._body.clone().unwrap() {
+            Value::Array(docs) => docs,
+            doc => vec![doc]
+        };
+
+        // validate everything first so a bad document doesn't leave half a batch behind, nor take keys
+        let parser = Jql::new_value_parser(serde_json::to_string(&schema).unwrap());
+        let mut checked = vec![];
+        for doc in docs {
+            let prepared = self.check_doc(&schema, &parser, db, col, doc);
+            if prepared.is_err() {
+                return DbRes { data: None, error: Some(prepared.err().unwrap()) };
+            }
+            checked.push(prepared.unwrap());
+        }
+        let mut keyed = vec![];
+        for doc in checked {
+            let prepared = self.key_doc(&schema, db, col, doc);
+            if prepared.is_err() {
+                return DbRes { data: None, error: Some(prepared.err().unwrap()) };
+            }
+            keyed.push(prepared.unwrap());
+        }
+        let attached = self.check_attachments(db, col, &schema, &keyed);
+        if attached.is_err() {
+            return DbRes { data: None, error: Some(attached.err().unwrap()) };
+        }
+
+        let name = Self::db_col_merged(cmd).unwrap();
+        let cf_created = self.open_col_cf(name.as_str(), &schema);
+        if cf_created.is_err() {
+            return DbRes { data: None, error: Some(TrxError::UpsertDocumentError(cf_created.err().unwrap().to_string())) };
+        }
+
+        let lock = self.col_lock(name.as_str());
+        let _guard = lock.lock().unwrap();
+        let now = now_secs();
+        let attaches = !schema.blob_fields().is_empty();
+        let keyed = keyed
+            .into_iter()
+            .map(|(key, doc)| (key, schema.stamp_expiry(&doc, now)))
+            .collect::<Vec<(TKey, Value)>>();
+        // what each document replaces, a key repeated in the batch replaces its earlier copy
+        let mut previous = vec![];
+        if attaches {
+            let mut pending: BTreeMap<TKey, Value> = BTreeMap::new();
+            for (key, doc) in keyed.iter() {
+                let before = pending.get(key).cloned().or_else(|| self.trx.get(name.as_str(), key.clone()));
+                previous.push(before);
+                pending.insert(key.clone(), doc.clone());
+            }
+        }
+        let mut claimed = vec![];
+        if attaches {
+            let claims = self.claim_attachments(db, col, &schema, &keyed);
+            if claims.is_err() {
+                return DbRes { data: None, error: Some(claims.err().unwrap()) };
+            }
+            claimed = claims.unwrap();
+        }
+        let created = self.trx.put_all(
+            name.as_str(),
+            keyed.iter().map(|(key, doc)| (key.clone(), serde_json::to_vec(&schema.stamp(doc)).unwrap())).collect()
+        );
+        if created.is_err() {
+            self.release_attachments(db, claimed);
+            return DbRes { data: None, error: Some(TrxError::UpsertDocumentError(created.err().unwrap().to_string())) };
+        }
+
+        let mut keys = vec![];
+        for (i, (key, doc)) in keyed.into_iter().enumerate() {
+            let shown = schema.decode_key(&key);
+            if attaches {
+                self.settle_attachments(db, col, &schema, &key, &doc, previous[i].as_ref());
+            }
+            self.publish(db, col, &schema, &key, ChangeKind::Upsert, Some(schema.stamp(&doc)));
+            keys.push(Value::String(shown));
+        }
+        DbRes { data: Some(keys), error: None }
+    }
+
+    ///
+    // the collection's column family, created with the `_storage` of its schema when it isn't there yet
+    ///
+    pub fn open_col_cf(&self, name: &str, schema: &JqlSchema) -> Result<(), TrxError> {
+        if self.trx.has_cf(name) {
+            return Ok(());
+        }
+        self.trx.tuning.set_cf(name, schema._storage.as_ref());
+        self.trx.create_cf(name)
+    }
+
+    ///
+    // `check_doc` and then `key_doc`, for one document on its own
+    ///
+    pub fn prepare_doc(&self, schema: &JqlSchema, parser: &Jql, db: &str, col: &str, doc: Value) -> Result<(TKey, Value), TrxError> {
+        self.check_doc(schema, parser, db, col, doc)
+            .and_then(|doc| self.key_doc(schema, db, col, doc))
+    }
+
+    ///
+    // defaults and validation. A document without its key is checked with the key `new_key` would hand
+    // out next, so a rejected document doesn't use up a sequence number
+    ///
+    pub fn check_doc(&self, schema: &JqlSchema, parser: &Jql, db: &str, col: &str, doc: Value) -> Result<Value, TrxError> {
+        let mut doc = schema.with_defaults(doc);
+        if doc.is_object() {
+            doc.as_object_mut().unwrap().remove(Types::ExpiresAt.as_str());
+        }
+        let mut checked = doc.clone();
+        if schema.key_of(&doc).is_none() && doc.is_object() {
+            let next = self.peek_key(schema, db, col);
+            if next.is_some() {
+                checked.as_object_mut().unwrap().insert(schema._key.to_string(), next.unwrap());
+            }
+        }
+        let chk = parser.parse_value_with_json(Ok(checked));
+        if chk.is_err() {
+            return Err(TrxError::UpsertDocumentError(chk.err().unwrap().to_string()));
+        }
+        Ok(doc)
+    }
+
+    ///
+    // a generated key when the document came without one and the strategy has one, then the storage key
+    ///
+    pub fn key_doc(&self, schema: &JqlSchema, db: &str, col: &str, doc: Value) -> Result<(TKey, Value), TrxError> {
+        let mut doc = doc;
+        if schema.key_of(&doc).is_none() {
+            let generated = self.new_key(schema, db, col);
+            if generated.is_err() {
+                return Err(generated.err().unwrap());
+            }
+            if generated.as_ref().unwrap().is_some() && doc.is_object() {
+                doc.as_object_mut().unwrap().insert(schema._key.to_string(), generated.unwrap().unwrap());
+            }
+        }
+        let key = schema.key_parts(&doc).and_then(|parts| schema.encode_key(&parts));
+        if key.is_none() {
+            return Err(TrxError::UpsertDocumentError(format!("document has no `{}`", schema._key)));
+        }
+        Ok((key.unwrap(), doc))
+    }
+
+    pub fn delete_doc(&self, cmd: &JqlCommand) -> DbRes {
+        let db = cmd._database.as_ref().unwrap();
+        let col = cmd._collection.as_ref().unwrap();
+        let wrp_schema = self.schema(db, col);
+        if wrp_schema.is_none() {
+            return DbRes { data: None, error: Some(TrxError::DeleteDocumentError(format!("collection does not exist `{}`", col))) };
+        }
+        let schema = wrp_schema.unwrap();
+        let name = Self::db_col_merged(cmd).unwrap();
+        if !self.trx.has_cf(name.as_str()) {
+            return DbRes { data: Some(vec![]), error: None };
+        }
+
+        let body = cmd._body.clone().unwrap();
+        let filter = body.get(Types::Filter.as_str()).cloned().unwrap_or(Value::Null);
+        let lock = self.col_lock(name.as_str());
+        let _guard = lock.lock().unwrap();
+        let found = self.select(name.as_str(), &schema, &body);
+        if found.is_err() {
+            return DbRes { data: None, error: Some(TrxError::DeleteDocumentError(found.err().unwrap())) };
+        }
+        let found = found
+            .unwrap()
+            .into_iter()
+            .filter(|(_, doc)| is_match(&filter, &schema.upgrade(doc.clone())))
+            .collect::<Vec<(TKey, Value)>>();
+
+        let dlt = self.trx.delete_all(name.as_str(), found.iter().map(|(key, _)| key.clone()).collect());
+        if dlt.is_err() {
+            return DbRes { data: None, error: Some(TrxError::DeleteDocumentError(dlt.err().unwrap().to_string())) };
+        }
+
+        let mut deleted = vec![];
+        for (key, doc) in found {
+            self.drop_attachments(db, col, &schema, &key, &doc);
+            self.publish(db, col, &schema, &key, ChangeKind::Delete, None);
+            deleted.push(Value::String(schema.decode_key(&key)));
+        }
+        DbRes { data: Some(deleted), error: None }
+    }
+
+    ///
+    // documents `_id` and `_from`/`_to` narrow the scan to, see `key_scope`. `_filter` is left to the caller
+    ///
+    fn select(&self, name: &str, schema: &JqlSchema, body: &Value) -> Result<Vec<(TKey, Value)>, String> {
+        let found = self.scan(name, schema, body)?;
+        if !schema.expires() {
+            return Ok(found);
+        }
+        // expired documents are gone for readers even before the sweeper gets to them
+        let now = now_secs();
+        Ok(found
+            .into_iter()
+            .filter(|(_, doc)| !is_expired(doc, now))
+            .collect())
+    }
+
+    fn scan(&self, name: &str, schema: &JqlSchema, body: &Value) -> Result<Vec<(TKey, Value)>, String> {
+        match schema.key_scope(body)? {
+            KeyScope::Nothing => Ok(vec![]),
+            KeyScope::Key(key) => Ok(self.trx.get(name, key.clone()).map(|doc| (key, doc)).into_iter().collect()),
+            KeyScope::Prefix(prefix) => Ok(self.trx.entries(name, prefix)),
+            KeyScope::Range(from, to) => Ok(self.trx.between(name, from, to)),
+            KeyScope::All => Ok(self.trx.entries(name, vec![]))
+        }
+    }
+
+    ///
+    // `doc` as stored, with its `_version`, readers upgrade it the way they upgrade reads
+    ///
+    pub fn publish(&self, db: &str, col: &str, schema: &JqlSchema, key: &[u8], kind: ChangeKind, doc: Option<Value>) {
+        let change = Change {
+            database: db.to_string(),
+            collection: col.to_string(),
+            key: schema.decode_key(key),
+            raw: key.to_vec(),
+            kind,
+            doc,
+        };
+        // no subscribers is not an error, the change simply goes nowhere
+        let sent = self.feed.send(change);
+        if sent.is_ok() {
+            trace!("published change to {} subscribers", sent.unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn key_selectors() {
+        let pairs: JqlSchema = serde_json::from_str(r#"
+            { "_name":"pairs", "_key":["a","b"], "_fields":[ { "_name":"a", "_declare":"string" }, { "_name":"b", "_declare":"int" } ] }
+        "#).unwrap();
+        assert_eq!(pairs.key_selector(&json!({}), Types::Id), Ok(None));
+        assert_eq!(pairs.key_selector(&json!({ "_id": null }), Types::Id), Ok(Some(vec![])));
+        assert_eq!(pairs.key_selector(&json!({ "_id": [] }), Types::Id), Ok(Some(vec![])));
+        assert_eq!(pairs.key_selector(&json!({ "_id": "[\"x\",1]" }), Types::Id), Ok(Some(vec![json!("x"), json!(1)])));
+        assert!(pairs.key_selector(&json!({ "_id": "[\"x\"," }), Types::Id).is_err());
+        assert_eq!(pairs.key_selector(&json!({ "_from": null }), Types::From), Ok(None));
+    }
+
+    #[test]
+    fn projections() {
+        let doc = json!({ "ids": "a1", "age": 3, "address": { "city": "Oslo", "zip": "0150" }, "items": [{ "sku": "x1" }] });
+        assert_eq!(project(&doc, &["ids", "address.city"]), json!({ "ids": "a1", "address": { "city": "Oslo" } }));
+        assert_eq!(project(&doc, &["items", "missing", "age.years"]), json!({ "items": [{ "sku": "x1" }] }));
+
+        let mut built = Map::new();
+        set_path(&mut built, "address.city", json!("Oslo"));
+        set_path(&mut built, "address.zip", json!("0150"));
+        assert_eq!(Value::Object(built), json!({ "address": { "city": "Oslo", "zip": "0150" } }));
+    }
+
+    #[test]
+    fn filter_match() {
+        let doc = json!({ "ids": "a1", "age": 3, "tags": ["x"] });
+        assert!(is_match(&Value::Null, &doc));
+        assert!(is_match(&json!({ "age": 3 }), &doc));
+        assert!(is_match(&json!({ "age": 3, "tags": ["x"] }), &doc));
+        assert!(!is_match(&json!({ "age": 4 }), &doc));
+        assert!(!is_match(&json!({ "missing": 1 }), &doc));
+
+        let nested = json!({ "ids": "a1", "address": { "city": "Oslo" }, "items": [{ "sku": "x1" }] });
+        assert!(is_match(&json!({ "address.city": "Oslo" }), &nested));
+        assert!(is_match(&json!({ "items.0.sku": "x1" }), &nested));
+        assert!(!is_match(&json!({ "address.zip": "0150" }), &nested));
+        assert!(!is_match(&json!({ "ids.city": "Oslo" }), &nested));
+    }
+
+    #[test]
+    fn defaults_fill_missing() {
+        let schema: JqlSchema = serde_json::from_str(r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"state", "_declare":"string", "_default":"open" },
+                    { "_name":"age", "_declare":"int", "_optional":true }
+                ]
+            }
+        "#).unwrap();
+        assert_eq!(schema.with_defaults(json!({ "ids":"a1" })), json!({ "ids":"a1", "state":"open" }));
+        assert_eq!(schema.with_defaults(json!({ "ids":"a1", "state":"done" })), json!({ "ids":"a1", "state":"done" }));
+
+        let nested: JqlSchema = serde_json::from_str(r#"
+            {
+                "_name":"orders",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"items", "_declare":"object[]", "_fields":[
+                        { "_name":"sku", "_declare":"string" },
+                        { "_name":"qty", "_declare":"uint", "_default":1 }
+                    ] }
+                ]
+            }
+        "#).unwrap();
+        let filled = nested.with_defaults(json!({ "ids":"o1", "items":[{ "sku":"x1" }, { "sku":"x2", "qty":3 }] }));
+        assert_eq!(filled["items"], json!([{ "sku":"x1", "qty":1 }, { "sku":"x2", "qty":3 }]));
+    }
+
+    #[test]
+    fn typed_order() {
+        assert_eq!(cmp_decimal("10.5", "9.75"), Ordering::Greater);
+        assert_eq!(cmp_decimal("1.50", "1.5"), Ordering::Equal);
+        assert_eq!(cmp_decimal("-2", "-10.01"), Ordering::Greater);
+        assert_eq!(cmp_decimal("-0.00", "0"), Ordering::Equal);
+        assert_eq!(cmp_decimal("007", "7.000"), Ordering::Equal);
+
+        // "/w==" is [255], "AA==" is [0]; as strings they'd sort the other way round
+        let bytes = cmp_declared(Some(DataTypes::Bytes), &json!("/w=="), &json!("AA=="));
+        assert_eq!(bytes, Ordering::Greater);
+        let geo = cmp_declared(Some(DataTypes::GeoPoint), &json!({ "lat": 1.0, "lon": 5.0 }), &json!({ "lat": 1.0, "lon": -5.0 }));
+        assert_eq!(geo, Ordering::Greater);
+
+        let schema: JqlSchema = serde_json::from_str(r#"
+            {
+                "_name":"orders",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"total", "_declare":"decimal" },
+                    { "_name":"items", "_declare":"object[]", "_fields":[ { "_name":"price", "_declare":"decimal" } ] }
+                ]
+            }
+        "#).unwrap();
+        assert!(schema.declare_of("items.0.price") == Some(DataTypes::Decimal));
+        assert!(schema.declare_of("missing").is_none());
+        let sorts = vec![json!({ "_field":"total", "_order":"asc" })];
+        let mut docs = vec![json!({ "total":"10.5" }), json!({ "total":"9.75" }), json!({ "total":"-1" })];
+        docs.sort_by(|a, b| sort_docs(&schema, &sorts, a, b));
+        let totals = docs.iter().map(|d| d["total"].as_str().unwrap()).collect::<Vec<&str>>();
+        assert_eq!(totals, vec!["-1", "9.75", "10.5"]);
+    }
+
+    #[test]
+    fn sort_order() {
+        let sorts = vec![json!({ "_field":"age", "_order":"desc" }), json!({ "_field":"name" })];
+        let mut docs = vec![
+            json!({ "name":"b", "age":1 }),
+            json!({ "name":"a", "age":1 }),
+            json!({ "name":"c", "age":7.5 }),
+            json!({ "name":"d" }),
+        ];
+        let schema = JqlSchema::builder("person", "name").build();
+        docs.sort_by(|a, b| sort_docs(&schema, &sorts, a, b));
+        let names = docs.iter().map(|d| d["name"].as_str().unwrap()).collect::<Vec<&str>>();
+        assert_eq!(names, vec!["c", "a", "b", "d"]);
+    }
+}
@@ -22,6 +22,15 @@ impl From<String> for DataTypes {
             "uint[]" => DataTypes::ArrayOfUint,
             "float[]" => DataTypes::ArrayOfFloat,
             "string[]" => DataTypes::ArrayOfString,
+            "boolean[]" => DataTypes::ArrayOfBoolean,
+            "object" => DataTypes::Object,
+            "object[]" => DataTypes::ArrayOfObject,
+            "decimal" => DataTypes::Decimal,
+            "uuid" => DataTypes::Uuid,
+            "bytes" => DataTypes::Bytes,
+            "geopoint" => DataTypes::GeoPoint,
+            "json" => DataTypes::Json,
+            "blob" => DataTypes::Blob,
             &_ => DataTypes::String
         }
     }
@@ -42,7 +51,15 @@ impl DataTypes {
             DataTypes::ArrayOfInt => "int[]".to_string(),
             DataTypes::ArrayOfUint => "uint[]".to_string(),
             DataTypes::ArrayOfFloat => "float[]".to_string(),
-            DataTypes::ArrayOfBoolean => "boolean[]".to_string()
+            DataTypes::ArrayOfBoolean => "boolean[]".to_string(),
+            DataTypes::Object => "object".to_string(),
+            DataTypes::ArrayOfObject => "object[]".to_string(),
+            DataTypes::Decimal => "decimal".to_string(),
+            DataTypes::Uuid => "uuid".to_string(),
+            DataTypes::Bytes => "bytes".to_string(),
+            DataTypes::GeoPoint => "geopoint".to_string(),
+            DataTypes::Json => "json".to_string(),
+            DataTypes::Blob => "blob".to_string()
         }
     }
 }
@@ -1,13 +1,30 @@
 use anyhow::{Result};
+use std::env::VarError;
+use std::fmt::Display;
 use std::fs;
+use std::path::Path;
+use std::str::FromStr;
 use serde_derive::{Deserialize};
 use log::{error};
 use crate::err::EnvReadError;
-use crate::var::ENV_PATH;
+use crate::var::{CONFIG_PATH, DEFAULT_LISTEN, DEFAULT_WIRE_LISTEN};
+
+/// !```
+/// let cfg = env::env()?;                                  // MOEDB_CONFIG, else ./moedb.toml, else the environment alone
+/// let cfg = env::from_path("/etc/moedb/moedb.toml")?;
+/// let cfg = ConfigBuilder::new().db_path("/var/lib/moedb").listen("0.0.0.0:7741").build()?;
+/// !```
+/// every key of the `[moedb]` section can be overridden by `MOEDB_<KEY>`, e.g. `MOEDB_DB_PATH`, and
+/// every key of `[storage]` by `MOEDB_STORAGE_<KEY>`, e.g. `MOEDB_STORAGE_COMPRESSION`. `default_auth`
+/// takes a comma separated list there. `log_path` falls back to `db_path`, the `[storage]` section is
+/// optional and checked in tune.rs
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct BaseConfig {
-    pub moedb: MoeDb,
+    #[serde(default)]
+    pub moedb: ConfigBuilder,
+    #[serde(default)]
+    pub storage: StorageConfig,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -15,40 +32,381 @@ pub struct MoeDb {
     pub db_path: String,
     pub log_path: String,
     pub default_auth: Vec<String>,
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    #[serde(default = "default_wire_listen")]
+    pub wire_listen: String,
+    #[serde(default)]
+    pub backup_path: Option<String>,
+    #[serde(default)]
+    pub storage: StorageConfig,
 }
 
-pub fn env() -> Result<MoeDb, EnvReadError> {
-    let content = fs::read_to_string(ENV_PATH);
+///
+// the `[storage]` section, the defaults are what every db used before it existed. Sizes are in bytes,
+// `rate_limit` in bytes per second
+///
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct StorageConfig {
+    pub compaction: String,
+    pub compression: String,
+    pub block_cache_size: Option<usize>,
+    pub write_buffer_size: Option<usize>,
+    pub bloom_filter_bits: Option<f64>,
+    pub wal_sync: String,
+    pub bytes_per_sync: u64,
+    pub rate_limit: Option<i64>,
+    pub background_jobs: i32,
+    pub mmap: bool,
+}
+
+///
+// the `[moedb]` section with every key optional, so the file, the environment and code can each fill in a part
+///
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct ConfigBuilder {
+    pub db_path: Option<String>,
+    pub log_path: Option<String>,
+    pub default_auth: Option<Vec<String>>,
+    pub listen: Option<String>,
+    pub wire_listen: Option<String>,
+    pub backup_path: Option<String>,
+    #[serde(skip)]
+    pub storage: Option<StorageConfig>,
+}
+
+fn default_listen() -> String {
+    DEFAULT_LISTEN.to_string()
+}
+
+fn default_wire_listen() -> String {
+    DEFAULT_WIRE_LISTEN.to_string()
+}
+
+///
+// `MOEDB_` and the key in upper case
+///
+pub fn env_key(key: &str) -> String {
+    format!("MOEDB_{}", key.to_uppercase())
+}
+
+fn env_value(key: &str) -> Result<Option<String>, EnvReadError> {
+    match std::env::var(env_key(key)) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(EnvReadError::InvalidValue(env_key(key), "not valid unicode".to_string()))
+    }
+}
+
+///
+// the value `lookup` has for `key` parsed, `None` when it has none
+///
+fn parse_env<T, F>(lookup: &F, key: &str) -> Result<Option<T>, EnvReadError>
+    where T: FromStr, T::Err: Display, F: Fn(&str) -> Result<Option<String>, EnvReadError> {
+    let raw = lookup(key)?;
+    if raw.is_none() {
+        return Ok(None);
+    }
+    let raw = raw.unwrap();
+    let parsed = raw.trim().parse::<T>();
+    if parsed.is_err() {
+        return Err(EnvReadError::InvalidValue(env_key(key), format!("`{}` {}", raw, parsed.err().unwrap())));
+    }
+    Ok(Some(parsed.unwrap()))
+}
+
+///
+// host and port, the host is resolved when the server binds
+///
+fn is_address_ok(addr: &str) -> bool {
+    match addr.rsplit_once(':') {
+        Some((host, port)) => !host.is_empty() && port.parse::<u16>().is_ok(),
+        None => false
+    }
+}
+
+fn create_dir(path: &str) -> Result<(), EnvReadError> {
+    let created = fs::create_dir_all(path);
+    if created.is_err() {
+        return Err(EnvReadError::CreateDirError(path.to_string(), created.err().unwrap().to_string()));
+    }
+    let mtd = fs::metadata(path);
+    if mtd.is_err() || !mtd.unwrap().is_dir() {
+        return Err(EnvReadError::CreateDirError(path.to_string(), "not a directory".to_string()));
+    }
+    Ok(())
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        ConfigBuilder::default()
+    }
+
+    pub fn db_path(mut self, path: &str) -> Self {
+        self.db_path = Some(path.to_string());
+        self
+    }
+
+    pub fn log_path(mut self, path: &str) -> Self {
+        self.log_path = Some(path.to_string());
+        self
+    }
+
+    pub fn default_auth(mut self, auth: &[&str]) -> Self {
+        self.default_auth = Some(auth.iter().map(|a| a.to_string()).collect());
+        self
+    }
+
+    pub fn listen(mut self, addr: &str) -> Self {
+        self.listen = Some(addr.to_string());
+        self
+    }
+
+    pub fn wire_listen(mut self, addr: &str) -> Self {
+        self.wire_listen = Some(addr.to_string());
+        self
+    }
+
+    pub fn backup_path(mut self, path: &str) -> Self {
+        self.backup_path = Some(path.to_string());
+        self
+    }
+
+    pub fn storage(mut self, storage: StorageConfig) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    ///
+    // `MOEDB_<KEY>` and `MOEDB_STORAGE_<KEY>` win over what is set so far
+    ///
+    pub fn with_env(self) -> Result<Self, EnvReadError> {
+        self.with_lookup(env_value)
+    }
+
+    fn with_lookup<F: Fn(&str) -> Result<Option<String>, EnvReadError>>(mut self, lookup: F) -> Result<Self, EnvReadError> {
+        let slots = [
+            ("db_path", &mut self.db_path),
+            ("log_path", &mut self.log_path),
+            ("listen", &mut self.listen),
+            ("wire_listen", &mut self.wire_listen),
+            ("backup_path", &mut self.backup_path),
+        ];
+        for (key, slot) in slots {
+            let value = lookup(key)?;
+            if value.is_some() {
+                *slot = value;
+            }
+        }
+        let auth = lookup("default_auth")?;
+        if auth.is_some() {
+            self.default_auth = Some(auth.unwrap()
+                .split(',')
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect());
+        }
+
+        let mut storage = self.storage.take().unwrap_or_default();
+        for (key, slot) in [
+            ("storage_compaction", &mut storage.compaction),
+            ("storage_compression", &mut storage.compression),
+            ("storage_wal_sync", &mut storage.wal_sync),
+        ] {
+            if let Some(value) = lookup(key)? {
+                *slot = value;
+            }
+        }
+        for (key, slot) in [
+            ("storage_block_cache_size", &mut storage.block_cache_size),
+            ("storage_write_buffer_size", &mut storage.write_buffer_size),
+        ] {
+            if let Some(size) = parse_env(&lookup, key)? {
+                *slot = Some(size);
+            }
+        }
+        if let Some(bits) = parse_env(&lookup, "storage_bloom_filter_bits")? {
+            storage.bloom_filter_bits = Some(bits);
+        }
+        if let Some(bytes) = parse_env(&lookup, "storage_bytes_per_sync")? {
+            storage.bytes_per_sync = bytes;
+        }
+        if let Some(rate) = parse_env(&lookup, "storage_rate_limit")? {
+            storage.rate_limit = Some(rate);
+        }
+        if let Some(jobs) = parse_env(&lookup, "storage_background_jobs")? {
+            storage.background_jobs = jobs;
+        }
+        if let Some(mmap) = parse_env(&lookup, "storage_mmap")? {
+            storage.mmap = mmap;
+        }
+        self.storage = Some(storage);
+        Ok(self)
+    }
+
+    ///
+    // fills in the defaults, checks the addresses and creates the db and log directories
+    ///
+    pub fn build(self) -> Result<MoeDb, EnvReadError> {
+        let db_path = self.db_path.unwrap_or_default();
+        if db_path.trim().is_empty() {
+            return Err(EnvReadError::MissingKey("db_path".to_string(), env_key("db_path")));
+        }
+        let moedb = MoeDb {
+            log_path: self.log_path.unwrap_or_else(|| db_path.clone()),
+            db_path,
+            default_auth: self.default_auth.unwrap_or_default(),
+            listen: self.listen.unwrap_or_else(default_listen),
+            wire_listen: self.wire_listen.unwrap_or_else(default_wire_listen),
+            backup_path: self.backup_path.filter(|p| !p.trim().is_empty()),
+            storage: self.storage.unwrap_or_default(),
+        };
+        for (key, addr) in [("listen", &moedb.listen), ("wire_listen", &moedb.wire_listen)] {
+            if !is_address_ok(addr.as_str()) {
+                return Err(EnvReadError::InvalidValue(key.to_string(), format!("`{}` is not a host:port", addr)));
+            }
+        }
+        let storage = moedb.storage.check();
+        if storage.is_err() {
+            return Err(EnvReadError::InvalidValue("storage".to_string(), storage.err().unwrap()));
+        }
+
+        let db_created = create_dir(moedb.db_path.as_str());
+        if db_created.is_err() {
+            let err = db_created.err().unwrap().to_string();
+            error!("{}",err);
+            return Err(EnvReadError::DbPathNotValid(err));
+        }
+        let log_created = create_dir(moedb.log_path.as_str());
+        if log_created.is_err() {
+            let err = log_created.err().unwrap().to_string();
+            error!("{}",err);
+            return Err(EnvReadError::LogPathNotValid(err));
+        }
+        Ok(moedb)
+    }
+}
+
+///
+// a db directory opened without a config file, RocksDB keeps its LOG next to the data
+///
+pub fn at(db_path: &str) -> MoeDb {
+    MoeDb {
+        db_path: db_path.to_string(),
+        log_path: db_path.to_string(),
+        default_auth: vec![],
+        listen: default_listen(),
+        wire_listen: default_wire_listen(),
+        backup_path: None,
+        storage: StorageConfig::default(),
+    }
+}
+
+///
+// the `[moedb]` section of a file as a builder, before the environment is applied
+///
+pub fn read_config<P: AsRef<Path>>(path: P) -> Result<ConfigBuilder, EnvReadError> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path);
     if content.is_err() {
-        let err = content.err().unwrap().to_string();
+        let err = format!("{}: {}", path.display(), content.err().unwrap());
         error!("{}",err);
         return Err(EnvReadError::NoContent(err));
     }
 
     let sys_cfg: Result<BaseConfig, toml::de::Error> = toml::from_str(content.unwrap().as_str());
     if sys_cfg.is_err() {
-        let err = sys_cfg.err().unwrap().to_string();
+        let err = format!("{}: {}", path.display(), sys_cfg.err().unwrap());
         error!("{}",err);
         return Err(EnvReadError::InvalidToml(err));
     }
+    let sys_cfg = sys_cfg.unwrap();
+    Ok(sys_cfg.moedb.storage(sys_cfg.storage))
+}
 
-    let moedb = sys_cfg.unwrap().moedb;
-
-    fs::create_dir_all(moedb.db_path.as_str()).unwrap();
-    fs::create_dir_all(moedb.log_path.as_str()).unwrap();
+pub fn from_path<P: AsRef<Path>>(path: P) -> Result<MoeDb, EnvReadError> {
+    read_config(path)?.with_env()?.build()
+}
 
-    let mtd_db = fs::metadata(moedb.db_path.as_str());
-    if mtd_db.is_err() || !mtd_db.as_ref().unwrap().is_dir() {
-        let err = mtd_db.err().unwrap().to_string();
-        error!("{}",err);
-        return Err(EnvReadError::DbPathNotValid(err));
+///
+// `MOEDB_CONFIG` must name a file that exists, without it `./moedb.toml` is used when there is one,
+// otherwise the config comes from the environment alone
+///
+pub fn env() -> Result<MoeDb, EnvReadError> {
+    let named = env_value("config")?;
+    if named.is_some() {
+        let named = named.unwrap();
+        if !Path::new(named.as_str()).is_file() {
+            return Err(EnvReadError::NotFound(named, env_key("config")));
+        }
+        return from_path(named);
+    }
+    if Path::new(CONFIG_PATH).is_file() {
+        return from_path(CONFIG_PATH);
     }
+    ConfigBuilder::new().with_env()?.build()
+}
 
-    let mtd_log = fs::metadata(moedb.log_path.as_str());
-    if mtd_log.is_err() || !mtd_log.as_ref().unwrap().is_dir() {
-        let err = mtd_log.err().unwrap().to_string();
-        error!("{}",err);
-        return Err(EnvReadError::LogPathNotValid(err));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_layers() {
+        let dir = std::env::temp_dir().join(format!("moedb-env-{}", std::process::id()));
+        let db_path = dir.join("db");
+        let built = ConfigBuilder::new()
+            .db_path(db_path.to_str().unwrap())
+            .default_auth(&["root"])
+            .build()
+            .unwrap();
+        assert_eq!(built.log_path, built.db_path);
+        assert_eq!(built.listen, DEFAULT_LISTEN);
+        assert_eq!(built.default_auth, vec!["root".to_string()]);
+        assert!(db_path.is_dir());
+
+        assert!(matches!(ConfigBuilder::new().build(), Err(EnvReadError::MissingKey(..))));
+        let bad = ConfigBuilder::new().db_path(db_path.to_str().unwrap()).listen("7741").build();
+        assert!(matches!(bad, Err(EnvReadError::InvalidValue(..))));
+
+        let file = dir.join("moedb.toml");
+        fs::write(&file, "[moedb]\nlog_path = \"elsewhere\"\nwire_listen = \"0.0.0.0:9000\"\n").unwrap();
+        let read = read_config(&file).unwrap();
+        assert_eq!(read.db_path, None);
+        assert_eq!(read.wire_listen.as_deref(), Some("0.0.0.0:9000"));
+        assert!(matches!(read_config(dir.join("missing.toml")), Err(EnvReadError::NoContent(_))));
+        assert_eq!(read.storage.as_ref().unwrap().compression, "snappy");
+        fs::write(&file, "[moedb]\ndb_path = \"db\"\n[storage]\ncompression = \"zstd\"\nbloom_filter_bits = 10.0\n").unwrap();
+        let storage = read_config(&file).unwrap().storage.unwrap();
+        assert_eq!(storage.compression, "zstd");
+        assert_eq!(storage.bloom_filter_bits, Some(10.0));
+        assert_eq!(storage.compaction, "universal");
+        fs::write(&file, "[moedb\n").unwrap();
+        assert!(matches!(read_config(&file), Err(EnvReadError::InvalidToml(_))));
+
+        let vars = [
+            ("storage_compression", "lz4"),
+            ("storage_block_cache_size", "1048576"),
+            ("storage_rate_limit", "4096"),
+            ("storage_mmap", "false"),
+        ];
+        let lookup = |key: &str| Ok(vars.iter().find(|(k, _)| k.eq(&key)).map(|(_, v)| v.to_string()));
+        let storage = ConfigBuilder::new()
+            .with_lookup(lookup)
+            .unwrap()
+            .storage
+            .unwrap();
+        assert_eq!(storage.compression, "lz4");
+        assert_eq!(storage.compaction, "universal");
+        assert_eq!(storage.block_cache_size, Some(1048576));
+        assert_eq!(storage.rate_limit, Some(4096));
+        assert!(!storage.mmap);
+        let bad = ConfigBuilder::new().with_lookup(|key: &str| Ok((key == "storage_background_jobs").then(|| "many".to_string())));
+        assert!(matches!(bad, Err(EnvReadError::InvalidValue(..))));
+
+        assert_eq!(env_key("db_path"), "MOEDB_DB_PATH");
+        assert_eq!(env_key("storage_wal_sync"), "MOEDB_STORAGE_WAL_SYNC");
+        let _ = fs::remove_dir_all(dir);
     }
-    Ok(moedb)
-}
\ No newline at end of file
+}
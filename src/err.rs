@@ -19,6 +19,26 @@ pub enum JqlError {
     NoCollectionProvided,
     #[error("no schema or malformed provided")]
     NoSchemaProvided,
+    #[error("no document or malformed provided")]
+    NoDocumentProvided,
+    #[error("`_id` or `_filter` is required")]
+    NoSelectorProvided,
+    #[error("query option error `{0}`")]
+    QueryOptionError(String),
+    #[error("`_add`, `_drop`, `_optional` or `_widen` is required")]
+    NoAlterationProvided,
+    #[error("alteration error `{0}`")]
+    AlterationError(String),
+    #[error("no bucket or malformed name provided")]
+    NoBucketProvided,
+    #[error("`_key` of the object is required")]
+    NoObjectKeyProvided,
+    #[error("`_data` has to be base64 `{0}`")]
+    ObjectDataError(String),
+    #[error("`_upload_id` is required")]
+    NoUploadProvided,
+    #[error("`_target` has to be a directory name")]
+    NoBackupTargetProvided,
     #[error("unknown query")]
     UnknownQuery,
 }
@@ -33,6 +53,14 @@ pub enum EnvReadError {
     LogPathNotValid(String),
     #[error("database path is not valid `{0}`")]
     DbPathNotValid(String),
+    #[error("config file `{0}` named by `{1}` does not exist")]
+    NotFound(String, String),
+    #[error("`{0}` is not set, add it to the `[moedb]` section or set `{1}`")]
+    MissingKey(String, String),
+    #[error("invalid value for `{0}`, {1}")]
+    InvalidValue(String, String),
+    #[error("could not create directory `{0}`, {1}")]
+    CreateDirError(String, String),
 }
 
 #[derive(Error, Debug, Serialize, Deserialize, Clone)]
@@ -49,6 +77,22 @@ pub enum MoeDbError {
     QueryError(String),
     #[error("transaction error `{0}`")]
     TransactionError(String),
+    #[error("server error `{0}`")]
+    ServerError(String),
+    #[error("wire protocol error `{0}`")]
+    WireError(String),
+    #[error("remote error `{0}`")]
+    RemoteError(String),
+    #[error("blocking task failed `{0}`")]
+    RuntimeError(String),
+    #[error("document conversion error `{0}`")]
+    DocumentError(String),
+    #[error("error upgrading stored keys `{0}`")]
+    KeyFormatError(String),
+    #[error("backup error `{0}`")]
+    BackupError(String),
+    #[error("config error `{0}`")]
+    ConfigError(String),
 }
 
 #[derive(Error, Debug, Serialize, Deserialize, Clone)]
@@ -63,6 +107,26 @@ pub enum TrxError {
     DropCollectionError(String),
     #[error("error dropping db `{0}`")]
     DropDbError(String),
+    #[error("error getting document `{0}`")]
+    GetDocumentError(String),
+    #[error("error upserting document `{0}`")]
+    UpsertDocumentError(String),
+    #[error("error deleting document `{0}`")]
+    DeleteDocumentError(String),
+    #[error("error altering collection `{0}`")]
+    AlterCollectionError(String),
+    #[error("object storage error `{0}`")]
+    ObjectError(String),
+    #[error("range not satisfiable `{0}`")]
+    RangeError(String),
+    #[error("upload error `{0}`")]
+    UploadError(String),
+    #[error("backup error `{0}`")]
+    BackupError(String),
+    #[error("export error `{0}`")]
+    ExportError(String),
+    #[error("import error `{0}`")]
+    ImportError(String),
     #[error("unknown error")]
     UnknownError
 }
@@ -1,21 +1,46 @@
-use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
 use log::{trace};
 use serde_json::Value;
+use tokio::sync::broadcast;
 use crate::env;
 use crate::err::{MoeDbError};
-use crate::hdrs::{ActionType, Exec, Jql, Trx};
+use crate::hdrs::{ActionType, Exec, Jql, ObjectLocks, Trx};
+use crate::var::FEED_CAPACITY;
 
 impl Exec {
-    pub fn new(cfg: Arc<env::MoeDb>) -> Result<Self, MoeDbError> {
+    pub fn new(cfg: Arc<env::MoeDb>, log: Option<Arc<Trx>>) -> Result<Self, MoeDbError> {
         let env = Arc::clone(&cfg);
         let trx = Trx::new(Arc::clone(&env), false);
         if trx.is_err() {
             return Err(trx.err().unwrap());
         }
-        Ok(Self {
+        let (feed, _) = broadcast::channel(FEED_CAPACITY);
+        let exec = Self {
             env: cfg,
-            trx: Arc::new(trx.unwrap())
-        })
+            trx: Arc::new(trx.unwrap()),
+            feed,
+            seq: Mutex::new(()),
+            uploads: Mutex::new(HashSet::new()),
+            backups: Mutex::new(()),
+            buckets: Mutex::new(()),
+            collections: Mutex::new(HashMap::new()),
+            objects: Arc::new(ObjectLocks::default()),
+            log
+        };
+        let upgraded = exec.upgrade_key_format();
+        if upgraded.is_err() {
+            return Err(upgraded.err().unwrap());
+        }
+        Ok(exec)
+    }
+
+    ///
+    // writers of one collection column family take turns on it: upserts, deletes, alters and the sweeper
+    ///
+    pub fn col_lock(&self, name: &str) -> Arc<Mutex<()>> {
+        let mut locks = self.collections.lock().unwrap();
+        Arc::clone(locks.entry(name.to_string()).or_default())
     }
 
     pub fn run(&self, id: &str, stmt: &str) -> Result<Option<Vec<Value>>, MoeDbError> {
@@ -34,14 +59,25 @@ impl Exec {
         let exec_res = match ActionType::from(parsed._action.as_str()) {
             ActionType::Create => self.new_col(&parsed),
             ActionType::CreateDb => self.new_db(&parsed),
-            ActionType::Get => todo!(),
-            ActionType::Upsert => todo!(),
-            ActionType::Delete => todo!(),
+            ActionType::Get => self.get_doc(&parsed),
+            ActionType::Upsert => self.upsert_doc(&parsed),
+            ActionType::Delete => self.delete_doc(&parsed),
             ActionType::Drop => self.drop_col(&parsed),
             ActionType::DropDb => self.drop_db(&parsed),
             ActionType::DbList => self.db_list(),
             ActionType::Truncate => self.trun_col(&parsed),
             ActionType::ColList => self.col_list(&parsed),
+            ActionType::Alter => self.alter_col(&parsed),
+            ActionType::PutObject => self.put_obj(&parsed),
+            ActionType::GetObject => self.get_obj(&parsed),
+            ActionType::DeleteObject => self.delete_obj(&parsed),
+            ActionType::ListObjects => self.list_obj(&parsed),
+            ActionType::BeginUpload => self.begin_upl(&parsed),
+            ActionType::PutPart => self.put_prt(&parsed),
+            ActionType::CompleteUpload => self.complete_upl(&parsed),
+            ActionType::AbortUpload => self.abort_upl(&parsed),
+            ActionType::ListParts => self.list_prt(&parsed),
+            ActionType::Backup => self.backup_db(&parsed),
             _ => self.db_list()
         };
 
@@ -0,0 +1,399 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use log::{info, warn};
+use serde_json::Value;
+use crate::doc::is_match;
+use crate::err::{MoeDbError, TrxError};
+use crate::func::unique_id;
+use crate::hdrs::{Attachment, ExportHeader, ExportReport, Exec, ImportReport, JqlSchema, KeyStrategy, MoeDb, Query, RejectedLine};
+use crate::ttl::{is_expired, now_secs, without_expiry};
+use crate::util::key_merger;
+use crate::var::{DB_SYS, EXPORT_FORMAT, EXPORT_MARKER, IMPORT_BATCH, IMPORT_ERROR_LIMIT};
+
+/// !```
+/// {"_moedb_export":1,"_database":"shop","_collection":"users","_schema":{"_name":"users","_key":"ids",...},"_sequence":42}
+/// {"ids":"u1","name":"moe"}
+/// {"ids":"u2","name":"joe"}
+/// {"_moedb_export":1,"_database":"shop","_collection":"orders",...}
+/// !```
+/// every collection starts with a header, its documents follow one per line as `get` returns them.
+/// `_filter` exports only the matching documents, gzip output is optional and recognised on import.
+/// Import creates what is missing through `create-collection` and loads the documents through `upsert`,
+/// so they are validated against the schema. Lines that don't make it are reported with their number.
+/// Documents with a `_ttl` start over on import. Objects behind `blob` fields are not part of an export,
+/// a field whose object the target database doesn't hold is left out on import and the document is
+/// counted in `_detached`. Documents that require such a field are rejected by the schema
+
+///
+// the schema as `create-collection` takes it, exported documents are already upgraded so the
+// version history stays behind
+///
+pub fn export_schema(schema: &JqlSchema) -> Value {
+    let mut shown = schema.clone();
+    shown._version = None;
+    shown._migrations = vec![];
+    serde_json::to_value(shown).unwrap()
+}
+
+pub fn export_header(line: &Value) -> Option<ExportHeader> {
+    line.get(EXPORT_MARKER)?;
+    serde_json::from_value::<ExportHeader>(line.clone()).ok()
+}
+
+///
+// gzip is told apart by its magic bytes, anything else is read as plain JSON Lines
+///
+pub fn open_input<'a, R: Read + 'a>(input: R) -> Result<Box<dyn BufRead + 'a>, std::io::Error> {
+    let mut reader = BufReader::new(input);
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))));
+    }
+    Ok(Box::new(reader))
+}
+
+impl ImportReport {
+    pub fn reject(&mut self, line: u64, error: String) {
+        self._rejected += 1;
+        if self._errors.len() < IMPORT_ERROR_LIMIT {
+            self._errors.push(RejectedLine { _line: line, _error: error });
+        }
+    }
+}
+
+impl Exec {
+    ///
+    // `col` narrows the export to one collection of `db`, `filter` to the documents matching it
+    ///
+    pub fn export_jsonl<W: Write>(&self, out: W, db: &str, col: Option<&str>, filter: &Value, compress: bool) -> Result<ExportReport, TrxError> {
+        if self.trx.get(DB_SYS, Self::db_key(db)).is_none() {
+            return Err(TrxError::ExportError(format!("db does not exist `{}`", db)));
+        }
+        let cols = match col {
+            Some(col) => vec![col.to_string()],
+            None => self.col_names(db)
+        };
+        if compress {
+            let mut gz = GzEncoder::new(out, Compression::default());
+            let report = self.write_export(&mut gz, db, cols, filter);
+            if report.is_err() {
+                return report;
+            }
+            return gz.finish().map(|_| report.unwrap()).map_err(|e| TrxError::ExportError(e.to_string()));
+        }
+        let mut out = BufWriter::new(out);
+        let report = self.write_export(&mut out, db, cols, filter);
+        if report.is_err() {
+            return report;
+        }
+        out.flush().map(|_| report.unwrap()).map_err(|e| TrxError::ExportError(e.to_string()))
+    }
+
+    fn write_export(&self, out: &mut dyn Write, db: &str, cols: Vec<String>, filter: &Value) -> Result<ExportReport, TrxError> {
+        let mut report = ExportReport::default();
+        for col in cols {
+            let schema = self.schema(db, col.as_str());
+            if schema.is_none() {
+                return Err(TrxError::ExportError(format!("collection does not exist `{}`", col)));
+            }
+            let schema = schema.unwrap();
+            let header = ExportHeader {
+                _moedb_export: EXPORT_FORMAT,
+                _database: db.to_string(),
+                _collection: col.clone(),
+                _schema: export_schema(&schema),
+                _sequence: match schema.key_strategy() {
+                    KeyStrategy::Sequence => self.trx.get(DB_SYS, Self::sequence_key(db, col.as_str())).and_then(|s| s.as_u64()),
+                    _ => None
+                },
+            };
+            let written = writeln!(out, "{}", serde_json::to_string(&header).unwrap());
+            if written.is_err() {
+                return Err(TrxError::ExportError(written.err().unwrap().to_string()));
+            }
+            report._collections += 1;
+            let name = key_merger(vec![db.to_string(), col.clone()]).unwrap();
+            if !self.trx.has_cf(name.as_str()) {
+                continue;
+            }
+            let now = now_secs();
+            let mut failed = None;
+            self.trx.each(name.as_str(), vec![], |_, doc| {
+                if is_expired(&doc, now) {
+                    return true;
+                }
+                let doc = schema.upgrade(doc);
+                if !is_match(filter, &doc) {
+                    return true;
+                }
+                let doc = without_expiry(doc);
+                let written = writeln!(out, "{}", serde_json::to_string(&doc).unwrap());
+                if written.is_err() {
+                    failed = Some(written.err().unwrap().to_string());
+                    return false;
+                }
+                report._documents += 1;
+                true
+            });
+            if failed.is_some() {
+                return Err(TrxError::ExportError(failed.unwrap()));
+            }
+        }
+        info!("exported {} documents of {} collections from `{}`", report._documents, report._collections, db);
+        Ok(report)
+    }
+
+    ///
+    // `into` loads everything into that database instead of the one named in the headers
+    ///
+    pub fn import_jsonl<R: Read>(&self, input: R, into: Option<&str>) -> Result<ImportReport, TrxError> {
+        let reader = open_input(input);
+        if reader.is_err() {
+            return Err(TrxError::ImportError(reader.err().unwrap().to_string()));
+        }
+        let mut report = ImportReport::default();
+        // the collection documents go to, `None` after a header that couldn't be set up
+        let mut target: Option<(String, String)> = None;
+        let mut schema: Option<JqlSchema> = None;
+        let mut failed_header: Option<String> = None;
+        let mut batch: Vec<(u64, Value)> = vec![];
+        for (idx, line) in reader.unwrap().lines().enumerate() {
+            let number = idx as u64 + 1;
+            if line.is_err() {
+                return Err(TrxError::ImportError(format!("line {} {}", number, line.err().unwrap())));
+            }
+            let line = line.unwrap();
+            if line.trim().is_empty() {
+                continue;
+            }
+            let value = serde_json::from_str::<Value>(line.as_str());
+            if value.is_err() {
+                report.reject(number, value.err().unwrap().to_string());
+                continue;
+            }
+            let value = value.unwrap();
+            if value.get(EXPORT_MARKER).is_some() {
+                self.import_batch(&target, &mut batch, &mut report);
+                let prepared = export_header(&value)
+                    .ok_or(TrxError::ImportError("malformed collection header".to_string()))
+                    .and_then(|h| self.prepare_import(h, into));
+                match prepared {
+                    Ok(names) => {
+                        report._collections.push(format!("{}.{}", names.0, names.1));
+                        schema = self.schema(names.0.as_str(), names.1.as_str());
+                        target = Some(names);
+                        failed_header = None;
+                    }
+                    Err(err) => {
+                        report.reject(number, err.to_string());
+                        target = None;
+                        failed_header = Some(err.to_string());
+                    }
+                }
+                continue;
+            }
+            if target.is_none() {
+                let reason = failed_header.clone().unwrap_or("no collection header before this document".to_string());
+                report.reject(number, reason);
+                continue;
+            }
+            if !value.is_object() {
+                report.reject(number, "a document has to be an object".to_string());
+                continue;
+            }
+            let mut value = value;
+            if schema.as_ref().map_or(false, |s| self.detach_missing(target.as_ref().unwrap().0.as_str(), s, &mut value)) {
+                report._detached += 1;
+            }
+            batch.push((number, value));
+            if batch.len() >= IMPORT_BATCH {
+                self.import_batch(&target, &mut batch, &mut report);
+            }
+        }
+        self.import_batch(&target, &mut batch, &mut report);
+        info!("imported {} documents, {} lines rejected, {} without their attachments", report._imported, report._rejected, report._detached);
+        Ok(report)
+    }
+
+    fn prepare_import(&self, header: ExportHeader, into: Option<&str>) -> Result<(String, String), TrxError> {
+        if header._moedb_export > EXPORT_FORMAT {
+            return Err(TrxError::ImportError(format!("export format {} is newer than this build reads", header._moedb_export)));
+        }
+        let db = into.unwrap_or(header._database.as_str()).to_string();
+        let col = header._collection.clone();
+        if self.trx.get(DB_SYS, Self::db_key(db.as_str())).is_none() {
+            let created = self.run(unique_id().as_str(), Query::database(db.as_str()).create().to_jql().as_str());
+            if created.is_err() {
+                return Err(TrxError::ImportError(created.err().unwrap().to_string()));
+            }
+        }
+        if self.schema(db.as_str(), col.as_str()).is_none() {
+            let schema = serde_json::from_value::<JqlSchema>(header._schema.clone());
+            if schema.is_err() {
+                return Err(TrxError::ImportError(format!("schema of `{}` {}", col, schema.err().unwrap())));
+            }
+            let mut schema = schema.unwrap();
+            schema._name = col.clone();
+            let created = self.run(unique_id().as_str(), Query::database(db.as_str()).create_collection(&schema).to_jql().as_str());
+            if created.is_err() {
+                return Err(TrxError::ImportError(created.err().unwrap().to_string()));
+            }
+        } else {
+            warn!("importing into the existing collection `{}`", col);
+        }
+        if header._sequence.is_some() {
+            let raised = self.raise_sequence(db.as_str(), col.as_str(), header._sequence.unwrap());
+            if raised.is_err() {
+                return Err(raised.err().unwrap());
+            }
+        }
+        Ok((db, col))
+    }
+
+    ///
+    // leaves out the `blob` fields whose object isn't in `db`, answers whether any were
+    ///
+    fn detach_missing(&self, db: &str, schema: &JqlSchema, doc: &mut Value) -> bool {
+        let missing = schema.blob_fields()
+            .into_iter()
+            .filter(|f| doc.get(*f)
+                .and_then(|v| serde_json::from_value::<Attachment>(v.clone()).ok())
+                .map_or(false, |a| self.object_meta(db, a._bucket.as_str(), a._key.as_str()).is_none()))
+            .map(|f| f.to_string())
+            .collect::<Vec<String>>();
+        let fields = doc.as_object_mut().unwrap();
+        for field in missing.iter() {
+            fields.remove(field.as_str());
+        }
+        !missing.is_empty()
+    }
+
+    ///
+    // a batch is upserted as one, only when that fails its documents go one by one to find the bad lines
+    ///
+    pub fn import_batch(&self, target: &Option<(String, String)>, batch: &mut Vec<(u64, Value)>, report: &mut ImportReport) {
+        if batch.is_empty() || target.is_none() {
+            batch.clear();
+            return;
+        }
+        let (db, col) = target.as_ref().unwrap();
+        let query = Query::collection(db.as_str(), col.as_str());
+        let docs = batch.iter().map(|(_, doc)| doc.clone()).collect::<Vec<Value>>();
+        let upserted = self.upsert_doc(&query.upsert(&docs).unwrap());
+        if upserted.error.is_none() {
+            report._imported += batch.len() as u64;
+            batch.clear();
+            return;
+        }
+        for (number, doc) in batch.drain(..) {
+            let upserted = self.upsert_doc(&query.upsert(&doc).unwrap());
+            match upserted.error {
+                None => report._imported += 1,
+                Some(err) => report.reject(number, err.to_string())
+            }
+        }
+    }
+}
+
+impl MoeDb {
+    pub fn export_jsonl<W: Write>(&self, out: W, db: &str, col: Option<&str>, filter: &Value, compress: bool) -> Result<ExportReport, MoeDbError> {
+        self.exec.export_jsonl(out, db, col, filter, compress)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn import_jsonl<R: Read>(&self, input: R, into: Option<&str>) -> Result<ImportReport, MoeDbError> {
+        self.exec.import_jsonl(input, into)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::hdrs::{DataTypes, MigrateMode, SchemaMigration};
+    use super::*;
+
+    #[test]
+    fn export_lines() {
+        let mut users = JqlSchema::builder("users", "ids")
+            .field("name", DataTypes::String)
+            .build();
+        users._version = Some(3);
+        users._migrations = vec![SchemaMigration {
+            _version: 2,
+            _add: vec![],
+            _drop: vec!["age".to_string()],
+            _optional: vec![],
+            _widen: vec![],
+            _migrate: Some(MigrateMode::Lazy.to_string()),
+        }];
+        let shown = export_schema(&users);
+        assert!(shown.get("_version").is_none());
+        assert!(shown.get("_migrations").is_none());
+        assert_eq!(shown["_key"], "ids");
+
+        let header = ExportHeader {
+            _moedb_export: EXPORT_FORMAT,
+            _database: "shop".to_string(),
+            _collection: "users".to_string(),
+            _schema: shown,
+            _sequence: Some(42),
+        };
+        let line = serde_json::to_value(&header).unwrap();
+        assert_eq!(export_header(&line).unwrap()._sequence, Some(42));
+        assert!(export_header(&json!({ "ids": "u1" })).is_none());
+
+        let plain = b"{\"ids\":\"u1\"}\n{\"ids\":\"u2\"}\n".to_vec();
+        let mut gz = GzEncoder::new(vec![], Compression::default());
+        gz.write_all(&plain).unwrap();
+        let packed = gz.finish().unwrap();
+        for input in [plain.clone(), packed] {
+            let lines = open_input(input.as_slice()).unwrap().lines().map(|l| l.unwrap()).collect::<Vec<String>>();
+            assert_eq!(lines, vec!["{\"ids\":\"u1\"}", "{\"ids\":\"u2\"}"]);
+        }
+
+        let mut report = ImportReport::default();
+        for line in 0..IMPORT_ERROR_LIMIT as u64 + 5 {
+            report.reject(line, "bad".to_string());
+        }
+        assert_eq!(report._rejected, IMPORT_ERROR_LIMIT as u64 + 5);
+        assert_eq!(report._errors.len(), IMPORT_ERROR_LIMIT);
+    }
+
+    #[test]
+    fn import_without_objects() {
+        let root = std::env::temp_dir().join(format!("moedb-exp-{}", unique_id()));
+        let db = MoeDb::new(crate::env::at(root.to_str().unwrap())).unwrap();
+        let header = json!({
+            "_moedb_export": EXPORT_FORMAT,
+            "_database": "shop",
+            "_collection": "items",
+            "_schema": {
+                "_name": "items",
+                "_key": "ids",
+                "_fields": [
+                    { "_name": "name", "_declare": "string" },
+                    { "_name": "photo", "_declare": "blob", "_optional": true }
+                ]
+            }
+        });
+        let lines = [
+            header,
+            json!({ "ids": "i1", "name": "lamp", "photo": { "_bucket": "photos", "_key": "lamp.png" } }),
+            json!({ "ids": "i2", "name": "desk" }),
+        ].iter().map(|l| l.to_string()).collect::<Vec<String>>().join("\n");
+        let report = db.import_jsonl(lines.as_bytes(), None).unwrap();
+        assert_eq!(report._imported, 2, "{:?}", report._errors);
+        assert_eq!(report._rejected, 0);
+        assert_eq!(report._detached, 1);
+        let res = db.execute(r#"{ "_action":"get", "_database":"shop", "_collection":"items", "_body":{ "_id":"i1" } }"#);
+        assert!(!res.error, "{}", res.message);
+        let data = res.data.unwrap();
+        assert_eq!(data[0]["name"], "lamp");
+        assert!(data[0].get("photo").is_none());
+        drop(db);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
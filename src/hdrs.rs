@@ -1,7 +1,12 @@
-use std::sync::Arc;
-use rocksdb::{DBWithThreadMode, MultiThreaded};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use rocksdb::{Cache, DBWithThreadMode, MultiThreaded};
 use serde_derive::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::sync::broadcast::Sender;
+use tokio::sync::oneshot;
 use valico::json_dsl::Builder;
 use crate::env;
 use crate::err::TrxError;
@@ -16,18 +21,67 @@ pub struct MoeDb {
     pub log: Arc<Logging>
 }
 
+pub struct AsyncMoeDb {
+    pub db: Arc<MoeDb>
+}
+
+pub struct Server {
+    pub db: Arc<MoeDb>
+}
+
+pub struct WireClient {
+    pub next: AtomicU64,
+    pub writer: tokio::sync::Mutex<OwnedWriteHalf>,
+    pub pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Response>>>>,
+    pub closed: Arc<AtomicBool>
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WireRequest {
+    pub id: u64,
+    pub stmt: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WireResponse {
+    pub id: u64,
+    pub time_taken: String,
+    pub error: bool,
+    pub message: String,
+    pub data: Option<Vec<u8>>,
+}
+
 pub struct Logging {
     pub trx: Arc<Trx>
 }
 
 pub struct Exec {
     pub env: Arc<env::MoeDb>,
-    pub trx: Arc<Trx>
+    pub trx: Arc<Trx>,
+    pub feed: Sender<Change>,
+    pub seq: Mutex<()>,
+    pub uploads: Mutex<HashSet<String>>,
+    pub backups: Mutex<()>,
+    pub buckets: Mutex<()>,
+    pub collections: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+    pub objects: Arc<ObjectLocks>,
+    pub log: Option<Arc<Trx>>
 }
 
 pub struct Trx {
     pub db: Arc<MoeDbMode>,
-    pub env: Arc<env::MoeDb>
+    pub env: Arc<env::MoeDb>,
+    pub tuning: Tuning,
+}
+
+///
+// `[storage]` of the config, the block cache every column family shares and the `_storage` of each
+// collection by column family with the cache of its own, see tune.rs
+///
+pub struct Tuning {
+    pub storage: env::StorageConfig,
+    pub cache: Option<Cache>,
+    pub cfs: RwLock<BTreeMap<String, (CfStorage, Option<Cache>)>>,
 }
 
 #[derive(Clone)]
@@ -59,15 +113,144 @@ pub struct Jql {
 #[derive(Clone, Deserialize, Serialize)]
 pub struct JqlSchema {
     pub _name: String,
-    pub _key: String,
+    pub _key: SchemaKey,
+    #[serde(default)]
     pub _fields: Vec<JqlSchemaFields>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _in_memory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _version: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _migrations: Vec<SchemaMigration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _key_strategy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _ttl: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _expire_field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _storage: Option<CfStorage>,
+}
+
+///
+// `[storage]` keys a collection can set for its own column family, the rest applies to the whole db
+///
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct CfStorage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _compaction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _compression: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _write_buffer_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _bloom_filter_bits: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _block_cache_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _max_table_files_size: Option<u64>,
+}
+
+///
+/// `"_key": "ids"` or, for compound keys, the fields in key order `"_key": ["device", "ts"]`
+///
+#[derive(Clone, Deserialize, Serialize, Debug)]
+#[serde(untagged)]
+pub enum SchemaKey {
+    Field(String),
+    Fields(Vec<String>),
 }
 
 #[derive(Clone, Deserialize, Serialize)]
 pub struct JqlSchemaFields {
     pub _name: String,
     pub _declare: String,
-    pub _optional: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "crate::util::bool_or_string")]
+    pub _optional: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _default: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _min_len: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _max_len: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _enum: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _fields: Option<Vec<JqlSchemaFields>>,
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct SchemaMigration {
+    #[serde(default)]
+    pub _version: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _add: Vec<JqlSchemaFields>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _drop: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _optional: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _widen: Vec<JqlSchemaFields>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _migrate: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub enum KeyStrategy {
+    Client,
+    Uuid,
+    Ulid,
+    Sequence,
+}
+
+#[derive(Clone, Debug)]
+pub enum SchemaMode {
+    Strict,
+    Flexible,
+    Schemaless,
+}
+
+#[derive(Clone, Debug)]
+pub enum MigrateMode {
+    Lazy,
+    Eager,
+}
+
+pub struct SchemaBuilder {
+    pub schema: JqlSchema
+}
+
+pub struct DbQuery {
+    pub database: String
+}
+
+pub struct Query {
+    pub database: String,
+    pub collection: String,
+    pub id: Option<Value>,
+    pub from: Option<Value>,
+    pub to: Option<Value>,
+    pub filter: Map<String, Value>,
+    pub sort: Vec<(String, SortOrder)>,
+    pub limit: Option<u64>,
+    pub skip: Option<u64>,
+    pub live: bool,
+    pub inline: Option<u64>,
+    pub project: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SortOrder {
+    Asc,
+    Desc,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -76,6 +259,256 @@ pub struct JqlCommand {
     pub _body: Option<Value>,
     pub _database: Option<String>,
     pub _collection: Option<String>,
+    pub _live: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub _bucket: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ObjectMeta {
+    pub _key: String,
+    pub _size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub _metadata: Map<String, Value>,
+    pub _checksum: String,
+    pub _chunk_size: u64,
+    pub _chunks: Vec<u32>,
+    pub _generation: String,
+    pub _updated_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub _owner: Option<String>,
+}
+
+///
+// `swap` is held while the meta of an object is replaced. `pins` counts the readers of each
+// `<bucket cf>#<generation>` and holds the meta a swap retired under them until the last one is done
+///
+#[derive(Default)]
+pub struct ObjectLocks {
+    pub swap: Mutex<()>,
+    pub pins: Mutex<HashMap<String, (usize, Option<ObjectMeta>)>>,
+}
+
+///
+// streams the chunks of `start..end` in order, each checked against its crc as it is read
+///
+pub struct ObjectReader {
+    pub trx: Arc<Trx>,
+    pub locks: Arc<ObjectLocks>,
+    pub cf: String,
+    pub meta: ObjectMeta,
+    pub start: u64,
+    pub end: u64,
+    pub next: usize,
+    pub whole: Option<crc32fast::Hasher>,
+}
+
+///
+// `std::io::Write` into a bucket, nothing is visible until `finish`, dropping it unfinished discards the chunks
+///
+pub struct ObjectWriter {
+    pub trx: Arc<Trx>,
+    pub locks: Arc<ObjectLocks>,
+    pub cf: String,
+    pub meta: ObjectMeta,
+    pub buffer: Vec<u8>,
+    pub hasher: crc32fast::Hasher,
+    pub finished: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ObjectUpload {
+    pub _upload_id: String,
+    pub _key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub _content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub _metadata: Map<String, Value>,
+    #[serde(default)]
+    pub _parts: BTreeMap<u32, UploadPart>,
+    pub _started_at: u64,
+    #[serde(default)]
+    pub _touched_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UploadPart {
+    pub _size: u64,
+    pub _checksum: String,
+}
+
+///
+// first line of every collection in a JSON Lines export, its documents follow one per line
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ExportHeader {
+    pub _moedb_export: u64,
+    pub _database: String,
+    pub _collection: String,
+    pub _schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub _sequence: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ExportReport {
+    pub _collections: u64,
+    pub _documents: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ImportReport {
+    pub _collections: Vec<String>,
+    pub _imported: u64,
+    pub _rejected: u64,
+    pub _errors: Vec<RejectedLine>,
+    #[serde(default)]
+    pub _detached: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct RejectedLine {
+    pub _line: u64,
+    pub _error: String,
+}
+
+///
+// `columns` maps a CSV column to the (dotted) field it fills on import and heads on export, unmapped
+// columns keep their name
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct CsvOptions {
+    pub delimiter: u8,
+    pub array_delimiter: String,
+    pub columns: BTreeMap<String, String>,
+    pub date_format: Option<String>,
+    pub datetime_format: Option<String>,
+}
+
+///
+// a line of the `moedb` shell once it is understood
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShellCommand {
+    Jql(String),
+    Use(String),
+    ImportCsv(String, String, CsvOptions),
+    ExportCsv(String, String, Vec<String>, CsvOptions),
+    ImportJsonl(String),
+    ExportJsonl(String, Option<String>),
+    Help,
+    Exit,
+    Invalid(String),
+}
+
+#[derive(Clone, Debug)]
+pub enum WalSync {
+    Os,
+    Bytes,
+    Always,
+}
+
+#[derive(Clone, Debug)]
+pub enum BackupMode {
+    Checkpoint,
+    Incremental,
+}
+
+///
+// written last into a backup directory, a backup without one never completed
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct BackupManifest {
+    pub _mode: String,
+    pub _created_at: u64,
+    pub _key_format: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub _files: Vec<BackupFile>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub _backup_ids: BTreeMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct BackupFile {
+    pub _path: String,
+    pub _size: u64,
+    pub _checksum: String,
+}
+
+///
+// value of a `blob` field, an object in a bucket of the document's database
+///
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Attachment {
+    pub _bucket: String,
+    pub _key: String,
+}
+
+pub struct ObjectQuery {
+    pub database: String,
+    pub bucket: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Change {
+    pub database: String,
+    pub collection: String,
+    pub key: String,
+    pub raw: TKey,
+    pub kind: ChangeKind,
+    pub doc: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ChangeKind {
+    Upsert,
+    Delete,
+    Expire,
+}
+
+pub struct LiveQuery {
+    pub id: String,
+    pub database: String,
+    pub collection: String,
+    pub schema: JqlSchema,
+    pub scope: KeyScope,
+    pub filter: Option<Value>,
+    pub paths: Vec<String>,
+    pub keys: HashSet<TKey>,
+}
+
+///
+// the stored keys `_id` or `_from`/`_to` of a body narrow a collection to, see doc.rs
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum KeyScope {
+    All,
+    Nothing,
+    Key(TKey),
+    Prefix(TKey),
+    Range(Option<TKey>, Option<TKey>),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LiveEvent {
+    pub _live_id: String,
+    pub _event: LiveEventKind,
+    pub key: Option<String>,
+    pub data: Option<Vec<Value>>,
+    pub message: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveEventKind {
+    Init,
+    Add,
+    Change,
+    Remove,
+    Cancel,
+    Error,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -93,6 +526,14 @@ pub enum DataTypes {
     ArrayOfUint,
     ArrayOfFloat,
     ArrayOfBoolean,
+    Object,
+    ArrayOfObject,
+    Decimal,
+    Uuid,
+    Bytes,
+    GeoPoint,
+    Json,
+    Blob,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug)]
@@ -102,6 +543,43 @@ pub enum Types {
     Fields,
     InMemory,
     Declare,
+    Id,
+    Filter,
+    Sort,
+    Limit,
+    Skip,
+    Field,
+    Order,
+    Version,
+    Default,
+    Migrate,
+    Optional,
+    Min,
+    Max,
+    MinLen,
+    MaxLen,
+    Pattern,
+    Enum,
+    Mode,
+    KeyStrategy,
+    From,
+    To,
+    Ttl,
+    ExpireField,
+    ExpiresAt,
+    Data,
+    ContentType,
+    Metadata,
+    Prefix,
+    Range,
+    UploadId,
+    Part,
+    Bucket,
+    Inline,
+    Target,
+    Keep,
+    Storage,
+    Project,
 }
 
 #[derive(Clone, Ord, PartialOrd, Eq, Debug)]
@@ -116,6 +594,17 @@ pub enum ActionType {
     DbList,
     ColList,
     Truncate,
+    Alter,
+    PutObject,
+    GetObject,
+    DeleteObject,
+    ListObjects,
+    BeginUpload,
+    PutPart,
+    CompleteUpload,
+    AbortUpload,
+    ListParts,
+    Backup,
     Unknown
 }
 
@@ -125,4 +614,5 @@ pub enum CommandType {
     Body,
     Database,
     Store,
+    Live,
 }
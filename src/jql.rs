@@ -1,26 +1,70 @@
 use fancy_regex::Regex;
+use serde_json::Value;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use valico::json_dsl::{Param, string};
+use valico::json_dsl::errors::WrongValue;
+use crate::bak::is_target_ok;
 use crate::err::JqlError;
 use crate::func::is_naming_ok;
-use crate::hdrs::{ActionType, DataTypes, Jql, JqlCommand};
+use crate::hdrs::{ActionType, Attachment, DataTypes, Jql, JqlCommand, MigrateMode, SchemaMigration, SortOrder, Types};
 use crate::jqls::JqlSchemaParser;
 
 /// !```
 /// {
-///     "_action": "create-db | create-collection | get | delete | drop-collection | drop-db | upsert | truncate | db-*",
+///     "_action": "create-db | create-collection | alter-collection | get | delete | drop-collection | drop-db | upsert | truncate | db-*
+///                 | put-object | get-object | delete-object | list-objects
+///                 | begin-upload | put-part | list-parts | complete-upload | abort-upload | backup",
 ///     "_database": "<your_database_name>",
 ///     "_collection": "<your_collection_name>",
-///     "_body": "your json based on `_action`"
+///     "_bucket": "<your_bucket_name>" (object actions only, see obj.rs),
+///     "_body": "your json based on `_action`",
+///     "_live": true|false (only for `get` over the live endpoint)
+/// }
+/// Get / Delete example
+/// {
+///     "_id": "<value_of_the_key_field>"
+/// }
+/// {
+///     "_filter": { "<field_name>": <value> },
+///     "_sort": [ { "_field": "<field_name>", "_order": "asc|desc" } ],
+///     "_skip": 0,
+///     "_limit": 10,
+///     "_project": [ "<field_name>", "<field>.<nested_field>" ],
+///     "_inline": true|<bytes> (see att.rs)
+/// }
+/// Backup example (see bak.rs)
+/// {
+///     "_target": "<directory_under_backup_path>",
+///     "_mode": "checkpoint|incremental",
+///     "_keep": <incremental backups to keep>
+/// }
+/// Upsert example
+/// { "<key_field>": "<value>", "<field_name>": <value> } or an array of them
+/// Alter Store example (see mig.rs)
+/// {
+///     "_add": [ { "_name": "<field_name>", "_declare": "<declaration>", "_default": <value> } ],
+///     "_drop": [ "<field_name>" ],
+///     "_optional": [ "<field_name>" ],
+///     "_widen": [ { "_name": "<field_name>", "_declare": "<wider_declaration>" } ],
+///     "_migrate": "lazy|eager"
 /// }
 /// Create Store example
 /// {
 ///     "_name": "<your_collection_name>",
 ///     "_key": "<your_field_name_that's_going_to_be_primary_key>",
 ///     "_in_memory":true|false,
+///     "_mode":"strict|flexible|schemaless",
 ///     "_fields":[
 ///         {
 ///             "_name":"<field_name>",
-///             "_declare":"<declaration>"
+///             "_declare":"<declaration>",
+///             "_optional":true|false,
+///             "_default":<value used when the field is missing>,
+///             "_min":<number>, "_max":<number>,                        (int, uint, float)
+///             "_min_len":<chars>, "_max_len":<chars>, "_pattern":"<regex>", (string)
+///             "_enum":[<allowed values>]                             (int, uint, float, string)
+///             "_fields":[<nested fields>]                            (object, object[])
 ///         }
 ///     ]
 /// }
@@ -41,7 +85,15 @@ impl Jql {
             DataTypes::ArrayOfBoolean.to_string(),
             DataTypes::ArrayOfInt.to_string(),
             DataTypes::ArrayOfUint.to_string(),
-            DataTypes::ArrayOfFloat.to_string()
+            DataTypes::ArrayOfFloat.to_string(),
+            DataTypes::Object.to_string(),
+            DataTypes::ArrayOfObject.to_string(),
+            DataTypes::Decimal.to_string(),
+            DataTypes::Uuid.to_string(),
+            DataTypes::Bytes.to_string(),
+            DataTypes::GeoPoint.to_string(),
+            DataTypes::Json.to_string(),
+            DataTypes::Blob.to_string()
         ]);
     }
 
@@ -67,6 +119,52 @@ impl Jql {
         p.regex(Regex::new(r"^\d197[0-9]|19[89][0-9]|20[0-9]{2}-(0[1-9]|1[012])-(0[1-9]|[12][0-9]|3[01]) (([0-1]?[0-9]|2[0-3]):[0-5][0-9]:[0-5][0-9])$").unwrap());
     }
 
+    ///
+    // exact numbers travel as strings, `"-12.50"`
+    ///
+    pub fn declare_decimal(p: &mut Param) {
+        p.regex(Regex::new(r"^-?\d+(\.\d+)?$").unwrap());
+    }
+
+    pub fn declare_uuid(p: &mut Param) {
+        p.regex(Regex::new(r"^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}$").unwrap());
+    }
+
+    pub fn declare_bytes(p: &mut Param) {
+        p.validate_with(|v, path| match v.as_str().map(|s| STANDARD.decode(s)) {
+            Some(Err(_)) => Err(vec![Box::new(WrongValue { path: path.to_string(), detail: Some("has to be base64".to_string()) })]),
+            _ => Ok(())
+        });
+    }
+
+    ///
+    // `{ "_bucket": "avatars", "_key": "users/1.png" }`, the object itself is checked on upsert
+    ///
+    pub fn declare_blob(p: &mut Param) {
+        p.validate_with(|v, path| match serde_json::from_value::<Attachment>(v.clone()) {
+            Ok(att) if is_naming_ok(&Some(att._bucket.clone())).is_some() && !att._key.is_empty() && v.as_object().map_or(0, |o| o.len()) == 2 => Ok(()),
+            _ => Err(vec![Box::new(WrongValue { path: path.to_string(), detail: Some("has to be { \"_bucket\": \"<bucket>\", \"_key\": \"<object key>\" }".to_string()) })])
+        });
+    }
+
+    ///
+    // `{ "lat": -90..90, "lon": -180..180 }`
+    ///
+    pub fn declare_geopoint(p: &mut Param) {
+        p.validate_with(|v, path| {
+            let lat = v.get("lat").and_then(|l| l.as_f64());
+            let lon = v.get("lon").and_then(|l| l.as_f64());
+            let keys = v.as_object().map_or(0, |o| o.len());
+            let ok = keys == 2
+                && lat.map_or(false, |l| (-90.0..=90.0).contains(&l))
+                && lon.map_or(false, |l| (-180.0..=180.0).contains(&l));
+            if ok {
+                return Ok(());
+            }
+            Err(vec![Box::new(WrongValue { path: path.to_string(), detail: Some("has to be { \"lat\": -90..90, \"lon\": -180..180 }".to_string()) })])
+        });
+    }
+
     pub fn parse(command: &str) -> Result<JqlCommand, JqlError> {
         let parser = serde_json::from_str::<JqlCommand>(command);
         if parser.is_err() {
@@ -88,13 +186,22 @@ impl Jql {
                 }
             }
             ActionType::Get => {
-                todo!()
+                match Jql::is_get_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
             }
             ActionType::Upsert => {
-                todo!()
+                match Jql::is_upsert_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
             }
             ActionType::Delete => {
-                todo!()
+                match Jql::is_delete_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
             }
             ActionType::Drop => {
                 match Jql::is_collection_ok(&to_return) {
@@ -121,10 +228,45 @@ impl Jql {
                     Err(er) => Err(er)
                 }
             }
+            ActionType::Alter => {
+                match Jql::is_alter_collection_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
+            }
+            ActionType::PutObject | ActionType::GetObject | ActionType::DeleteObject | ActionType::ListObjects
+            | ActionType::BeginUpload | ActionType::PutPart | ActionType::CompleteUpload | ActionType::AbortUpload | ActionType::ListParts => {
+                match Jql::is_object_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
+            }
+            ActionType::Backup => {
+                match Jql::is_backup_ok(&to_return) {
+                    Ok(_) => Ok(to_return),
+                    Err(er) => Err(er)
+                }
+            }
             _ => Err(JqlError::UnknownQuery)
         };
     }
 
+    fn is_backup_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let body = match &cmd._body {
+            Some(Value::Object(body)) => body,
+            _ => return Err(JqlError::NoBackupTargetProvided)
+        };
+        if !body.get(Types::Target.as_str()).and_then(|t| t.as_str()).map_or(false, is_target_ok) {
+            return Err(JqlError::NoBackupTargetProvided);
+        }
+        let mode = body.get(Types::Mode.as_str()).map_or(true, |m| m == "checkpoint" || m == "incremental");
+        let keep = body.get(Types::Keep.as_str()).map_or(true, |k| k.as_u64().map_or(false, |k| k >= 1));
+        if !mode || !keep {
+            return Err(JqlError::QueryOptionError(format!("`{}` has to be checkpoint or incremental and `{}` a positive integer", Types::Mode.as_str(), Types::Keep.as_str())));
+        }
+        Ok(())
+    }
+
     fn is_collection_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
         match is_naming_ok(&cmd._database) {
             None => Err(JqlError::NoDatabaseProvided),
@@ -135,6 +277,66 @@ impl Jql {
         }
     }
 
+    fn is_object_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let chk = Jql::is_db_ok(cmd);
+        if chk.is_err() {
+            return chk;
+        }
+        if is_naming_ok(&cmd._bucket).is_none() {
+            return Err(JqlError::NoBucketProvided);
+        }
+        let action = ActionType::from(cmd._action.as_str());
+        let body = match &cmd._body {
+            Some(Value::Object(body)) => body.clone(),
+            None if action == ActionType::ListObjects => return Ok(()),
+            _ => return Err(JqlError::NoObjectKeyProvided)
+        };
+        if action == ActionType::ListObjects {
+            let prefix = body.get(Types::Prefix.as_str()).map_or(true, |p| p.is_string());
+            let limit = body.get(Types::Limit.as_str()).map_or(true, |l| l.is_u64());
+            if !prefix || !limit {
+                return Err(JqlError::QueryOptionError(format!("`{}` has to be a string and `{}` a positive integer", Types::Prefix.as_str(), Types::Limit.as_str())));
+            }
+            return Ok(());
+        }
+        if matches!(action, ActionType::PutPart | ActionType::CompleteUpload | ActionType::AbortUpload | ActionType::ListParts) {
+            if !body.get(Types::UploadId.as_str()).map_or(false, |u| u.is_string()) {
+                return Err(JqlError::NoUploadProvided);
+            }
+            let part = body.get(Types::Part.as_str()).and_then(|p| p.as_u64());
+            if action == ActionType::PutPart && !part.map_or(false, |p| p >= 1 && p <= u32::MAX as u64) {
+                return Err(JqlError::QueryOptionError(format!("`{}` has to be a positive integer", Types::Part.as_str())));
+            }
+        } else if !body.get(Types::Key.as_str()).and_then(|k| k.as_str()).map_or(false, |k| !k.is_empty()) {
+            return Err(JqlError::NoObjectKeyProvided);
+        }
+        if action == ActionType::GetObject {
+            let range = match body.get(Types::Range.as_str()) {
+                None => true,
+                Some(Value::Array(r)) => !r.is_empty() && r.len() <= 2 && r.iter().all(|b| b.is_u64()),
+                Some(_) => false
+            };
+            if !range {
+                return Err(JqlError::QueryOptionError(format!("`{}` has to be [start] or [start, end]", Types::Range.as_str())));
+            }
+        }
+        if action == ActionType::PutObject || action == ActionType::PutPart {
+            let data = body.get(Types::Data.as_str()).and_then(|d| d.as_str());
+            if data.is_none() {
+                return Err(JqlError::ObjectDataError("missing".to_string()));
+            }
+            if STANDARD.decode(data.unwrap()).is_err() {
+                return Err(JqlError::ObjectDataError("not base64".to_string()));
+            }
+        }
+        let content_type = body.get(Types::ContentType.as_str()).map_or(true, |c| c.is_string());
+        let metadata = body.get(Types::Metadata.as_str()).map_or(true, |m| m.is_object());
+        if !content_type || !metadata {
+            return Err(JqlError::QueryOptionError(format!("`{}` has to be a string and `{}` an object", Types::ContentType.as_str(), Types::Metadata.as_str())));
+        }
+        Ok(())
+    }
+
     fn is_db_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
         match is_naming_ok(&cmd._database) {
             None => Err(JqlError::NoDatabaseProvided),
@@ -142,6 +344,136 @@ impl Jql {
         }
     }
 
+    fn is_get_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let chk = Jql::is_collection_ok(cmd);
+        if chk.is_err() {
+            return chk;
+        }
+        let body = match &cmd._body {
+            None => return Ok(()),
+            Some(Value::Object(body)) => body,
+            Some(_) => return Err(JqlError::NoSelectorProvided)
+        };
+        match body.get(Types::Filter.as_str()) {
+            None | Some(Value::Object(_)) => {}
+            Some(_) => return Err(JqlError::NoSelectorProvided)
+        }
+        match body.get(Types::Inline.as_str()) {
+            None | Some(Value::Bool(_)) => {}
+            Some(v) if v.is_u64() => {}
+            Some(v) => return Err(JqlError::QueryOptionError(format!("`{}` has to be true or a size in bytes, got {}", Types::Inline.as_str(), v)))
+        }
+        match body.get(Types::Project.as_str()) {
+            None => {}
+            Some(Value::Array(paths)) if paths.iter().all(|p| is_projection_ok(p)) => {}
+            Some(v) => return Err(JqlError::QueryOptionError(format!("`{}` has to be an array of field names or dotted paths through objects, got {}", Types::Project.as_str(), v)))
+        }
+        for opt in [Types::Limit, Types::Skip] {
+            match body.get(opt.as_str()) {
+                None => {}
+                Some(v) if v.is_u64() => {}
+                Some(v) => return Err(JqlError::QueryOptionError(format!("`{}` has to be a positive integer, got {}", opt.as_str(), v)))
+            }
+        }
+        match body.get(Types::Sort.as_str()) {
+            None => Ok(()),
+            Some(Value::Array(sorts)) => {
+                for sort in sorts {
+                    let field = sort.get(Types::Field.as_str()).map_or(false, |f| f.is_string());
+                    let order = match sort.get(Types::Order.as_str()) {
+                        None => true,
+                        Some(o) => o.as_str().map_or(false, |o| o.eq(SortOrder::Asc.as_str()) || o.eq(SortOrder::Desc.as_str()))
+                    };
+                    if !field || !order {
+                        return Err(JqlError::QueryOptionError(format!("`{}` entries need `{}` and an `{}` of asc|desc, got {}", Types::Sort.as_str(), Types::Field.as_str(), Types::Order.as_str(), sort)));
+                    }
+                }
+                Ok(())
+            }
+            Some(v) => Err(JqlError::QueryOptionError(format!("`{}` has to be an array, got {}", Types::Sort.as_str(), v)))
+        }
+    }
+
+    fn is_upsert_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let chk = Jql::is_collection_ok(cmd);
+        if chk.is_err() {
+            return chk;
+        }
+        match &cmd._body {
+            Some(Value::Object(_)) => Ok(()),
+            Some(Value::Array(docs)) if !docs.is_empty() && docs.iter().all(|d| d.is_object()) => Ok(()),
+            _ => Err(JqlError::NoDocumentProvided)
+        }
+    }
+
+    fn is_delete_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let chk = Jql::is_collection_ok(cmd);
+        if chk.is_err() {
+            return chk;
+        }
+        match &cmd._body {
+            Some(Value::Object(body)) => {
+                // a key or part of one, `null`, `{}` and `[]` would select every document
+                let by_id = match body.get(Types::Id.as_str()) {
+                    Some(Value::String(_)) | Some(Value::Number(_)) | Some(Value::Bool(_)) => true,
+                    Some(Value::Array(parts)) => !parts.is_empty(),
+                    _ => false
+                };
+                let by_filter = body.get(Types::Filter.as_str()).map_or(false, |f| f.as_object().map_or(false, |f| !f.is_empty()));
+                if by_id || by_filter {
+                    Ok(())
+                } else {
+                    Err(JqlError::NoSelectorProvided)
+                }
+            }
+            _ => Err(JqlError::NoSelectorProvided)
+        }
+    }
+
+    fn is_alter_collection_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
+        let chk = Jql::is_collection_ok(cmd);
+        if chk.is_err() {
+            return chk;
+        }
+        let body = match &cmd._body {
+            Some(Value::Object(_)) => cmd._body.clone().unwrap(),
+            _ => return Err(JqlError::NoAlterationProvided)
+        };
+        let migration = serde_json::from_value::<SchemaMigration>(body);
+        if migration.is_err() {
+            return Err(JqlError::AlterationError(migration.err().unwrap().to_string()));
+        }
+        let migration = migration.unwrap();
+        if migration.is_empty() {
+            return Err(JqlError::NoAlterationProvided);
+        }
+        let names = migration._add.iter().map(|f| &f._name)
+            .chain(migration._drop.iter())
+            .chain(migration._optional.iter())
+            .chain(migration._widen.iter().map(|f| &f._name));
+        for name in names {
+            if is_naming_ok(&Some(name.clone())).is_none() {
+                return Err(JqlError::AlterationError(format!("`{}` is not a valid field name", name)));
+            }
+        }
+        for field in migration._add.iter().chain(migration._widen.iter()) {
+            if !DataTypes::from(field._declare.clone()).to_string().eq(&field._declare) {
+                return Err(JqlError::AlterationError(format!("`{}` is not a known declaration", field._declare)));
+            }
+        }
+        for field in migration._add.iter() {
+            let chk = Jql::check_field(field);
+            if chk.is_err() {
+                return Err(JqlError::AlterationError(chk.err().unwrap().to_string()));
+            }
+        }
+        match migration._migrate.as_deref() {
+            None => Ok(()),
+            Some(mode) if mode.eq(MigrateMode::Lazy.as_str()) || mode.eq(MigrateMode::Eager.as_str()) => Ok(()),
+            Some(mode) => Err(JqlError::AlterationError(format!("`{}` has to be lazy|eager, got {}", Types::Migrate.as_str(), mode)))
+        }
+    }
+
     fn is_create_collection_ok(cmd: &JqlCommand) -> Result<(), JqlError> {
         match is_naming_ok(&cmd._database) {
             None => Err(JqlError::NoDatabaseProvided),
@@ -161,6 +493,13 @@ impl Jql {
     }
 }
 
+///
+// projections walk objects, an array index has no place to put the value back
+///
+fn is_projection_ok(path: &Value) -> bool {
+    path.as_str().map_or(false, |p| p.split('.').all(|part| !part.is_empty() && part.parse::<usize>().is_err()))
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Instant;
@@ -225,6 +564,96 @@ mod tests {
         assert!(parser.is_ok());
     }
 
+    #[test]
+    fn upsert_and_delete() {
+        let upsert = r#"
+            {
+                "_action":"upsert",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":[{ "ids":"a1", "hello":["world"] }]
+            }
+        "#;
+        assert!(Jql::parse(upsert).is_ok());
+
+        let delete = r#"
+            {
+                "_action":"delete",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":{ }
+            }
+        "#;
+        let parser = Jql::parse(delete);
+        assert!(parser.is_err());
+
+        for selector in [r#""_id":null"#, r#""_id":{}"#, r#""_id":[]"#, r#""_filter":{}"#] {
+            let delete = format!(r#"{{ "_action":"delete", "_database":"moss", "_collection":"ops", "_body":{{ {} }} }}"#, selector);
+            assert!(matches!(Jql::parse(delete.as_str()), Err(JqlError::NoSelectorProvided)), "{}", selector);
+        }
+        for selector in [r#""_id":"a1""#, r#""_id":7"#, r#""_id":false"#, r#""_id":["a1",2]"#, r#""_filter":{ "state":"open" }"#] {
+            let delete = format!(r#"{{ "_action":"delete", "_database":"moss", "_collection":"ops", "_body":{{ {} }} }}"#, selector);
+            assert!(Jql::parse(delete.as_str()).is_ok(), "{}", selector);
+        }
+    }
+
+    #[test]
+    fn get_with_options() {
+        let get = r#"
+            {
+                "_action":"get",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":{
+                    "_filter":{ "state":"open" },
+                    "_sort":[{ "_field":"age", "_order":"desc" }],
+                    "_limit":10,
+                    "_project":[ "state", "address.city" ]
+                }
+            }
+        "#;
+        assert!(Jql::parse(get).is_ok());
+
+        let bad = r#"
+            {
+                "_action":"get",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":{ "_sort":[{ "_field":"age", "_order":"up" }], "_limit":-1 }
+            }
+        "#;
+        assert!(Jql::parse(bad).is_err());
+        let through_array = get.replace("address.city", "items.0.sku");
+        assert!(Jql::parse(through_array.as_str()).is_err());
+    }
+
+    #[test]
+    fn alter_collection() {
+        let alter = r#"
+            {
+                "_action":"alter-collection",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":{
+                    "_add":[{ "_name":"city", "_declare":"string", "_default":"none" }],
+                    "_widen":[{ "_name":"age", "_declare":"float" }],
+                    "_migrate":"eager"
+                }
+            }
+        "#;
+        assert!(Jql::parse(alter).is_ok());
+
+        let empty = r#"
+            {
+                "_action":"alter-collection",
+                "_database":"moss",
+                "_collection":"ops",
+                "_body":{ "_migrate":"later" }
+            }
+        "#;
+        assert!(Jql::parse(empty).is_err());
+    }
+
     #[test]
     fn drop_collection() {
         let json = r#"
@@ -1,9 +1,12 @@
 use anyhow::{Result};
-use serde_json::Value;
-use valico::json_dsl::{array, boolean, Builder};
+use fancy_regex::Regex;
+use serde_json::{Map, Value};
+use valico::json_dsl::{array, boolean, Builder, f64, object, string, u64};
 use log::{error};
 use crate::err::JqlError;
-use crate::hdrs::{Jql, Types};
+use crate::hdrs::{DataTypes, Jql, JqlSchema, JqlSchemaFields, KeyStrategy, SchemaMode, Types};
+use crate::func::is_naming_ok;
+use crate::jqlv::JqlValueParser;
 
 pub trait JqlSchemaParser {
     fn new_schema_parser() -> Self;
@@ -18,15 +21,45 @@ impl JqlSchemaParser for Jql {
         Self {
             prs: Builder::build(|b| {
                 b.req(Types::Name.as_str(), Jql::naming_regx);
-                b.req(Types::Key.as_str(), Jql::naming_regx);
-                b.req(Types::Fields.as_str(), |p| {
+                // a field name or a list of them, checked below
+                b.req_defined(Types::Key.as_str());
+                // `schemaless` collections may leave them out, checked below
+                b.opt(Types::Fields.as_str(), |p| {
                     p.coerce(array());
                     p.nest(|n| {
                         n.req(Types::Name.as_str(), Jql::naming_regx);
                         n.req(Types::Declare.as_str(), Jql::type_declaration);
+                        n.opt_typed(Types::Optional.as_str(), boolean());
+                        n.opt_typed(Types::Min.as_str(), f64());
+                        n.opt_typed(Types::Max.as_str(), f64());
+                        n.opt_typed(Types::MinLen.as_str(), u64());
+                        n.opt_typed(Types::MaxLen.as_str(), u64());
+                        n.opt_typed(Types::Pattern.as_str(), string());
+                        n.opt_typed(Types::Enum.as_str(), array());
+                        n.opt_typed(Types::Fields.as_str(), array());
                     });
                 });
                 b.opt_typed(Types::InMemory.as_str(), boolean());
+                b.opt_typed(Types::Ttl.as_str(), u64());
+                b.opt(Types::ExpireField.as_str(), Jql::naming_regx);
+                b.opt_typed(Types::Storage.as_str(), object());
+                b.opt(Types::KeyStrategy.as_str(), |p| {
+                    p.coerce(string());
+                    p.allow_values(&[
+                        KeyStrategy::Client.as_str(),
+                        KeyStrategy::Uuid.as_str(),
+                        KeyStrategy::Ulid.as_str(),
+                        KeyStrategy::Sequence.as_str()
+                    ]);
+                });
+                b.opt(Types::Mode.as_str(), |p| {
+                    p.coerce(string());
+                    p.allow_values(&[
+                        SchemaMode::Strict.as_str(),
+                        SchemaMode::Flexible.as_str(),
+                        SchemaMode::Schemaless.as_str()
+                    ]);
+                });
             })
         }
     }
@@ -41,15 +74,30 @@ impl JqlSchemaParser for Jql {
             let mut v = values.unwrap();
             let state = self.prs.process(&mut v, None);
             return if state.is_strictly_valid() {
-                let props = v.as_object().unwrap();
-                for o in props {
-                    if o.0.eq(Types::Fields.as_str()) {
-                        let fields = o.1.as_array().unwrap();
-                        if fields.len() == 0 {
-                            let err = JqlError::NoFieldsProvided(v.to_string());
-                            error!("{}",err);
-                            return Err(err);
-                        }
+                let schema = serde_json::from_value::<JqlSchema>(v.clone());
+                if schema.is_err() {
+                    let err = JqlError::SchemaPropertyTypeError(schema.err().unwrap().to_string());
+                    error!("{}",err);
+                    return Err(err);
+                }
+                let schema = schema.unwrap();
+                if schema._fields.is_empty() && schema.mode() != SchemaMode::Schemaless {
+                    let err = JqlError::NoFieldsProvided(v.to_string());
+                    error!("{}",err);
+                    return Err(err);
+                }
+                let chk = Jql::check_key(&schema)
+                    .and_then(|_| Jql::check_expiry(&schema))
+                    .and_then(|_| Jql::check_storage(&schema));
+                if chk.is_err() {
+                    error!("{}",chk.as_ref().err().unwrap());
+                    return chk;
+                }
+                for field in schema._fields.iter() {
+                    let chk = Jql::check_field(field);
+                    if chk.is_err() {
+                        error!("{}",chk.as_ref().err().unwrap());
+                        return chk;
                     }
                 }
                 Ok(())
@@ -66,6 +114,149 @@ impl JqlSchemaParser for Jql {
     }
 }
 
+impl Jql {
+    ///
+    // compound keys need every part declared as something the key encoding orders, and can't be generated
+    ///
+    fn check_key(schema: &JqlSchema) -> Result<(), JqlError> {
+        let err = |msg: String| Err(JqlError::SchemaPropertyTypeError(format!("`{}` {}", Types::Key.as_str(), msg)));
+        let fields = schema._key.fields();
+        if fields.is_empty() {
+            return err("needs at least one field".to_string());
+        }
+        for name in fields.iter() {
+            if is_naming_ok(&Some(name.to_string())).is_none() {
+                return err(format!("`{}` is not a valid field name", name));
+            }
+        }
+        let strategy = schema.key_strategy();
+        if schema._key.is_compound() {
+            if strategy != KeyStrategy::Client {
+                return err(format!("compound keys can't use the `{}` key strategy", strategy));
+            }
+            for name in fields.iter() {
+                let declare = schema._fields.iter().find(|f| f._name.eq(name));
+                let orderable = declare.map_or(false, |f| {
+                    f._optional != Some(true) && matches!(DataTypes::from(f._declare.clone()),
+                        DataTypes::Int | DataTypes::Uint | DataTypes::Float | DataTypes::String | DataTypes::Boolean |
+                        DataTypes::Date | DataTypes::DateTime | DataTypes::Time | DataTypes::Uuid)
+                });
+                if !orderable {
+                    return err(format!("part `{}` has to be a required int, uint, float, string, boolean, date, datetime, time or uuid field", name));
+                }
+            }
+            return Ok(());
+        }
+        let key_field = schema._fields.iter().find(|f| f._name.eq(fields[0]));
+        if key_field.is_some() && !strategy.fits(&DataTypes::from(key_field.unwrap()._declare.clone())) {
+            return err(format!("`{}` key strategy doesn't fit `{}` declared as `{}`", strategy, schema._key, key_field.unwrap()._declare));
+        }
+        Ok(())
+    }
+
+    ///
+    // the expire field holds unix seconds or a datetime, an undeclared one is read the same way
+    ///
+    fn check_expiry(schema: &JqlSchema) -> Result<(), JqlError> {
+        if schema._ttl == Some(0) {
+            return Err(JqlError::SchemaPropertyTypeError(format!("`{}` has to be at least 1 second", Types::Ttl.as_str())));
+        }
+        let field = schema._expire_field
+            .as_ref()
+            .and_then(|name| schema._fields.iter().find(|f| f._name.eq(name)));
+        if field.is_some() && !matches!(DataTypes::from(field.unwrap()._declare.clone()), DataTypes::Uint | DataTypes::DateTime) {
+            return Err(JqlError::SchemaPropertyTypeError(format!("`{}` `{}` has to be declared as uint or datetime", Types::ExpireField.as_str(), field.unwrap()._name)));
+        }
+        Ok(())
+    }
+
+    fn check_storage(schema: &JqlSchema) -> Result<(), JqlError> {
+        let checked = schema._storage.as_ref().map_or(Ok(()), |storage| storage.check());
+        if checked.is_err() {
+            return Err(JqlError::SchemaPropertyTypeError(format!("`{}` {}", Types::Storage.as_str(), checked.err().unwrap())));
+        }
+        Ok(())
+    }
+
+    ///
+    // constraints have to fit the declaration and each other, and a `_default` has to pass them
+    ///
+    pub fn check_field(field: &JqlSchemaFields) -> Result<(), JqlError> {
+        Jql::check_field_at(field, field._name.as_str())
+    }
+
+    fn check_field_at(field: &JqlSchemaFields, path: &str) -> Result<(), JqlError> {
+        let declare = DataTypes::from(field._declare.clone());
+        let numeric = matches!(declare, DataTypes::Int | DataTypes::Uint | DataTypes::Float);
+        let text = matches!(declare, DataTypes::String);
+        let nesting = matches!(declare, DataTypes::Object | DataTypes::ArrayOfObject);
+        let err = |msg: String| Err(JqlError::SchemaPropertyTypeError(format!("`{}` {}", path, msg)));
+
+        if is_naming_ok(&Some(field._name.clone())).is_none() {
+            return err("is not a valid field name".to_string());
+        }
+        if !declare.to_string().eq(&field._declare) {
+            return err(format!("`{}` is not a known declaration", field._declare));
+        }
+        if declare == DataTypes::Blob && path.contains('.') {
+            return err("is `blob`, attachments can only be top level fields".to_string());
+        }
+        if nesting && field._fields.as_ref().map_or(true, |f| f.is_empty()) {
+            return err(format!("is `{}` and needs its own `{}`", declare, Types::Fields.as_str()));
+        }
+        if !nesting && field._fields.is_some() {
+            return err(format!("is `{}`, only object and object[] take `{}`", declare, Types::Fields.as_str()));
+        }
+        if nesting {
+            for nested in field._fields.as_ref().unwrap() {
+                let chk = Jql::check_field_at(nested, format!("{}.{}", path, nested._name).as_str());
+                if chk.is_err() {
+                    return chk;
+                }
+            }
+        }
+
+        if (field._min.is_some() || field._max.is_some()) && !numeric {
+            return err(format!("is `{}`, `{}`/`{}` only apply to int, uint and float", declare, Types::Min.as_str(), Types::Max.as_str()));
+        }
+        if field._min.is_some() && field._max.is_some() && field._min.unwrap() > field._max.unwrap() {
+            return err(format!("`{}` is greater than `{}`", Types::Min.as_str(), Types::Max.as_str()));
+        }
+        if (field._min_len.is_some() || field._max_len.is_some() || field._pattern.is_some()) && !text {
+            return err(format!("is `{}`, `{}`/`{}`/`{}` only apply to string", declare, Types::MinLen.as_str(), Types::MaxLen.as_str(), Types::Pattern.as_str()));
+        }
+        if field._min_len.is_some() && field._max_len.is_some() && field._min_len.unwrap() > field._max_len.unwrap() {
+            return err(format!("`{}` is greater than `{}`", Types::MinLen.as_str(), Types::MaxLen.as_str()));
+        }
+        if field._pattern.is_some() {
+            let regx = Regex::new(field._pattern.as_ref().unwrap().as_str());
+            if regx.is_err() {
+                return err(format!("`{}` is not a valid regex {}", Types::Pattern.as_str(), regx.err().unwrap()));
+            }
+        }
+        if field._enum.is_some() {
+            if !numeric && !text {
+                return err(format!("is `{}`, `{}` only applies to int, uint, float and string", declare, Types::Enum.as_str()));
+            }
+            if field._enum.as_ref().unwrap().is_empty() {
+                return err(format!("`{}` needs at least one value", Types::Enum.as_str()));
+            }
+        }
+        if field._default.is_some() {
+            let mut single = JqlSchema::builder(field._name.as_str(), field._name.as_str()).build();
+            single._fields.push(field.clone());
+            let parser = Jql::new_value_parser(serde_json::to_string(&single).unwrap());
+            let mut doc = Map::new();
+            doc.insert(field._name.clone(), field._default.clone().unwrap());
+            let chk = parser.parse_value_with_json(Ok(Value::Object(doc)));
+            if chk.is_err() {
+                return err(format!("`{}` is not valid {}", Types::Default.as_str(), chk.err().unwrap()));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Instant;
@@ -92,4 +283,58 @@ mod tests {
         assert!(!res.is_err(), "{}", res.err().unwrap());
         // println!("schema {:?}",elp.elapsed());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn schema_constraints() {
+        let jql = Jql::new_schema_parser();
+        let misplaced = r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[ { "_name":"hello", "_declare":"string", "_min":1 } ]
+            }
+        "#;
+        assert!(jql.parse_schema(misplaced.to_string()).is_err());
+
+        let bad_default = r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[ { "_name":"state", "_declare":"string", "_enum":["open","done"], "_default":"lost" } ]
+            }
+        "#;
+        assert!(jql.parse_schema(bad_default.to_string()).is_err());
+    }
+
+    #[test]
+    fn schema_modes() {
+        let jql = Jql::new_schema_parser();
+        let schemaless = r#"{ "_name":"events", "_key":"ids", "_mode":"schemaless" }"#;
+        assert!(jql.parse_schema(schemaless.to_string()).is_ok());
+
+        let strict = r#"{ "_name":"events", "_key":"ids", "_mode":"strict" }"#;
+        assert!(jql.parse_schema(strict.to_string()).is_err());
+
+        let unknown = r#"{ "_name":"events", "_key":"ids", "_mode":"loose", "_fields":[ { "_name":"hello", "_declare":"string" } ] }"#;
+        assert!(jql.parse_schema(unknown.to_string()).is_err());
+    }
+
+    #[test]
+    fn schema_compound_key() {
+        let jql = Jql::new_schema_parser();
+        let readings = r#"
+            {
+                "_name":"readings",
+                "_key":["device","ts"],
+                "_fields":[ { "_name":"device", "_declare":"string" }, { "_name":"ts", "_declare":"int" } ]
+            }
+        "#;
+        assert!(jql.parse_schema(readings.to_string()).is_ok());
+
+        let undeclared = r#"{ "_name":"readings", "_key":["device","ts"], "_fields":[ { "_name":"device", "_declare":"string" } ] }"#;
+        assert!(jql.parse_schema(undeclared.to_string()).is_err());
+
+        let generated = r#"{ "_name":"readings", "_key":["device","ts"], "_key_strategy":"ulid", "_fields":[ { "_name":"device", "_declare":"string" }, { "_name":"ts", "_declare":"int" } ] }"#;
+        assert!(jql.parse_schema(generated.to_string()).is_err());
+    }
+}
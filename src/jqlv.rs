@@ -1,8 +1,12 @@
+use fancy_regex::Regex;
+use itertools::Itertools;
 use serde_json::Value;
 use log::{error};
-use valico::json_dsl::{array_of, boolean, Builder, f64, i64, string, u64};
+use valico::common::error::ValicoErrors;
+use valico::json_dsl::{array_of, boolean, Builder, f64, i64, object, Param, string, u64};
+use valico::json_dsl::errors::WrongValue;
 use crate::err::JqlError;
-use crate::hdrs::{DataTypes, Jql, JqlSchema};
+use crate::hdrs::{DataTypes, Jql, JqlSchema, JqlSchemaFields, SchemaMode, Types};
 
 pub trait JqlValueParser {
     fn new_value_parser(base: String) -> Self;
@@ -15,38 +19,13 @@ pub trait JqlValueParser {
 impl JqlValueParser for Jql {
     fn new_value_parser(base: String) -> Self {
         let schema: JqlSchema = serde_json::from_str(base.as_str()).unwrap();
-        let builder = Builder::build(|f| {
-            for pair in schema._fields {
-                let nv = pair._name;
-                let cr = match DataTypes::from(pair._declare.clone()) {
-                    DataTypes::Int => i64(),
-                    DataTypes::Uint => u64(),
-                    DataTypes::Float => f64(),
-                    DataTypes::String => string(),
-                    DataTypes::Boolean => boolean(),
-                    DataTypes::DateTime => string(),
-                    DataTypes::Date => string(),
-                    DataTypes::Time => string(),
-                    DataTypes::ArrayOfString => array_of(string()),
-                    DataTypes::ArrayOfInt => array_of(i64()),
-                    DataTypes::ArrayOfUint => array_of(u64()),
-                    DataTypes::ArrayOfFloat => array_of(f64()),
-                    DataTypes::ArrayOfBoolean => array_of(boolean())
-                };
-                f.req(nv.as_str(), |p| {
-                    p.coerce(cr);
-                    if DataTypes::from(pair._declare.clone()) == DataTypes::DateTime {
-                        Jql::declare_datetime(p);
-                    }
-                    if DataTypes::from(pair._declare.clone()) == DataTypes::Date {
-                        Jql::declare_date(p);
-                    }
-                    if DataTypes::from(pair._declare.clone()) == DataTypes::Time {
-                        Jql::declare_time(p);
-                    }
-                });
-            }
-        });
+        let strict = schema.mode() == SchemaMode::Strict;
+        // the key and the `_expire_field` may be left out of `_fields`, and stamps of ours come back on
+        // exported documents
+        let mut reserved = schema._key.fields().iter().map(|k| k.to_string()).collect::<Vec<String>>();
+        reserved.extend(schema._expire_field.clone());
+        reserved.extend([Types::Version, Types::ExpiresAt, Types::Ttl].iter().map(|t| t.as_str().to_string()));
+        let builder = Builder::build(|f| Jql::declare_fields(f, schema._fields, strict, reserved));
         Self {
             prs: builder
         }
@@ -64,7 +43,7 @@ impl JqlValueParser for Jql {
             return if state.is_strictly_valid() {
                 Ok(())
             } else {
-                let err = JqlError::DocumentPropertyDataTypeError(format!("{:?}", state));
+                let err = JqlError::DocumentPropertyDataTypeError(describe(&state.errors));
                 error!("{}",err);
                 Err(err)
             };
@@ -76,6 +55,173 @@ impl JqlValueParser for Jql {
     }
 }
 
+impl Jql {
+    ///
+    // `object` and `object[]` declare their own `_fields`, which are checked the same way one level down
+    ///
+    fn declare_fields(f: &mut Builder, fields: Vec<JqlSchemaFields>, strict: bool, reserved: Vec<String>) {
+        if strict {
+            let mut declared = fields.iter().map(|f| f._name.clone()).collect::<Vec<String>>();
+            declared.extend(reserved);
+            f.validate_with(move |v, path| {
+                let unknown = v
+                    .as_object()
+                    .map_or(vec![], |o| o.keys().filter(|k| !declared.contains(k)).cloned().collect::<Vec<String>>());
+                if unknown.is_empty() {
+                    return Ok(());
+                }
+                let errors: ValicoErrors = unknown
+                    .into_iter()
+                    .flat_map(|k| wrong_value(format!("{}/{}", path.trim_end_matches('/'), k).as_str(), "is not declared in a strict collection".to_string()))
+                    .collect();
+                Err(errors)
+            });
+        }
+        for pair in fields {
+            let nv = pair._name.clone();
+            let declare = DataTypes::from(pair._declare.clone());
+            let cr = match declare {
+                DataTypes::Int => Some(i64()),
+                DataTypes::Uint => Some(u64()),
+                DataTypes::Float => Some(f64()),
+                DataTypes::String => Some(string()),
+                DataTypes::Boolean => Some(boolean()),
+                DataTypes::DateTime => Some(string()),
+                DataTypes::Date => Some(string()),
+                DataTypes::Time => Some(string()),
+                DataTypes::Object => Some(object()),
+                DataTypes::ArrayOfString => Some(array_of(string())),
+                DataTypes::ArrayOfInt => Some(array_of(i64())),
+                DataTypes::ArrayOfUint => Some(array_of(u64())),
+                DataTypes::ArrayOfFloat => Some(array_of(f64())),
+                DataTypes::ArrayOfBoolean => Some(array_of(boolean())),
+                DataTypes::ArrayOfObject => Some(array_of(object())),
+                DataTypes::Decimal => Some(string()),
+                DataTypes::Uuid => Some(string()),
+                DataTypes::Bytes => Some(string()),
+                DataTypes::GeoPoint => Some(object()),
+                DataTypes::Blob => Some(object()),
+                // anything goes
+                DataTypes::Json => None
+            };
+            let optional = pair._optional.unwrap_or(false);
+            let rules = |p: &mut Param| {
+                if cr.is_some() {
+                    p.coerce(cr.unwrap());
+                }
+                if optional {
+                    p.allow_null();
+                }
+                match declare {
+                    DataTypes::DateTime => Jql::declare_datetime(p),
+                    DataTypes::Date => Jql::declare_date(p),
+                    DataTypes::Time => Jql::declare_time(p),
+                    DataTypes::Decimal => Jql::declare_decimal(p),
+                    DataTypes::Uuid => Jql::declare_uuid(p),
+                    DataTypes::Bytes => Jql::declare_bytes(p),
+                    DataTypes::GeoPoint => Jql::declare_geopoint(p),
+                    DataTypes::Blob => Jql::declare_blob(p),
+                    _ => {}
+                }
+                if pair._fields.is_some() {
+                    // a nest applies to every item when the value is an array
+                    let nested = pair._fields.clone().unwrap();
+                    p.nest(|n| Jql::declare_fields(n, nested, strict, vec![]));
+                }
+                Jql::constraints(p, &pair);
+            };
+            // a field with a `_default` is filled in before validation, see `JqlSchema::with_defaults`
+            if optional || pair._default.is_some() {
+                f.opt(nv.as_str(), rules);
+            } else {
+                f.req(nv.as_str(), rules);
+            }
+        }
+    }
+
+    ///
+    // `_min`/`_max`, `_min_len`/`_max_len`, `_pattern` and `_enum`, `JqlSchemaParser` already checked they fit the declaration
+    ///
+    pub fn constraints(p: &mut Param, field: &JqlSchemaFields) {
+        if field._min.is_some() {
+            let min = field._min.unwrap();
+            p.validate_with(move |v, path| match v.as_f64() {
+                Some(n) if n < min => Err(wrong_value(path, format!("has to be at least {}", min))),
+                _ => Ok(())
+            });
+        }
+        if field._max.is_some() {
+            let max = field._max.unwrap();
+            p.validate_with(move |v, path| match v.as_f64() {
+                Some(n) if n > max => Err(wrong_value(path, format!("has to be at most {}", max))),
+                _ => Ok(())
+            });
+        }
+        if field._min_len.is_some() {
+            let min_len = field._min_len.unwrap();
+            p.validate_with(move |v, path| match v.as_str() {
+                Some(s) if (s.chars().count() as u64) < min_len => Err(wrong_value(path, format!("has to be at least {} characters long", min_len))),
+                _ => Ok(())
+            });
+        }
+        if field._max_len.is_some() {
+            let max_len = field._max_len.unwrap();
+            p.validate_with(move |v, path| match v.as_str() {
+                Some(s) if (s.chars().count() as u64) > max_len => Err(wrong_value(path, format!("has to be at most {} characters long", max_len))),
+                _ => Ok(())
+            });
+        }
+        if field._pattern.is_some() {
+            let pattern = field._pattern.clone().unwrap();
+            let regx = Regex::new(pattern.as_str());
+            if regx.is_ok() {
+                let regx = regx.unwrap();
+                p.validate_with(move |v, path| match v.as_str() {
+                    Some(s) if !regx.is_match(s).unwrap_or(false) => Err(wrong_value(path, format!("has to match `{}`", pattern))),
+                    _ => Ok(())
+                });
+            }
+        }
+        if field._enum.is_some() {
+            let allowed = field._enum.clone().unwrap();
+            p.validate_with(move |v, path| {
+                if v.is_null() || allowed.iter().any(|a| is_same_value(a, v)) {
+                    return Ok(());
+                }
+                Err(wrong_value(path, format!("has to be one of {}", Value::Array(allowed.clone()))))
+            });
+        }
+    }
+}
+
+///
+// numbers by their value, so `1` allows `1.0` in a float field
+///
+fn is_same_value(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b
+    }
+}
+
+fn wrong_value(path: &str, detail: String) -> ValicoErrors {
+    vec![Box::new(WrongValue { path: path.to_string(), detail: Some(detail) })]
+}
+
+///
+// "`age` has to be at least 0, `address.city` this field is required"
+///
+fn describe(errors: &ValicoErrors) -> String {
+    errors
+        .iter()
+        .map(|e| {
+            let field = e.get_path().trim_start_matches('/').replace('/', ".");
+            let detail = e.get_detail().map_or(e.get_title().to_lowercase(), |d| d.to_string());
+            format!("`{}` {}", field, detail)
+        })
+        .join(", ")
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Instant;
@@ -109,4 +255,150 @@ mod tests {
         }
         // println!("schema {:?}",elp.elapsed());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn constraints_check() {
+        let schema = r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"ids", "_declare":"string", "_pattern":"^[a-z]\\d+$" },
+                    { "_name":"age", "_declare":"int", "_min":0, "_max":150 },
+                    { "_name":"nick", "_declare":"string", "_optional":true, "_max_len":8 },
+                    { "_name":"state", "_declare":"string", "_default":"open", "_enum":["open","done"] }
+                ]
+            }
+        "#;
+        let jql = Jql::new_value_parser(schema.to_string());
+        assert!(jql.parse_value(r#"{ "ids":"a1", "age":30, "state":"done" }"#.to_string()).is_ok());
+        assert!(jql.parse_value(r#"{ "ids":"a1", "age":30, "nick":null }"#.to_string()).is_ok());
+
+        let res = jql.parse_value(r#"{ "ids":"a1", "age":-1 }"#.to_string());
+        assert!(res.err().unwrap().to_string().contains("`age` has to be at least 0"));
+        let res = jql.parse_value(r#"{ "ids":"A1", "age":1, "nick":"far too long" }"#.to_string());
+        let msg = res.err().unwrap().to_string();
+        assert!(msg.contains("`ids` has to match"), "{}", msg);
+        assert!(msg.contains("`nick` has to be at most 8 characters long"), "{}", msg);
+        let res = jql.parse_value(r#"{ "ids":"a1", "age":1, "state":"lost" }"#.to_string());
+        assert!(res.err().unwrap().to_string().contains("`state` has to be one of"));
+        assert!(jql.parse_value(r#"{ "ids":"a1" }"#.to_string()).is_err());
+
+        let rated = Jql::new_value_parser(r#"
+            { "_name":"rating", "_key":"ids", "_fields":[ { "_name":"stars", "_declare":"float", "_enum":[1, 2, 2.5] } ] }
+        "#.to_string());
+        assert!(rated.parse_value(r#"{ "ids":"r1", "stars":1.0 }"#.to_string()).is_ok());
+        assert!(rated.parse_value(r#"{ "ids":"r1", "stars":2.5 }"#.to_string()).is_ok());
+        assert!(rated.parse_value(r#"{ "ids":"r1", "stars":1.5 }"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn nested_check() {
+        let schema = r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"ids", "_declare":"string" },
+                    { "_name":"address", "_declare":"object", "_fields":[
+                        { "_name":"city", "_declare":"string" },
+                        { "_name":"zip", "_declare":"string", "_optional":true }
+                    ] },
+                    { "_name":"items", "_declare":"object[]", "_fields":[
+                        { "_name":"sku", "_declare":"string" },
+                        { "_name":"qty", "_declare":"uint", "_min":1 }
+                    ] }
+                ]
+            }
+        "#;
+        let jql = Jql::new_value_parser(schema.to_string());
+        let ok = r#"{ "ids":"a1", "address":{ "city":"Oslo" }, "items":[{ "sku":"x1", "qty":2 }] }"#;
+        assert!(jql.parse_value(ok.to_string()).is_ok());
+
+        let bad = r#"{ "ids":"a1", "address":{ "zip":"0150" }, "items":[{ "sku":"x1", "qty":0 }] }"#;
+        let msg = jql.parse_value(bad.to_string()).err().unwrap().to_string();
+        assert!(msg.contains("`address.city`"), "{}", msg);
+        assert!(msg.contains("`items.0.qty` has to be at least 1"), "{}", msg);
+    }
+
+    #[test]
+    fn extended_types_check() {
+        let schema = r#"
+            {
+                "_name":"payment",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"ids", "_declare":"uuid" },
+                    { "_name":"amount", "_declare":"decimal" },
+                    { "_name":"receipt", "_declare":"bytes" },
+                    { "_name":"at", "_declare":"geopoint" },
+                    { "_name":"extra", "_declare":"json" },
+                    { "_name":"scan", "_declare":"blob", "_optional":true }
+                ]
+            }
+        "#;
+        let jql = Jql::new_value_parser(schema.to_string());
+        let ok = r#"{
+            "ids":"67e55044-10b1-426f-9247-bb680e5fe0c8",
+            "amount":"-12.50",
+            "receipt":"aGVsbG8=",
+            "at":{ "lat":59.91, "lon":10.75 },
+            "extra":[1, { "any":"thing" }],
+            "scan":{ "_bucket":"receipts", "_key":"2023/r1.pdf" }
+        }"#;
+        assert!(jql.parse_value(ok.to_string()).is_ok());
+
+        let bad = r#"{
+            "ids":"not-a-uuid",
+            "amount":"12,50",
+            "receipt":"***",
+            "at":{ "lat":91, "lon":10.75 },
+            "extra":null,
+            "scan":{ "_bucket":"receipts" }
+        }"#;
+        let msg = jql.parse_value(bad.to_string()).err().unwrap().to_string();
+        for field in ["`ids`", "`amount`", "`receipt` has to be base64", "`at` has to be", "`scan` has to be"] {
+            assert!(msg.contains(field), "{} {}", field, msg);
+        }
+    }
+
+    #[test]
+    fn strict_mode_check() {
+        let schema = r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_mode":"strict",
+                "_fields":[
+                    { "_name":"ids", "_declare":"string" },
+                    { "_name":"address", "_declare":"object", "_fields":[ { "_name":"city", "_declare":"string" } ] }
+                ]
+            }
+        "#;
+        let jql = Jql::new_value_parser(schema.to_string());
+        assert!(jql.parse_value(r#"{ "ids":"a1", "address":{ "city":"Oslo" } }"#.to_string()).is_ok());
+        let msg = jql.parse_value(r#"{ "ids":"a1", "extra":1, "address":{ "city":"Oslo", "zip":"0150" } }"#.to_string())
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(msg.contains("`extra` is not declared"), "{}", msg);
+        assert!(msg.contains("`address.zip` is not declared"), "{}", msg);
+
+        let flexible = Jql::new_value_parser(schema.replace("strict", "flexible"));
+        assert!(flexible.parse_value(r#"{ "ids":"a1", "extra":1, "address":{ "city":"Oslo" } }"#.to_string()).is_ok());
+
+        // the key and our stamps don't have to be declared, they still can't appear nested
+        let undeclared_key = Jql::new_value_parser(r#"
+            { "_name":"readings", "_key":["device","ts"], "_mode":"strict", "_ttl":60, "_fields":[ { "_name":"value", "_declare":"float" } ] }
+        "#.to_string());
+        assert!(undeclared_key.parse_value(r#"{ "device":"d1", "ts":1, "value":0.5, "_version":1, "_expires_at":100 }"#.to_string()).is_ok());
+        assert!(undeclared_key.parse_value(r#"{ "device":"d1", "ts":1, "value":0.5, "extra":1 }"#.to_string()).is_err());
+
+        let undeclared_expiry = Jql::new_value_parser(r#"
+            { "_name":"sessions", "_key":"ids", "_mode":"strict", "_expire_field":"valid_until", "_fields":[ { "_name":"user", "_declare":"string" } ] }
+        "#.to_string());
+        assert!(undeclared_expiry.parse_value(r#"{ "ids":"s1", "user":"moe", "valid_until":1700000000 }"#.to_string()).is_ok());
+        assert!(undeclared_expiry.parse_value(r#"{ "ids":"s1", "user":"moe", "valid_for":60 }"#.to_string()).is_err());
+        assert!(jql.parse_value(r#"{ "ids":"a1", "address":{ "city":"Oslo", "ids":"a2" } }"#.to_string()).is_err());
+    }
+}
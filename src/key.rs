@@ -0,0 +1,337 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde_json::Value;
+use uuid::Uuid;
+use crate::err::TrxError;
+use crate::func::unique_id;
+use crate::doc::key_to_string;
+use crate::hdrs::{DataTypes, Exec, JqlSchema, KeyStrategy, SchemaKey, TKey};
+use crate::tup::{pack, pack_names, unpack};
+use crate::var::{DB_SYS, SEQ_PREFIX};
+
+/// !```
+/// "_key_strategy": "client"     the document brings its own key (default)
+/// "_key_strategy": "uuid"       a missing key becomes `unique_id()`
+/// "_key_strategy": "ulid"       a missing key becomes a 26 char id that sorts by insertion time
+/// "_key_strategy": "sequence"   a missing key becomes the next number of the collection, starting at 1
+/// !```
+
+impl Display for KeyStrategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for KeyStrategy {
+    fn from(value: &str) -> Self {
+        match value {
+            "uuid" => KeyStrategy::Uuid,
+            "ulid" => KeyStrategy::Ulid,
+            "sequence" => KeyStrategy::Sequence,
+            _ => KeyStrategy::Client
+        }
+    }
+}
+
+impl KeyStrategy {
+    pub fn as_str(&self) -> &str {
+        match self {
+            KeyStrategy::Client => "client",
+            KeyStrategy::Uuid => "uuid",
+            KeyStrategy::Ulid => "ulid",
+            KeyStrategy::Sequence => "sequence"
+        }
+    }
+
+    ///
+    // declarations the generated key fits in, an undeclared key field takes anything
+    ///
+    pub fn fits(&self, declare: &DataTypes) -> bool {
+        match self {
+            KeyStrategy::Client => true,
+            KeyStrategy::Uuid | KeyStrategy::Ulid => matches!(declare, DataTypes::String),
+            KeyStrategy::Sequence => matches!(declare, DataTypes::Int | DataTypes::Uint)
+        }
+    }
+}
+
+impl PartialEq for KeyStrategy {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
+
+impl SchemaKey {
+    pub fn fields(&self) -> Vec<&str> {
+        match self {
+            SchemaKey::Field(f) => vec![f.as_str()],
+            SchemaKey::Fields(fs) => fs.iter().map(|f| f.as_str()).collect()
+        }
+    }
+
+    pub fn is_compound(&self) -> bool {
+        matches!(self, SchemaKey::Fields(_))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields().contains(&name)
+    }
+}
+
+impl Display for SchemaKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.fields().join(", "))
+    }
+}
+
+impl PartialEq<&str> for SchemaKey {
+    fn eq(&self, other: &&str) -> bool {
+        !self.is_compound() && self.fields()[0].eq(*other)
+    }
+}
+
+impl JqlSchema {
+    pub fn key_strategy(&self) -> KeyStrategy {
+        KeyStrategy::from(self._key_strategy.as_deref().unwrap_or_default())
+    }
+
+    ///
+    // the key fields of a document in key order, only strings, numbers and booleans make a key
+    ///
+    pub fn key_parts(&self, doc: &Value) -> Option<Vec<Value>> {
+        self._key
+            .fields()
+            .into_iter()
+            .map(|f| doc.get(f).filter(|v| v.is_string() || v.is_number() || v.is_boolean()).cloned())
+            .collect()
+    }
+
+    ///
+    // storage key of (a leading part of) the key parts, packed by their declared type. An `_id` of "42"
+    // finds the int key 42. Undeclared keys pack by what they hold, numbers sort as numbers, and text
+    // that reads back as the same number or boolean is taken as one, so 42 and "42" stay the same key.
+    // Integers and fractions carry different tags, all integers sort before any fraction
+    ///
+    pub fn encode_key(&self, parts: &[Value]) -> Option<TKey> {
+        let fields = self._key.fields();
+        if parts.len() > fields.len() {
+            return None;
+        }
+        let declared = fields
+            .iter()
+            .zip(parts.iter())
+            .map(|(f, v)| {
+                let declare = self.declare_of(f);
+                let value = coerce_part(&declare, v);
+                (declare, value)
+            })
+            .collect::<Vec<_>>();
+        pack(&declared.iter().map(|(d, v)| (d.clone(), v)).collect::<Vec<_>>())
+    }
+
+    ///
+    // what clients see of a stored key, compound keys come back as their JSON array `["dev1",42]`
+    ///
+    pub fn decode_key(&self, key: &[u8]) -> String {
+        let parts = unpack(key).unwrap_or_default();
+        match self._key.is_compound() {
+            true => Value::Array(parts).to_string(),
+            false => parts.first().and_then(key_to_string).unwrap_or_default()
+        }
+    }
+}
+
+fn coerce_part(declare: &Option<DataTypes>, part: &Value) -> Value {
+    match (declare, part) {
+        (Some(DataTypes::Int | DataTypes::Uint | DataTypes::Float), Value::String(s)) => {
+            serde_json::from_str::<serde_json::Number>(s).map_or(part.clone(), Value::Number)
+        }
+        (Some(DataTypes::Boolean), Value::String(s)) => s.parse::<bool>().map_or(part.clone(), Value::Bool),
+        (Some(DataTypes::String | DataTypes::Uuid), Value::Number(_) | Value::Bool(_)) => {
+            Value::String(key_to_string(part).unwrap())
+        }
+        // "007" or "1e3" would come back as another text, they stay text
+        (None, Value::String(s)) => serde_json::from_str::<Value>(s)
+            .ok()
+            .filter(|v| (v.is_number() || v.is_boolean()) && key_to_string(v).as_deref() == Some(s.as_str()))
+            .unwrap_or(part.clone()),
+        _ => part.clone()
+    }
+}
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+static LAST_ULID: Mutex<(u64, u128)> = Mutex::new((0, 0));
+
+///
+/// 48 bits of unix millis and 80 random bits in Crockford base32. Ids made in the same
+/// millisecond (or while the clock steps back) continue from the previous one, so they stay ordered
+///
+pub fn time_ordered_id() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let mask = (1u128 << 80) - 1;
+    let mut last = LAST_ULID.lock().unwrap();
+    let (ms, rand) = if now > last.0 {
+        (now, Uuid::new_v4().as_u128() & mask)
+    } else {
+        (last.0, (last.1 + 1) & mask)
+    };
+    *last = (ms, rand);
+    let id = ((ms as u128) << 80) | rand;
+    (0..26)
+        .map(|i| CROCKFORD[((id >> (125 - 5 * i)) & 31) as usize] as char)
+        .collect()
+}
+
+impl Exec {
+    ///
+    // key for a document that came without one, `None` when the collection expects clients to bring it
+    ///
+    pub fn new_key(&self, schema: &JqlSchema, db: &str, col: &str) -> Result<Option<Value>, TrxError> {
+        match schema.key_strategy() {
+            KeyStrategy::Client => Ok(None),
+            KeyStrategy::Uuid => Ok(Some(Value::String(unique_id()))),
+            KeyStrategy::Ulid => Ok(Some(Value::String(time_ordered_id()))),
+            KeyStrategy::Sequence => self.next_sequence(db, col).map(|n| Some(Value::from(n)))
+        }
+    }
+
+    ///
+    // what `new_key` would hand out, without using a sequence number up
+    ///
+    pub fn peek_key(&self, schema: &JqlSchema, db: &str, col: &str) -> Option<Value> {
+        match schema.key_strategy() {
+            KeyStrategy::Client => None,
+            KeyStrategy::Uuid => Some(Value::String(unique_id())),
+            KeyStrategy::Ulid => Some(Value::String(time_ordered_id())),
+            KeyStrategy::Sequence => {
+                let current = self.trx.get(DB_SYS, Self::sequence_key(db, col)).and_then(|v| v.as_u64());
+                Some(Value::from(current.unwrap_or(0) + 1))
+            }
+        }
+    }
+
+    fn next_sequence(&self, db: &str, col: &str) -> Result<u64, TrxError> {
+        let key = Self::sequence_key(db, col);
+        // read, add and write have to happen as one
+        let _guard = self.seq.lock().unwrap();
+        let current = self.trx
+            .get(DB_SYS, key.clone())
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        let next = current + 1;
+        let saved = self.trx.put(DB_SYS, key, serde_json::to_vec(&next).unwrap());
+        if saved.is_err() {
+            return Err(TrxError::UpsertDocumentError(saved.err().unwrap().to_string()));
+        }
+        Ok(next)
+    }
+
+    ///
+    // imported documents bring their own numbers, the sequence must not hand them out again
+    ///
+    pub fn raise_sequence(&self, db: &str, col: &str, at: u64) -> Result<(), TrxError> {
+        let key = Self::sequence_key(db, col);
+        let _guard = self.seq.lock().unwrap();
+        let current = self.trx
+            .get(DB_SYS, key.clone())
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        if current >= at {
+            return Ok(());
+        }
+        self.trx.put(DB_SYS, key, serde_json::to_vec(&at).unwrap())
+            .map_err(|e| TrxError::ImportError(e.to_string()))
+    }
+
+    pub fn sequence_key(db: &str, col: &str) -> TKey {
+        pack_names(&[SEQ_PREFIX, db, col])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ulid_order() {
+        let ids = (0..1000).map(|_| time_ordered_id()).collect::<Vec<String>>();
+        assert!(ids.iter().all(|id| id.len() == 26));
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(ids, sorted);
+        sorted.dedup();
+        assert_eq!(sorted.len(), ids.len());
+    }
+
+    #[test]
+    fn compound_keys() {
+        let schema = JqlSchema::builder("readings", "device")
+            .compound_key(&["device", "ts"])
+            .field("device", DataTypes::String)
+            .field("ts", DataTypes::Int)
+            .build();
+        let doc = serde_json::json!({ "device": "d1", "ts": 1700000000, "temp": 21.5 });
+        let parts = schema.key_parts(&doc).unwrap();
+        let key = schema.encode_key(&parts).unwrap();
+        assert!(key.starts_with(&schema.encode_key(&parts[..1]).unwrap()));
+        assert_eq!(schema.decode_key(&key), r#"["d1",1700000000]"#);
+        assert_eq!(schema.key_of(&doc).unwrap(), schema.decode_key(&key));
+        assert!(schema.key_parts(&serde_json::json!({ "device": "d1" })).is_none());
+    }
+
+    #[test]
+    fn typed_single_keys() {
+        let numbered = JqlSchema::builder("orders", "num").field("num", DataTypes::Int).build();
+        let nine = numbered.encode_key(&[serde_json::json!(9)]).unwrap();
+        let ten = numbered.encode_key(&[serde_json::json!(10)]).unwrap();
+        assert!(nine < ten);
+        assert_eq!(numbered.encode_key(&[serde_json::json!("10")]).unwrap(), ten);
+        assert_eq!(numbered.decode_key(&ten), "10");
+
+        let undeclared = JqlSchema::builder("events", "ids").build();
+        assert_eq!(undeclared.encode_key(&[serde_json::json!(42)]), undeclared.encode_key(&[serde_json::json!("42")]));
+        let nine = undeclared.encode_key(&[serde_json::json!(9)]).unwrap();
+        let ten = undeclared.encode_key(&[serde_json::json!(10)]).unwrap();
+        assert!(nine < ten);
+        assert!(undeclared.encode_key(&[serde_json::json!(-3)]).unwrap() < nine);
+        assert!(undeclared.encode_key(&[serde_json::json!(1.5)]).unwrap() < undeclared.encode_key(&[serde_json::json!(2.5)]).unwrap());
+        assert_eq!(undeclared.decode_key(&ten), "10");
+        assert_eq!(undeclared.encode_key(&[serde_json::json!(true)]), undeclared.encode_key(&[serde_json::json!("true")]));
+        for text in ["007", "1e3", "ten"] {
+            let key = undeclared.encode_key(&[serde_json::json!(text)]).unwrap();
+            assert_eq!(undeclared.decode_key(&key), text);
+        }
+    }
+
+    #[test]
+    fn strategy_fits() {
+        assert!(KeyStrategy::from("sequence").fits(&DataTypes::Uint));
+        assert!(!KeyStrategy::from("sequence").fits(&DataTypes::String));
+        assert!(KeyStrategy::from("ulid").fits(&DataTypes::String));
+        assert!(KeyStrategy::from("whatever") == KeyStrategy::Client);
+    }
+
+    #[test]
+    fn rejected_keep_sequence() {
+        let root = std::env::temp_dir().join(format!("moedb-key-{}", unique_id()));
+        let db = crate::hdrs::MoeDb::new(crate::env::at(root.to_str().unwrap())).unwrap();
+        for stmt in [
+            r#"{ "_action":"create-db", "_database":"shop", "_body":{} }"#,
+            r#"{ "_action":"create-collection", "_database":"shop", "_body":{ "_name":"orders", "_key":"ids", "_key_strategy":"sequence", "_fields":[ { "_name":"ids", "_declare":"uint" }, { "_name":"total", "_declare":"float" } ] } }"#,
+        ] {
+            let res = db.execute(stmt);
+            assert!(!res.error, "{}", res.message);
+        }
+        let upsert = |body: &str| db.execute(format!(r#"{{ "_action":"upsert", "_database":"shop", "_collection":"orders", "_body":{} }}"#, body).as_str());
+        assert!(upsert(r#"{ "total":"many" }"#).error);
+        assert!(upsert(r#"[ { "total":1.5 }, { "total":2.5 }, { "total":"many" } ]"#).error, "the last one fails the batch");
+        let res = upsert(r#"{ "total":3.5 }"#);
+        assert!(!res.error, "{}", res.message);
+        assert_eq!(res.data.unwrap(), vec![serde_json::json!("1")], "no number was used up");
+        drop(db);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -4,10 +4,10 @@ use crate::err::MoeDbError;
 use crate::func::unique_id;
 use crate::hdrs::MoeDb;
 
-mod err;
-mod env;
+pub mod err;
+pub mod env;
 mod var;
-mod hdrs;
+pub mod hdrs;
 mod util;
 mod jqlv;
 mod jqls;
@@ -24,16 +24,52 @@ mod doc;
 mod resp;
 mod sys;
 mod log;
+mod srv;
+mod live;
+mod wire;
+pub mod aio;
+mod stp;
+mod bld;
+pub mod mdl;
+mod mig;
+mod sch;
+mod key;
+mod tup;
+mod ttl;
+mod obj;
+mod upl;
+mod att;
+mod bak;
+mod exp;
+mod blk;
+mod tab;
+mod tune;
+pub mod sh;
 
-fn start_moedb() -> Result<MoeDb, MoeDbError> {
-    let cfg = env::env().unwrap();
+///
+// config from `env::env()`, see env.rs for where it is looked up
+///
+pub fn start_moedb() -> Result<MoeDb, MoeDbError> {
+    let cfg = env::env();
+    if cfg.is_err() {
+        return Err(MoeDbError::ConfigError(cfg.err().unwrap().to_string()));
+    }
+    start_moedb_with(cfg.unwrap())
+}
+
+pub fn start_moedb_with(cfg: env::MoeDb) -> Result<MoeDb, MoeDbError> {
     let log_file = format!("{}/{}.LOG",cfg.log_path.as_str(),unique_id());
-    CombinedLogger::init(
+    let file = File::create(log_file.as_str());
+    if file.is_err() {
+        return Err(MoeDbError::ConfigError(format!("{}: {}", log_file, file.err().unwrap())));
+    }
+    // a second db in the same process keeps the logger of the first
+    let _ = CombinedLogger::init(
         vec![
             TermLogger::new(LevelFilter::Trace, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-            WriteLogger::new(LevelFilter::Trace, Config::default(), File::create(log_file.as_str()).unwrap()),
+            WriteLogger::new(LevelFilter::Trace, Config::default(), file.unwrap()),
         ]
-    ).unwrap();
+    );
 
     MoeDb::new(cfg)
 }
@@ -43,22 +79,25 @@ mod tests {
     use std::time::Instant;
     use crate::hdrs::MoeDb;
     use super::*;
+
+    const TEST_CONFIG: &str = "tests/moedb.toml";
+
     #[test]
     pub fn open_env() {
-        let cfg = env::env();
+        let cfg = env::from_path(TEST_CONFIG);
         assert_eq!(cfg.is_err(), false, "no error reading env file");
     }
 
     #[test]
     pub fn read_moedb_path() {
-        let cfg = env::env();
+        let cfg = env::from_path(TEST_CONFIG);
         assert_eq!(cfg.unwrap().db_path.as_str().is_empty(), false, "moedb path not empty");
     }
 
     #[test]
     pub fn create_db() {
         let elp = Instant::now();
-        let db = start_moedb().unwrap();
+        let db = start_moedb_with(env::from_path(TEST_CONFIG).unwrap()).unwrap();
         let res = db.execute(r#"
             {
                 "_action":"create-db",
@@ -123,6 +162,142 @@ mod tests {
         assert!(!res.error, "{}", res.message);
         println!("col_list {:?} response {} res.error {}", elp.elapsed(), serde_json::to_string(&pr.data.unwrap()).unwrap(), res.error);
 
+        upsert_document(db);
+    }
+
+    pub fn upsert_document(db: MoeDb) {
+        let elp = Instant::now();
+        let mut feed = db.subscribe();
+        let res = db.execute(r#"
+            {
+                "_action":"upsert",
+                "_database":"random",
+                "_collection":"numbers",
+                "_body":[
+                    { "ids":"n1", "hello":["one"] },
+                    { "ids":"n2", "hello":["two"] }
+                ]
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        assert_eq!(res.data.unwrap().len(), 2);
+        assert_eq!(feed.try_recv().unwrap().key, "n1");
+        println!("upsert_document {:?} res.error {}", elp.elapsed(), res.error);
+
+        get_document(db);
+    }
+
+    pub fn get_document(db: MoeDb) {
+        let elp = Instant::now();
+        let res = db.execute(r#"
+            {
+                "_action":"get",
+                "_database":"random",
+                "_collection":"numbers",
+                "_body":{ "_filter":{ "hello":["two"] } }
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        let data = res.data.unwrap();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0]["ids"], "n2");
+        println!("get_document {:?} res.error {}", elp.elapsed(), res.error);
+
+        delete_document(db);
+    }
+
+    pub fn delete_document(db: MoeDb) {
+        let elp = Instant::now();
+        let res = db.execute(r#"
+            {
+                "_action":"delete",
+                "_database":"random",
+                "_collection":"numbers",
+                "_body":{ "_id":"n1" }
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        assert_eq!(res.data.unwrap().len(), 1);
+        println!("delete_document {:?} res.error {}", elp.elapsed(), res.error);
+
+        delete_without_key(db);
+    }
+
+    pub fn delete_without_key(db: MoeDb) {
+        let res = db.execute(r#"
+            {
+                "_action":"create-collection",
+                "_database":"random",
+                "_body":{
+                    "_name":"pairs",
+                    "_key":["a","b"],
+                    "_fields":[ { "_name":"a", "_declare":"string" }, { "_name":"b", "_declare":"int" } ]
+                }
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        let res = db.execute(r#"
+            {
+                "_action":"upsert",
+                "_database":"random",
+                "_collection":"pairs",
+                "_body":[ { "a":"x", "b":1 }, { "a":"y", "b":2 } ]
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+
+        let selectors = [
+            ("numbers", r#"{ "_id":null }"#),
+            ("numbers", r#"{ "_id":[] }"#),
+            ("pairs", r#"{ "_id":null }"#),
+            ("pairs", r#"{ "_id":[] }"#),
+            ("pairs", r#"{ "_id":"[\"x\"," }"#),
+        ];
+        for (col, body) in selectors {
+            let res = db.execute(format!(
+                r#"{{ "_action":"delete", "_database":"random", "_collection":"{}", "_body":{} }}"#, col, body
+            ).as_str());
+            assert!(res.error || res.data.as_ref().map_or(true, |d| d.is_empty()), "{} {} deleted {:?}", col, body, res.data);
+        }
+        for (col, left) in [("numbers", 1), ("pairs", 2)] {
+            let res = db.execute(format!(
+                r#"{{ "_action":"get", "_database":"random", "_collection":"{}", "_body":{{}} }}"#, col
+            ).as_str());
+            assert_eq!(res.data.unwrap().len(), left, "documents left in {}", col);
+        }
+        let res = db.execute(r#"{ "_action":"drop-collection", "_database":"random", "_collection":"pairs" }"#);
+        assert!(!res.error, "{}", res.message);
+
+        alter_collection(db);
+    }
+
+    pub fn alter_collection(db: MoeDb) {
+        let elp = Instant::now();
+        let res = db.execute(r#"
+            {
+                "_action":"alter-collection",
+                "_database":"random",
+                "_collection":"numbers",
+                "_body":{
+                    "_add":[{ "_name":"rank", "_declare":"int", "_default":0 }]
+                }
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        assert_eq!(res.data.unwrap()[0]["_version"], 2);
+
+        let res = db.execute(r#"
+            {
+                "_action":"get",
+                "_database":"random",
+                "_collection":"numbers",
+                "_body":{ "_id":"n2" }
+            }
+        "#);
+        assert!(!res.error, "{}", res.message);
+        assert_eq!(res.data.unwrap()[0]["rank"], 0);
+        println!("alter_collection {:?} res.error {}", elp.elapsed(), res.error);
+
         truncate_collection(db);
     }
 
@@ -0,0 +1,173 @@
+use std::collections::HashSet;
+use serde_json::Value;
+use crate::doc::{is_match, project};
+use crate::hdrs::{Change, ChangeKind, JqlCommand, JqlSchema, KeyScope, LiveEvent, LiveEventKind, LiveQuery, TKey, Types};
+use crate::ttl::without_expiry;
+
+impl LiveQuery {
+    ///
+    // `docs` is the initial result set before any `_project`, its keys are what the client currently holds
+    ///
+    pub fn new(id: String, cmd: &JqlCommand, schema: &JqlSchema, docs: &Vec<Value>) -> Self {
+        let body = cmd._body.clone().unwrap_or(Value::Null);
+        let keys = docs
+            .iter()
+            .filter_map(|d| schema.key_parts(d).and_then(|parts| schema.encode_key(&parts)))
+            .collect::<HashSet<TKey>>();
+        let paths = body.get(Types::Project.as_str())
+            .and_then(|p| p.as_array().cloned())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| p.as_str().map(|p| p.to_string()))
+            .collect::<Vec<String>>();
+        Self {
+            id,
+            database: cmd._database.clone().unwrap_or_default(),
+            collection: cmd._collection.clone().unwrap_or_default(),
+            schema: schema.clone(),
+            // the same body already ran as a `get`, a selector it couldn't read never gets here
+            scope: schema.key_scope(&body).unwrap_or(KeyScope::Nothing),
+            filter: body.get(Types::Filter.as_str()).cloned(),
+            paths,
+            keys,
+        }
+    }
+
+    ///
+    // a document the way the `get` would have returned it
+    ///
+    pub fn view(&self, doc: Value) -> Value {
+        let doc = without_expiry(doc);
+        if self.paths.is_empty() {
+            return doc;
+        }
+        project(&doc, &self.paths.iter().map(|p| p.as_str()).collect::<Vec<&str>>())
+    }
+
+    pub fn event(&self, kind: LiveEventKind, key: Option<String>, data: Option<Vec<Value>>, message: Option<String>) -> LiveEvent {
+        LiveEvent {
+            _live_id: self.id.clone(),
+            _event: kind,
+            key,
+            data,
+            message,
+        }
+    }
+
+    ///
+    // turns a raw change into what the client has to do with its result set, if anything
+    ///
+    pub fn apply(&mut self, change: &Change) -> Option<LiveEvent> {
+        if change.database.ne(&self.database) || change.collection.ne(&self.collection) {
+            return None;
+        }
+        let held = self.keys.contains(&change.raw);
+        let doc = change.doc.clone().map(|d| self.schema.upgrade(d));
+        let matched = match change.kind {
+            ChangeKind::Delete | ChangeKind::Expire => false,
+            ChangeKind::Upsert => {
+                let filter_ok = self.filter.as_ref().map_or(true, |f| is_match(f, doc.as_ref().unwrap_or(&Value::Null)));
+                self.scope.contains(&change.raw) && filter_ok
+            }
+        };
+        let data = doc.map(|d| vec![self.view(d)]);
+        let key = Some(change.key.clone());
+        match (held, matched) {
+            (false, true) => {
+                self.keys.insert(change.raw.clone());
+                Some(self.event(LiveEventKind::Add, key, data, None))
+            }
+            (true, true) => Some(self.event(LiveEventKind::Change, key, data, None)),
+            (true, false) => {
+                self.keys.remove(&change.raw);
+                Some(self.event(LiveEventKind::Remove, key, None, None))
+            }
+            (false, false) => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::hdrs::DataTypes;
+    use super::*;
+
+    fn change(schema: &JqlSchema, kind: ChangeKind, doc: Value) -> Change {
+        let raw = schema.encode_key(&schema.key_parts(&doc).unwrap()).unwrap();
+        Change {
+            database: "moss".to_string(),
+            collection: "ops".to_string(),
+            key: schema.decode_key(&raw),
+            raw,
+            kind: kind.clone(),
+            doc: if kind == ChangeKind::Upsert { Some(schema.stamp(&doc)) } else { None },
+        }
+    }
+
+    fn live_get(body: Value) -> JqlCommand {
+        serde_json::from_value(json!({
+            "_action":"get",
+            "_database":"moss",
+            "_collection":"ops",
+            "_body": body,
+            "_live": true
+        })).unwrap()
+    }
+
+    #[test]
+    fn live_transitions() {
+        let cmd = live_get(json!({ "_filter":{ "state":"open" } }));
+        let schema: JqlSchema = serde_json::from_str(r#"
+            {
+                "_name":"ops",
+                "_key":"ids",
+                "_fields":[{ "_name":"state", "_declare":"string" }]
+            }
+        "#).unwrap();
+        let docs = vec![json!({ "ids":"a1", "state":"open" })];
+        let mut live = LiveQuery::new("q1".to_string(), &cmd, &schema, &docs);
+
+        let ev = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "ids":"a2", "state":"open" })));
+        assert_eq!(ev.unwrap()._event, LiveEventKind::Add);
+
+        let ev = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "ids":"a1", "state":"open", "n":1 })));
+        assert_eq!(ev.unwrap()._event, LiveEventKind::Change);
+
+        let ev = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "ids":"a1", "state":"closed" })));
+        assert_eq!(ev.unwrap()._event, LiveEventKind::Remove);
+
+        let ev = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "ids":"a3", "state":"closed" })));
+        assert!(ev.is_none());
+
+        let ev = live.apply(&change(&schema, ChangeKind::Delete, json!({ "ids":"a2" })));
+        assert_eq!(ev.unwrap()._event, LiveEventKind::Remove);
+        assert!(live.keys.is_empty());
+    }
+
+    #[test]
+    fn live_scope_and_view() {
+        let schema = JqlSchema::builder("ops", "device")
+            .compound_key(&["device", "ts"])
+            .field("device", DataTypes::String)
+            .field("ts", DataTypes::Int)
+            .build();
+        let cmd = live_get(json!({ "_id":["d1"], "_project":["ts", "temp"] }));
+        let mut live = LiveQuery::new("q1".to_string(), &cmd, &schema, &vec![]);
+
+        let other = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "device":"d2", "ts":1, "temp":20 })));
+        assert!(other.is_none());
+
+        let ev = live.apply(&change(&schema, ChangeKind::Upsert, json!({ "device":"d1", "ts":2, "temp":21, "_expires_at":99 })));
+        let ev = ev.unwrap();
+        assert_eq!(ev._event, LiveEventKind::Add);
+        assert_eq!(ev.key.as_deref(), Some(r#"["d1",2]"#));
+        assert_eq!(ev.data.unwrap(), vec![json!({ "ts":2, "temp":21 })]);
+
+        let cmd = live_get(json!({ "_from":["d1", 5], "_to":["d1", 9] }));
+        let mut live = LiveQuery::new("q2".to_string(), &cmd, &schema, &vec![]);
+        assert!(live.apply(&change(&schema, ChangeKind::Upsert, json!({ "device":"d1", "ts":4 }))).is_none());
+        assert!(live.apply(&change(&schema, ChangeKind::Upsert, json!({ "device":"d1", "ts":5 }))).is_some());
+        assert!(live.apply(&change(&schema, ChangeKind::Upsert, json!({ "device":"d1", "ts":9 }))).is_none());
+    }
+}
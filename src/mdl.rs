@@ -0,0 +1,174 @@
+use chrono::{NaiveDate, NaiveTime};
+use uuid::Uuid;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use crate::aio::decode;
+use crate::err::MoeDbError;
+use crate::hdrs::{Attachment, DataTypes, JqlSchema, JqlSchemaFields, MoeDb, Query, SchemaKey};
+
+pub use moedb_derive::Collection;
+
+///
+/// Rust type -> `_declare`. `Option<T>` declares like `T` and marks the field `_optional`
+///
+pub trait FieldType {
+    fn declare() -> DataTypes;
+
+    fn optional() -> bool {
+        false
+    }
+}
+
+macro_rules! field_type {
+    ($declare:expr => $($ty:ty),+) => {
+        $(
+            impl FieldType for $ty {
+                fn declare() -> DataTypes {
+                    $declare
+                }
+            }
+        )+
+    };
+}
+
+field_type!(DataTypes::Int => i8, i16, i32, i64, isize);
+field_type!(DataTypes::Uint => u8, u16, u32, u64, usize);
+field_type!(DataTypes::Float => f32, f64);
+field_type!(DataTypes::String => String, char);
+field_type!(DataTypes::Boolean => bool);
+field_type!(DataTypes::Date => NaiveDate);
+field_type!(DataTypes::Time => NaiveTime);
+field_type!(DataTypes::ArrayOfInt => Vec<i8>, Vec<i16>, Vec<i32>, Vec<i64>, Vec<isize>);
+field_type!(DataTypes::ArrayOfUint => Vec<u8>, Vec<u16>, Vec<u32>, Vec<u64>, Vec<usize>);
+field_type!(DataTypes::ArrayOfFloat => Vec<f32>, Vec<f64>);
+field_type!(DataTypes::ArrayOfString => Vec<String>);
+field_type!(DataTypes::ArrayOfBoolean => Vec<bool>);
+field_type!(DataTypes::Uuid => Uuid);
+field_type!(DataTypes::Json => Value);
+field_type!(DataTypes::Blob => Attachment);
+
+impl<T: FieldType> FieldType for Option<T> {
+    fn declare() -> DataTypes {
+        T::declare()
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+pub fn field<T: FieldType>(name: &str) -> JqlSchemaFields {
+    let field = JqlSchemaFields::new(name, T::declare());
+    if T::optional() {
+        return field.optional();
+    }
+    field
+}
+
+///
+/// A Rust struct that is stored as a collection. Usually derived, see `moedb_derive::Collection`
+///
+pub trait Collection: Serialize + DeserializeOwned {
+    fn name() -> &'static str;
+    fn key_field() -> &'static str;
+    fn fields() -> Vec<JqlSchemaFields>;
+
+    fn schema() -> JqlSchema {
+        JqlSchema {
+            _name: Self::name().to_string(),
+            _key: SchemaKey::Field(Self::key_field().to_string()),
+            _fields: Self::fields(),
+            _in_memory: None,
+            _version: None,
+            _migrations: vec![],
+            _mode: None,
+            _key_strategy: None,
+            _ttl: None,
+            _expire_field: None,
+            _storage: None,
+        }
+    }
+
+    fn query(db: &str) -> Query {
+        Query::collection(db, Self::name())
+    }
+
+    fn create_collection(moedb: &MoeDb, db: &str) -> Result<(), MoeDbError> {
+        moedb.run_command(&Query::database(db).create_collection(&Self::schema())).map(|_| ())
+    }
+
+    fn get(moedb: &MoeDb, db: &str, id: &str) -> Result<Option<Self>, MoeDbError> {
+        let data = moedb.run_command(&Self::query(db).id(id).get())?;
+        match data.unwrap_or_default().into_iter().next() {
+            None => Ok(None),
+            Some(doc) => decode::<Self>(doc).map(Some)
+        }
+    }
+
+    fn find(moedb: &MoeDb, query: Query) -> Result<Vec<Self>, MoeDbError> {
+        let data = moedb.run_command(&query.get())?;
+        data.unwrap_or_default()
+            .into_iter()
+            .map(decode::<Self>)
+            .collect()
+    }
+
+    fn upsert(&self, moedb: &MoeDb, db: &str) -> Result<String, MoeDbError> {
+        let data = moedb.run_command(&Self::query(db).upsert(self)?)?;
+        match data.unwrap_or_default().into_iter().next() {
+            Some(Value::String(key)) => Ok(key),
+            _ => Err(MoeDbError::DocumentError("no key returned for upserted document".to_string()))
+        }
+    }
+
+    fn delete(moedb: &MoeDb, db: &str, id: &str) -> Result<bool, MoeDbError> {
+        let data = moedb.run_command(&Self::query(db).id(id).delete())?;
+        Ok(!data.unwrap_or_default().is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_derive::{Deserialize, Serialize};
+    use crate::hdrs::Jql;
+    use crate::jqls::JqlSchemaParser;
+    use crate as moedb;
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Collection)]
+    #[moedb(name = "people")]
+    struct Person {
+        #[moedb(key)]
+        ids: String,
+        hello: Vec<String>,
+        age: Option<i64>,
+        #[moedb(rename = "nickName")]
+        #[serde(rename = "nickName")]
+        nick_name: String,
+        #[moedb(skip)]
+        #[serde(skip)]
+        cached: bool,
+    }
+
+    #[test]
+    fn derived_schema() {
+        let schema = Person::schema();
+        assert_eq!(schema._name, "people");
+        assert_eq!(schema._key, "ids");
+        let fields = schema._fields
+            .iter()
+            .map(|f| (f._name.as_str(), f._declare.as_str(), f._optional.is_some()))
+            .collect::<Vec<(&str, &str, bool)>>();
+        assert_eq!(fields, vec![
+            ("ids", "string", false),
+            ("hello", "string[]", false),
+            ("age", "int", true),
+            ("nickName", "string", false),
+        ]);
+
+        let jql = Jql::new_schema_parser();
+        let res = jql.parse_schema(serde_json::to_string(&schema).unwrap());
+        assert!(res.is_ok(), "{}", res.err().unwrap());
+    }
+}
@@ -0,0 +1,475 @@
+use std::fmt::{Display, Formatter};
+use std::collections::HashSet;
+use serde_json::{Map, Value};
+use log::{info, warn};
+use crate::err::{MoeDbError, TrxError};
+use crate::hdrs::{DataTypes, DbRes, Exec, JqlCommand, JqlSchema, MigrateMode, SchemaMigration, SchemaMode, TKey, TValue, Types};
+use crate::tup::{pack_names, unpack_names};
+use crate::util::key_merger;
+use crate::var::{BACKFILL_BATCH, COL_PREFIX, DB_PREFIX, DB_SYS, FORMAT_PREFIX, KEY_FORMAT, SEQ_PREFIX};
+
+/// !```
+/// {
+///     "_action": "alter-collection",
+///     "_database": "<your_database_name>",
+///     "_collection": "<your_collection_name>",
+///     "_body": {
+///         "_add": [ { "_name": "<field_name>", "_declare": "<declaration>", "_default": <value> } ],
+///         "_drop": [ "<field_name>" ],
+///         "_optional": [ "<field_name>" ],
+///         "_widen": [ { "_name": "<field_name>", "_declare": "<wider_declaration>" } ],
+///         "_migrate": "lazy|eager"
+///     }
+/// }
+/// !```
+/// Every alteration bumps the schema `_version` and is kept in `_migrations`. Documents are stored
+/// with the `_version` they were written under, `lazy` upgrades them when they are read, `eager`
+/// rewrites the whole collection right away, in batches, while writers of the collection wait
+
+impl Display for MigrateMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for MigrateMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "eager" => MigrateMode::Eager,
+            _ => MigrateMode::Lazy
+        }
+    }
+}
+
+impl MigrateMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            MigrateMode::Lazy => "lazy",
+            MigrateMode::Eager => "eager"
+        }
+    }
+}
+
+impl PartialEq for MigrateMode {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
+
+///
+/// only conversions that keep every stored value valid
+///
+pub fn can_widen(from: &DataTypes, to: &DataTypes) -> bool {
+    matches!(
+        (from, to),
+        (DataTypes::Int, DataTypes::Float)
+            | (DataTypes::Uint, DataTypes::Int)
+            | (DataTypes::Uint, DataTypes::Float)
+            | (DataTypes::Date, DataTypes::DateTime)
+            | (DataTypes::Int, DataTypes::Decimal)
+            | (DataTypes::Uint, DataTypes::Decimal)
+            | (DataTypes::ArrayOfInt, DataTypes::ArrayOfFloat)
+            | (DataTypes::ArrayOfUint, DataTypes::ArrayOfInt)
+            | (DataTypes::ArrayOfUint, DataTypes::ArrayOfFloat)
+    )
+}
+
+fn widen_value(to: &DataTypes, v: Value) -> Value {
+    match (to, v) {
+        (DataTypes::Float, Value::Number(n)) => n.as_f64().map_or(Value::Number(n), Value::from),
+        (DataTypes::Int, Value::Number(n)) => n.as_i64().map_or(Value::Number(n), Value::from),
+        (DataTypes::DateTime, Value::String(s)) => Value::String(format!("{} 00:00:00", s)),
+        (DataTypes::Decimal, Value::Number(n)) => Value::String(n.to_string()),
+        (DataTypes::ArrayOfFloat, Value::Array(items)) => Value::Array(
+            items.into_iter().map(|i| widen_value(&DataTypes::Float, i)).collect()
+        ),
+        (DataTypes::ArrayOfInt, Value::Array(items)) => Value::Array(
+            items.into_iter().map(|i| widen_value(&DataTypes::Int, i)).collect()
+        ),
+        (_, v) => v
+    }
+}
+
+impl SchemaMigration {
+    pub fn is_empty(&self) -> bool {
+        self._add.is_empty() && self._drop.is_empty() && self._optional.is_empty() && self._widen.is_empty()
+    }
+
+    pub fn mode(&self) -> MigrateMode {
+        MigrateMode::from(self._migrate.as_deref().unwrap_or_default())
+    }
+
+    pub fn migrate(&self, doc: &mut Map<String, Value>) {
+        for name in self._drop.iter() {
+            doc.remove(name.as_str());
+        }
+        for field in self._add.iter() {
+            if !doc.contains_key(field._name.as_str()) && field._default.is_some() {
+                doc.insert(field._name.clone(), field._default.clone().unwrap());
+            }
+        }
+        for field in self._widen.iter() {
+            let v = doc.remove(field._name.as_str());
+            if v.is_some() {
+                doc.insert(field._name.clone(), widen_value(&DataTypes::from(field._declare.clone()), v.unwrap()));
+            }
+        }
+    }
+}
+
+impl JqlSchema {
+    pub fn version(&self) -> u64 {
+        self._version.unwrap_or(1)
+    }
+
+    ///
+    // the schema after `migration`, or why it can't be applied to this one
+    ///
+    pub fn alter(&self, migration: &SchemaMigration) -> Result<JqlSchema, TrxError> {
+        let mut seen = HashSet::new();
+        let names = migration._add.iter().map(|f| &f._name)
+            .chain(migration._drop.iter())
+            .chain(migration._optional.iter())
+            .chain(migration._widen.iter().map(|f| &f._name));
+        for name in names {
+            if self._key.contains(name) {
+                return Err(TrxError::AlterCollectionError(format!("key field `{}` can't be altered", name)));
+            }
+            if !seen.insert(name.clone()) {
+                return Err(TrxError::AlterCollectionError(format!("field `{}` appears in more than one alteration", name)));
+            }
+        }
+
+        let mut schema = self.clone();
+        for field in migration._add.iter() {
+            if schema._fields.iter().any(|f| f._name.eq(&field._name)) {
+                return Err(TrxError::AlterCollectionError(format!("field `{}` already exists", field._name)));
+            }
+            if field._default.is_none() && !field._optional.unwrap_or(false) {
+                return Err(TrxError::AlterCollectionError(format!("new field `{}` needs a `{}` or has to be `{}`", field._name, Types::Default.as_str(), Types::Optional.as_str())));
+            }
+            schema._fields.push(field.clone());
+        }
+        for name in migration._drop.iter() {
+            let len = schema._fields.len();
+            schema._fields.retain(|f| !f._name.eq(name));
+            if schema._fields.len() == len {
+                return Err(TrxError::AlterCollectionError(format!("field `{}` does not exist", name)));
+            }
+        }
+        if schema._fields.is_empty() && schema.mode() != SchemaMode::Schemaless {
+            return Err(TrxError::AlterCollectionError("a collection needs at least one field".to_string()));
+        }
+        for name in migration._optional.iter() {
+            let field = schema._fields.iter_mut().find(|f| f._name.eq(name));
+            if field.is_none() {
+                return Err(TrxError::AlterCollectionError(format!("field `{}` does not exist", name)));
+            }
+            field.unwrap()._optional = Some(true);
+        }
+        for widen in migration._widen.iter() {
+            let field = schema._fields.iter_mut().find(|f| f._name.eq(&widen._name));
+            if field.is_none() {
+                return Err(TrxError::AlterCollectionError(format!("field `{}` does not exist", widen._name)));
+            }
+            let field = field.unwrap();
+            let from = DataTypes::from(field._declare.clone());
+            let to = DataTypes::from(widen._declare.clone());
+            if !can_widen(&from, &to) {
+                return Err(TrxError::AlterCollectionError(format!("`{}` can't be widened from `{}` to `{}`", field._name, from, to)));
+            }
+            field._declare = to.to_string();
+        }
+
+        let mut applied = migration.clone();
+        applied._version = self.version() + 1;
+        applied._migrate = Some(migration.mode().to_string());
+        schema._version = Some(applied._version);
+        schema._migrations.push(applied);
+        Ok(schema)
+    }
+
+    ///
+    // stored documents carry the schema version they were written under
+    ///
+    pub fn stamp(&self, doc: &Value) -> Value {
+        let mut stamped = doc.clone();
+        if stamped.is_object() {
+            stamped.as_object_mut().unwrap().insert(Types::Version.as_str().to_string(), Value::from(self.version()));
+        }
+        stamped
+    }
+
+    ///
+    // brings a stored document up to the current version, documents from before versioning count as 1
+    ///
+    pub fn upgrade(&self, doc: Value) -> Value {
+        let mut fields = match doc {
+            Value::Object(fields) => fields,
+            other => return other
+        };
+        let from = fields
+            .remove(Types::Version.as_str())
+            .and_then(|v| v.as_u64())
+            .unwrap_or(1);
+        self._migrations
+            .iter()
+            .filter(|m| m._version > from)
+            .for_each(|m| m.migrate(&mut fields));
+        Value::Object(fields)
+    }
+}
+
+impl Exec {
+    pub fn alter_col(&self, cmd: &JqlCommand) -> DbRes {
+        let db = cmd._database.as_ref().unwrap();
+        let col = cmd._collection.as_ref().unwrap();
+        let name = Self::db_col_merged(cmd).unwrap();
+        // two alters reading the same version would lose one migration, writers wait for the backfill
+        let lock = self.col_lock(name.as_str());
+        let _guard = lock.lock().unwrap();
+        let wrp_schema = self.schema(db, col);
+        if wrp_schema.is_none() {
+            return DbRes { data: None, error: Some(TrxError::AlterCollectionError(format!("collection does not exist `{}`", col))) };
+        }
+        let migration = serde_json::from_value::<SchemaMigration>(cmd._body.clone().unwrap());
+        if migration.is_err() {
+            return DbRes { data: None, error: Some(TrxError::AlterCollectionError(migration.err().unwrap().to_string())) };
+        }
+        let migration = migration.unwrap();
+        let altered = wrp_schema.unwrap().alter(&migration);
+        if altered.is_err() {
+            return DbRes { data: None, error: Some(altered.err().unwrap()) };
+        }
+        let schema = altered.unwrap();
+
+        // the schema goes first, documents an eager backfill doesn't reach are still upgraded on read
+        let saved = self.trx.put(
+            DB_SYS,
+            Self::col_key(db, col),
+            serde_json::to_vec(&schema).unwrap()
+        );
+        if saved.is_err() {
+            return DbRes { data: None, error: Some(TrxError::AlterCollectionError(saved.err().unwrap().to_string())) };
+        }
+
+        if migration.mode() == MigrateMode::Eager && self.trx.has_cf(name.as_str()) {
+            let filled = self.backfill(name.as_str(), &schema);
+            if filled.is_err() {
+                return DbRes { data: None, error: Some(TrxError::AlterCollectionError(format!(
+                    "`{}` is at version {} but the backfill stopped, documents it didn't reach are upgraded on read: {}",
+                    col, schema.version(), filled.err().unwrap()
+                ))) };
+            }
+        }
+        DbRes { data: Some(vec![serde_json::to_value(&schema).unwrap()]), error: None }
+    }
+
+    ///
+    // rewrites every document under an older version in batches of `BACKFILL_BATCH`. Nothing goes to the
+    // change feed, the documents read the same before and after and one event each would only make live
+    // sessions lag
+    ///
+    fn backfill(&self, name: &str, schema: &JqlSchema) -> Result<(), String> {
+        let mut batch = vec![];
+        let mut failed = None;
+        let flush = |batch: &mut Vec<(TKey, TValue)>| {
+            let written = self.trx.put_all(name, std::mem::take(batch));
+            written.map_err(|e| e.to_string())
+        };
+        self.trx.each(name, vec![], |key, doc| {
+            if doc.get(Types::Version.as_str()).and_then(|v| v.as_u64()) == Some(schema.version()) {
+                return true;
+            }
+            let upgraded = schema.stamp(&schema.upgrade(doc));
+            batch.push((key.to_vec(), serde_json::to_vec(&upgraded).unwrap()));
+            if batch.len() < BACKFILL_BATCH {
+                return true;
+            }
+            failed = flush(&mut batch).err();
+            failed.is_none()
+        });
+        if failed.is_some() {
+            return Err(failed.unwrap());
+        }
+        flush(&mut batch)
+    }
+}
+
+impl Exec {
+    ///
+    /// stores written before keys were packed hold `database::#db`, `db#col` and `sequence::#db#col` in sys
+    /// and every document under the text of its key. Format 2 packed undeclared key fields as text, so
+    /// their numbers sorted lexically. Both are rewritten once when the store is opened, the format marker
+    /// makes later opens skip it. Packed keys start with the string tag `0`, legacy names always start
+    /// with a letter, and documents already under their key are left alone, so a run cut short picks up
+    /// where it stopped
+    ///
+    pub fn upgrade_key_format(&self) -> Result<(), MoeDbError> {
+        let marker = pack_names(&[FORMAT_PREFIX]);
+        let format = self.trx.get(DB_SYS, marker.clone()).and_then(|f| f.as_u64());
+        if format.map_or(false, |f| f >= KEY_FORMAT) {
+            return Ok(());
+        }
+        let moved = self.move_keys(DB_SYS, |key, _| {
+            if key.first() == Some(&b'0') {
+                return None;
+            }
+            let legacy = String::from_utf8_lossy(key).to_string();
+            let db_prefix = format!("{}#", DB_PREFIX);
+            let seq_prefix = format!("{}#", SEQ_PREFIX);
+            let packed = if legacy.starts_with(&db_prefix) {
+                Self::db_key(&legacy[db_prefix.len()..])
+            } else if legacy.starts_with(&seq_prefix) {
+                let (db, col) = legacy[seq_prefix.len()..].split_once('#')?;
+                Self::sequence_key(db, col)
+            } else {
+                let (db, col) = legacy.split_once('#')?;
+                Self::col_key(db, col)
+            };
+            info!("upgrading sys key `{}`", legacy);
+            Some(packed)
+        });
+        if moved.is_err() {
+            return moved;
+        }
+        let mut upgraded = Ok(());
+        self.trx.each(DB_SYS, pack_names(&[COL_PREFIX]), |key, schema| {
+            let names = unpack_names(key).unwrap_or_default();
+            if names.len() != 3 {
+                return true;
+            }
+            upgraded = self.upgrade_doc_keys(names[1].as_str(), names[2].as_str(), &schema);
+            upgraded.is_ok()
+        });
+        if upgraded.is_err() {
+            return upgraded;
+        }
+        let marked = self.trx.put(DB_SYS, marker, serde_json::to_vec(&KEY_FORMAT).unwrap());
+        if marked.is_err() {
+            return Err(MoeDbError::KeyFormatError(marked.err().unwrap().to_string()));
+        }
+        Ok(())
+    }
+
+    ///
+    // document keys are rebuilt from the documents, so text keys and earlier packings end up the same
+    ///
+    fn upgrade_doc_keys(&self, db: &str, col: &str, schema: &Value) -> Result<(), MoeDbError> {
+        let schema = serde_json::from_value::<JqlSchema>(schema.clone());
+        if schema.is_err() {
+            return Err(MoeDbError::KeyFormatError(format!("schema of `{}` {}", col, schema.err().unwrap())));
+        }
+        let schema = schema.unwrap();
+        let name = key_merger(vec![db.to_string(), col.to_string()]).unwrap();
+        if !self.trx.has_cf(name.as_str()) {
+            return Ok(());
+        }
+        self.move_keys(name.as_str(), |key, doc| {
+            let packed: Option<TKey> = schema.key_parts(doc).and_then(|parts| schema.encode_key(&parts));
+            if packed.is_none() {
+                warn!("`{}` document under `{}` has no usable key, left as is", col, String::from_utf8_lossy(key));
+                return None;
+            }
+            packed.filter(|p| p.as_slice() != key)
+        })
+    }
+
+    ///
+    // walks `cf_name` and moves every entry `to` gives a new key for, in batches of `BACKFILL_BATCH`. A
+    // batch moves whole, a run cut short leaves each entry under either its old or its new key
+    ///
+    fn move_keys<F: FnMut(&[u8], &Value) -> Option<TKey>>(&self, cf_name: &str, mut to: F) -> Result<(), MoeDbError> {
+        let mut batch = vec![];
+        let mut failed = None;
+        let flush = |batch: &mut Vec<(TKey, TKey, TValue)>| {
+            let moved = self.trx.move_all(cf_name, std::mem::take(batch));
+            moved.map_err(|e| MoeDbError::KeyFormatError(e.to_string()))
+        };
+        self.trx.each(cf_name, vec![], |key, value| {
+            let packed = to(key, &value);
+            if packed.is_none() {
+                return true;
+            }
+            batch.push((key.to_vec(), packed.unwrap(), serde_json::to_vec(&value).unwrap()));
+            if batch.len() < BACKFILL_BATCH {
+                return true;
+            }
+            failed = flush(&mut batch).err();
+            failed.is_none()
+        });
+        if failed.is_some() {
+            return Err(failed.unwrap());
+        }
+        flush(&mut batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn person() -> JqlSchema {
+        serde_json::from_str(r#"
+            {
+                "_name":"person",
+                "_key":"ids",
+                "_fields":[
+                    { "_name":"hello", "_declare":"string[]" },
+                    { "_name":"age", "_declare":"int" },
+                    { "_name":"nick", "_declare":"string" }
+                ]
+            }
+        "#).unwrap()
+    }
+
+    #[test]
+    fn alter_schema() {
+        let migration: SchemaMigration = serde_json::from_value(json!({
+            "_drop": ["nick"],
+            "_optional": ["hello"],
+            "_widen": [{ "_name":"age", "_declare":"float" }]
+        })).unwrap();
+        let schema = person().alter(&migration).unwrap();
+        assert_eq!(schema.version(), 2);
+        assert_eq!(schema._migrations.len(), 1);
+        assert_eq!(schema._migrations[0]._migrate.as_deref(), Some("lazy"));
+        let fields = schema._fields
+            .iter()
+            .map(|f| (f._name.as_str(), f._declare.as_str(), f._optional.is_some()))
+            .collect::<Vec<(&str, &str, bool)>>();
+        assert_eq!(fields, vec![("hello", "string[]", true), ("age", "float", false)]);
+
+        let narrow: SchemaMigration = serde_json::from_value(json!({ "_widen": [{ "_name":"age", "_declare":"uint" }] })).unwrap();
+        assert!(person().alter(&narrow).is_err());
+        let key: SchemaMigration = serde_json::from_value(json!({ "_drop": ["ids"] })).unwrap();
+        assert!(person().alter(&key).is_err());
+        let bare: SchemaMigration = serde_json::from_value(json!({ "_add": [{ "_name":"city", "_declare":"string" }] })).unwrap();
+        assert!(person().alter(&bare).is_err());
+    }
+
+    #[test]
+    fn upgrade_on_read() {
+        let mut schema = person();
+        let v2: SchemaMigration = serde_json::from_value(json!({
+            "_version": 2,
+            "_drop": ["nick"],
+            "_widen": [{ "_name":"age", "_declare":"float" }]
+        })).unwrap();
+        let v3: SchemaMigration = serde_json::from_value(json!({
+            "_version": 3,
+            "_add": [{ "_name":"nick", "_declare":"string", "_default":"none" }]
+        })).unwrap();
+        schema._migrations = vec![v2, v3];
+        schema._version = Some(3);
+
+        let old = json!({ "ids":"a1", "hello":[], "age":3, "nick":"moe" });
+        assert_eq!(schema.upgrade(old), json!({ "ids":"a1", "hello":[], "age":3.0, "nick":"none" }));
+
+        // written under v3, nothing to do but drop the stamp
+        let current = schema.stamp(&json!({ "ids":"a2", "hello":[], "age":1.5, "nick":"moe" }));
+        assert_eq!(current[Types::Version.as_str()], json!(3));
+        assert_eq!(schema.upgrade(current), json!({ "ids":"a2", "hello":[], "age":1.5, "nick":"moe" }));
+    }
+}
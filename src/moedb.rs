@@ -3,34 +3,64 @@ use std::sync::Arc;
 use std::time::Instant;
 use crossbeam::channel::unbounded;
 use log::{trace};
+use serde_json::Value;
+use tokio::sync::broadcast::Receiver;
 use crate::env;
 use crate::err::MoeDbError;
 use crate::func::unique_id;
-use crate::hdrs::{Exec, Logging, MoeDb, Response};
+use crate::hdrs::{Change, Exec, JqlCommand, Logging, MoeDb, Response};
 
 impl MoeDb {
     pub fn new(cfg: env::MoeDb) -> Result<Self, MoeDbError> {
         let config = Arc::new(cfg);
-        let exec = Exec::new(Arc::clone(&config));
-        if exec.is_err() {
-            return Err(exec.err().unwrap());
-        }
         let log = Logging::new(Arc::clone(&config));
         if log.is_err() {
             return Err(log.err().unwrap());
         }
+        let log = log.unwrap();
+        // backups take the query log along with the main db
+        let exec = Exec::new(Arc::clone(&config), Some(Arc::clone(&log.trx)));
+        if exec.is_err() {
+            return Err(exec.err().unwrap());
+        }
+        let exec = Arc::new(exec.unwrap());
+        Exec::start_sweeper(&exec);
         Ok(Self {
-            exec: Arc::new(exec.unwrap()),
-            log: Arc::new(log.unwrap())
+            exec,
+            log: Arc::new(log)
         })
     }
     ///
+    // every successful upsert / delete after this call shows up on the receiver
+    ///
+    pub fn subscribe(&self) -> Receiver<Change> {
+        self.exec.feed.subscribe()
+    }
+    ///
     // create_db 27.280959ms response query executed in "4.23975ms"
     // create_collection 19.507875ms response query executed in "19.24775ms"
     // db_list 660.291µs response ["random"]
     // col_list 4.234375ms response ["numbers"]
     ///
     pub fn execute(&self, stmt: &str) -> Response {
+        self.execute_with_error(stmt).0
+    }
+
+    pub fn execute_command(&self, cmd: &JqlCommand) -> Response {
+        self.execute(cmd.to_jql().as_str())
+    }
+
+    pub fn run_command(&self, cmd: &JqlCommand) -> Result<Option<Vec<Value>>, MoeDbError> {
+        match self.execute_with_error(cmd.to_jql().as_str()) {
+            (_, Some(err)) => Err(err),
+            (res, None) => Ok(res.data)
+        }
+    }
+    ///
+    // same as `execute` but hands back the underlying error as well, so callers
+    // like the server can tell a bad query from a failed transaction
+    ///
+    pub fn execute_with_error(&self, stmt: &str) -> (Response, Option<MoeDbError>) {
         let elp = Instant::now();
         let uid = unique_id();
         let query_id = uid.as_str();
@@ -40,8 +70,10 @@ impl MoeDb {
         let err = res.is_err();
         let mut message =  "".to_string();
         let mut data = None;
+        let mut error = None;
         if err {
             message = res.as_ref().err().unwrap().to_string();
+            error = res.err();
         } else {
             data = res.unwrap();
         }
@@ -54,6 +86,6 @@ impl MoeDb {
         };
         trace!("executed query {} in {}",query_id.clone(), ended.as_str());
 
-        response
+        (response, error)
     }
 }
\ No newline at end of file
@@ -15,6 +15,43 @@ impl From<String> for Types {
             "_fields" => Types::Fields,
             "_in_memory" => Types::InMemory,
             "_declare" => Types::Declare,
+            "_id" => Types::Id,
+            "_filter" => Types::Filter,
+            "_sort" => Types::Sort,
+            "_limit" => Types::Limit,
+            "_skip" => Types::Skip,
+            "_field" => Types::Field,
+            "_order" => Types::Order,
+            "_version" => Types::Version,
+            "_default" => Types::Default,
+            "_migrate" => Types::Migrate,
+            "_optional" => Types::Optional,
+            "_min" => Types::Min,
+            "_max" => Types::Max,
+            "_min_len" => Types::MinLen,
+            "_max_len" => Types::MaxLen,
+            "_pattern" => Types::Pattern,
+            "_enum" => Types::Enum,
+            "_mode" => Types::Mode,
+            "_key_strategy" => Types::KeyStrategy,
+            "_from" => Types::From,
+            "_to" => Types::To,
+            "_ttl" => Types::Ttl,
+            "_expire_field" => Types::ExpireField,
+            "_expires_at" => Types::ExpiresAt,
+            "_data" => Types::Data,
+            "_content_type" => Types::ContentType,
+            "_metadata" => Types::Metadata,
+            "_prefix" => Types::Prefix,
+            "_range" => Types::Range,
+            "_upload_id" => Types::UploadId,
+            "_part" => Types::Part,
+            "_bucket" => Types::Bucket,
+            "_inline" => Types::Inline,
+            "_target" => Types::Target,
+            "_keep" => Types::Keep,
+            "_storage" => Types::Storage,
+            "_project" => Types::Project,
             _ => Types::Name
         }
     }
@@ -27,7 +64,44 @@ impl Types {
             Types::Key => "_key",
             Types::Fields => "_fields",
             Types::InMemory => "_in_memory",
-            Types::Declare => "_declare"
+            Types::Declare => "_declare",
+            Types::Id => "_id",
+            Types::Filter => "_filter",
+            Types::Sort => "_sort",
+            Types::Limit => "_limit",
+            Types::Skip => "_skip",
+            Types::Field => "_field",
+            Types::Order => "_order",
+            Types::Version => "_version",
+            Types::Default => "_default",
+            Types::Migrate => "_migrate",
+            Types::Optional => "_optional",
+            Types::Min => "_min",
+            Types::Max => "_max",
+            Types::MinLen => "_min_len",
+            Types::MaxLen => "_max_len",
+            Types::Pattern => "_pattern",
+            Types::Enum => "_enum",
+            Types::Mode => "_mode",
+            Types::KeyStrategy => "_key_strategy",
+            Types::From => "_from",
+            Types::To => "_to",
+            Types::Ttl => "_ttl",
+            Types::ExpireField => "_expire_field",
+            Types::ExpiresAt => "_expires_at",
+            Types::Data => "_data",
+            Types::ContentType => "_content_type",
+            Types::Metadata => "_metadata",
+            Types::Prefix => "_prefix",
+            Types::Range => "_range",
+            Types::UploadId => "_upload_id",
+            Types::Part => "_part",
+            Types::Bucket => "_bucket",
+            Types::Inline => "_inline",
+            Types::Target => "_target",
+            Types::Keep => "_keep",
+            Types::Storage => "_storage",
+            Types::Project => "_project"
         }
     }
 }
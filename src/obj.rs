@@ -0,0 +1,733 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use itertools::Itertools;
+use log::error;
+use serde_json::{Map, Value};
+use crate::err::{MoeDbError, TrxError};
+use crate::func::unique_id;
+use crate::hdrs::{DbRes, Exec, JqlCommand, MoeDb, ObjectLocks, ObjectMeta, ObjectReader, ObjectWriter, TKey, Trx, Types};
+use crate::tup::{pack, pack_names, unpack_names};
+use crate::ttl::now_secs;
+use crate::util::key_merger;
+use crate::var::{BUCKET_MARKER, BUCKET_PREFIX, DB_SYS, OBJECT_CHUNK};
+
+/// !```
+/// {
+///     "_action": "put-object | get-object | delete-object | list-objects",
+///     "_database": "shop",
+///     "_bucket": "images",
+///     "_body": {
+///         "_key": "products/42.png",
+///         "_data": "<base64>",                  (put-object)
+///         "_content_type": "image/png",         (put-object, optional)
+///         "_metadata": { "alt": "red mug" },    (put-object, optional)
+///         "_range": [0, 1024],                  (get-object, optional, bytes start..end, end optional)
+///         "_prefix": "products/",               (list-objects, optional)
+///         "_limit": 100                         (list-objects, optional)
+///     }
+/// }
+/// !```
+/// a bucket is the column family `<db>#objects#<bucket>`, created by its first put. An object is one
+/// meta entry plus `OBJECT_CHUNK` sized chunks, each with its own crc32 next to the crc32 of the whole
+/// object. Chunks are written under a fresh generation before the meta points at them, so a put that
+/// fails half way leaves the previous version readable. Metas are swapped one at a time, and a reader
+/// pins the generation it started on, whose chunks outlive a newer put or a delete until it is done.
+/// `ObjectWriter` and `ObjectReader` move one chunk at a time, multipart uploads live in upl.rs
+
+const META: &str = "meta";
+const CHUNK: &str = "chunk";
+
+pub fn checksum(data: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(data))
+}
+
+fn meta_key(key: &str) -> TKey {
+    pack_names(&[META, key])
+}
+
+fn chunk_key(generation: &str, idx: usize) -> TKey {
+    let (tag, generation, idx) = (Value::from(CHUNK), Value::from(generation), Value::from(idx as u64));
+    pack(&[(None, &tag), (None, &generation), (None, &idx)]).unwrap()
+}
+
+///
+// `start..end` of an object of `size` bytes, an open end runs to the last byte
+///
+pub fn clamp_range(start: u64, end: Option<u64>, size: u64) -> Result<(u64, u64), TrxError> {
+    let end = end.map_or(size, |e| e.min(size));
+    if start > end || (start == end && size > 0) {
+        return Err(TrxError::RangeError(format!("{}..{} of {} bytes", start, end, size)));
+    }
+    Ok((start, end))
+}
+
+///
+// leftovers only cost space, so failures are logged rather than surfaced
+///
+fn drop_chunks(trx: &Trx, name: &str, meta: &ObjectMeta) {
+    for idx in 0..meta._chunks.len() {
+        let dlt = trx.delete(name, chunk_key(meta._generation.as_str(), idx));
+        if dlt.is_err() {
+            error!("dropping chunk {} of `{}` {}", idx, meta._key, dlt.err().unwrap());
+        }
+    }
+}
+
+fn pin_key(name: &str, generation: &str) -> String {
+    key_merger(vec![name.to_string(), generation.to_string()]).unwrap()
+}
+
+impl ObjectLocks {
+    pub fn pin(&self, name: &str, generation: &str) {
+        let mut pins = self.pins.lock().unwrap();
+        pins.entry(pin_key(name, generation)).or_insert((0, None)).0 += 1;
+    }
+
+    ///
+    // the meta whose chunks can go once its last reader unpinned it
+    ///
+    pub fn unpin(&self, name: &str, generation: &str) -> Option<ObjectMeta> {
+        let mut pins = self.pins.lock().unwrap();
+        let key = pin_key(name, generation);
+        let pin = pins.get_mut(&key);
+        if pin.is_none() {
+            return None;
+        }
+        let pin = pin.unwrap();
+        pin.0 = pin.0.saturating_sub(1);
+        if pin.0 > 0 {
+            return None;
+        }
+        pins.remove(&key).and_then(|(_, retired)| retired)
+    }
+
+    ///
+    // the meta back when nobody reads it so its chunks can go now, otherwise the last reader drops them
+    ///
+    pub fn retire(&self, name: &str, meta: ObjectMeta) -> Option<ObjectMeta> {
+        let mut pins = self.pins.lock().unwrap();
+        match pins.get_mut(&pin_key(name, meta._generation.as_str())) {
+            Some(pin) => {
+                pin.1 = Some(meta);
+                None
+            }
+            None => Some(meta)
+        }
+    }
+}
+
+fn retire_chunks(trx: &Trx, locks: &ObjectLocks, name: &str, meta: ObjectMeta) {
+    let unread = locks.retire(name, meta);
+    if unread.is_some() {
+        drop_chunks(trx, name, &unread.unwrap());
+    }
+}
+
+fn read_meta(trx: &Trx, name: &str, key: &str) -> Option<ObjectMeta> {
+    if !trx.has_cf(name) {
+        return None;
+    }
+    trx.get(name, meta_key(key))
+        .and_then(|m| serde_json::from_value::<ObjectMeta>(m).ok())
+}
+
+impl Iterator for ObjectReader {
+    type Item = Result<Vec<u8>, TrxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = self.meta._chunk_size;
+        let idx = self.next;
+        let offset = idx as u64 * size;
+        if offset >= self.end || idx >= self.meta._chunks.len() {
+            return None;
+        }
+        self.next += 1;
+        let chunk = self.trx.get_raw(self.cf.as_str(), chunk_key(self.meta._generation.as_str(), idx));
+        if chunk.is_none() {
+            self.next = self.meta._chunks.len();
+            return Some(Err(TrxError::ObjectError(format!("chunk {} of `{}` is missing", idx, self.meta._key))));
+        }
+        let chunk = chunk.unwrap();
+        if crc32fast::hash(&chunk) != self.meta._chunks[idx] {
+            self.next = self.meta._chunks.len();
+            return Some(Err(TrxError::ObjectError(format!("chunk {} of `{}` failed its checksum", idx, self.meta._key))));
+        }
+        if self.whole.is_some() {
+            self.whole.as_mut().unwrap().update(&chunk);
+            if self.next == self.meta._chunks.len() {
+                let sum = format!("{:08x}", self.whole.take().unwrap().finalize());
+                if sum != self.meta._checksum {
+                    return Some(Err(TrxError::ObjectError(format!("`{}` failed its checksum", self.meta._key))));
+                }
+            }
+        }
+        let from = self.start.saturating_sub(offset) as usize;
+        let to = ((self.end - offset) as usize).min(chunk.len());
+        Some(Ok(chunk[from..to].to_vec()))
+    }
+}
+
+impl Drop for ObjectReader {
+    fn drop(&mut self) {
+        let retired = self.locks.unpin(self.cf.as_str(), self.meta._generation.as_str());
+        if retired.is_some() {
+            drop_chunks(&self.trx, self.cf.as_str(), &retired.unwrap());
+        }
+    }
+}
+
+impl ObjectReader {
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let chunk = self.meta._chunk_size as usize;
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let take = rest.len().min(chunk - self.buffer.len());
+            self.buffer.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.buffer.len() == chunk {
+                let flushed = self.flush_chunk();
+                if flushed.is_err() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::Other, flushed.err().unwrap().to_string()));
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ObjectWriter {
+    fn flush_chunk(&mut self) -> Result<(), TrxError> {
+        let idx = self.meta._chunks.len();
+        let put = self.trx.put(self.cf.as_str(), chunk_key(self.meta._generation.as_str(), idx), self.buffer.clone());
+        if put.is_err() {
+            return Err(TrxError::ObjectError(put.err().unwrap().to_string()));
+        }
+        self.hasher.update(&self.buffer);
+        self.meta._chunks.push(crc32fast::hash(&self.buffer));
+        self.meta._size += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+
+    ///
+    // writes what is left, points the meta at the new chunks and only then drops the previous version
+    ///
+    pub fn finish(mut self) -> Result<ObjectMeta, TrxError> {
+        if !self.buffer.is_empty() {
+            let flushed = self.flush_chunk();
+            if flushed.is_err() {
+                return Err(flushed.err().unwrap());
+            }
+        }
+        self.meta._checksum = format!("{:08x}", self.hasher.clone().finalize());
+        self.meta._updated_at = now_secs();
+        // concurrent puts of a key each retire the version the other replaced, none of them leaks
+        let swap = self.locks.swap.lock().unwrap();
+        let previous = read_meta(&self.trx, self.cf.as_str(), self.meta._key.as_str());
+        // a new version of an attachment stays with its document
+        self.meta._owner = previous.as_ref().and_then(|p| p._owner.clone());
+        let put = self.trx.put(self.cf.as_str(), meta_key(self.meta._key.as_str()), serde_json::to_vec(&self.meta).unwrap());
+        if put.is_err() {
+            return Err(TrxError::ObjectError(put.err().unwrap().to_string()));
+        }
+        drop(swap);
+        self.finished = true;
+        if previous.is_some() {
+            retire_chunks(&self.trx, &self.locks, self.cf.as_str(), previous.unwrap());
+        }
+        Ok(self.meta.clone())
+    }
+}
+
+impl Drop for ObjectWriter {
+    fn drop(&mut self) {
+        if !self.finished {
+            drop_chunks(&self.trx, self.cf.as_str(), &self.meta);
+        }
+    }
+}
+
+impl ObjectMeta {
+    ///
+    // what clients get to see, chunk bookkeeping stays inside
+    ///
+    pub fn to_response(&self) -> Value {
+        let mut res = serde_json::to_value(self).unwrap();
+        let fields = res.as_object_mut().unwrap();
+        fields.remove("_chunks");
+        fields.remove("_generation");
+        res
+    }
+}
+
+impl Exec {
+    pub fn bucket_cf(db: &str, bucket: &str) -> String {
+        key_merger(vec![db.to_string(), BUCKET_MARKER.to_string(), bucket.to_string()]).unwrap()
+    }
+
+    pub fn bucket_key(db: &str, bucket: &str) -> TKey {
+        pack_names(&[BUCKET_PREFIX, db, bucket])
+    }
+
+    pub fn bucket_names(&self, db: &str) -> Vec<String> {
+        self.trx.entries(DB_SYS, pack_names(&[BUCKET_PREFIX, db]))
+            .into_iter()
+            .filter_map(|(key, _)| unpack_names(&key))
+            .filter(|names| names.len() == 3)
+            .map(|mut names| names.remove(2))
+            .collect_vec()
+    }
+
+    pub fn open_bucket(&self, db: &str, bucket: &str) -> Result<String, TrxError> {
+        if self.trx.get(DB_SYS, Self::db_key(db)).is_none() {
+            return Err(TrxError::ObjectError(format!("db does not exist `{}`", db)));
+        }
+        let name = Self::bucket_cf(db, bucket);
+        if self.trx.has_cf(name.as_str()) {
+            return Ok(name);
+        }
+        // two first puts into a bucket would both try to create it
+        let _lock = self.buckets.lock().unwrap();
+        if self.trx.has_cf(name.as_str()) {
+            return Ok(name);
+        }
+        let created = self.trx.create_cf(name.as_str());
+        if created.is_err() {
+            return Err(created.err().unwrap());
+        }
+        let registered = self.trx.put(DB_SYS, Self::bucket_key(db, bucket), serde_json::to_vec(&true).unwrap());
+        if registered.is_err() {
+            return Err(TrxError::ObjectError(registered.err().unwrap().to_string()));
+        }
+        Ok(name)
+    }
+
+    ///
+    // chunks go straight to the bucket as they fill up, so the object never has to sit in memory
+    ///
+    pub fn object_writer(&self, db: &str, bucket: &str, key: &str, content_type: Option<String>, metadata: Map<String, Value>) -> Result<ObjectWriter, TrxError> {
+        let name = self.open_bucket(db, bucket);
+        if name.is_err() {
+            return Err(name.err().unwrap());
+        }
+        Ok(ObjectWriter {
+            trx: Arc::clone(&self.trx),
+            locks: Arc::clone(&self.objects),
+            cf: name.unwrap(),
+            meta: ObjectMeta {
+                _key: key.to_string(),
+                _size: 0,
+                _content_type: content_type,
+                _metadata: metadata,
+                _checksum: String::new(),
+                _chunk_size: OBJECT_CHUNK as u64,
+                _chunks: vec![],
+                _generation: unique_id(),
+                _updated_at: 0,
+                _owner: None,
+            },
+            buffer: Vec::with_capacity(OBJECT_CHUNK),
+            hasher: crc32fast::Hasher::new(),
+            finished: false,
+        })
+    }
+
+    pub fn put_object_from<R: Read>(&self, db: &str, bucket: &str, key: &str, mut source: R, content_type: Option<String>, metadata: Map<String, Value>) -> Result<ObjectMeta, TrxError> {
+        let writer = self.object_writer(db, bucket, key, content_type, metadata);
+        if writer.is_err() {
+            return Err(writer.err().unwrap());
+        }
+        let mut writer = writer.unwrap();
+        let copied = std::io::copy(&mut source, &mut writer);
+        if copied.is_err() {
+            return Err(TrxError::ObjectError(copied.err().unwrap().to_string()));
+        }
+        writer.finish()
+    }
+
+    pub fn put_object(&self, db: &str, bucket: &str, key: &str, data: &[u8], content_type: Option<String>, metadata: Map<String, Value>) -> Result<ObjectMeta, TrxError> {
+        self.put_object_from(db, bucket, key, data, content_type, metadata)
+    }
+
+    pub fn object_meta(&self, db: &str, bucket: &str, key: &str) -> Option<ObjectMeta> {
+        read_meta(&self.trx, Self::bucket_cf(db, bucket).as_str(), key)
+    }
+
+    ///
+    // the whole object's checksum can only be checked when the range covers all of it, chunks always are
+    ///
+    pub fn object_reader(&self, db: &str, bucket: &str, key: &str, start: u64, end: Option<u64>) -> Result<ObjectReader, TrxError> {
+        let name = Self::bucket_cf(db, bucket);
+        // pinned before a put or delete can retire it
+        let swap = self.objects.swap.lock().unwrap();
+        let meta = read_meta(&self.trx, name.as_str(), key);
+        if meta.is_none() {
+            return Err(TrxError::ObjectError(format!("object does not exist `{}`", key)));
+        }
+        let meta = meta.unwrap();
+        let range = clamp_range(start, end, meta._size);
+        if range.is_err() {
+            return Err(range.err().unwrap());
+        }
+        self.objects.pin(name.as_str(), meta._generation.as_str());
+        drop(swap);
+        let (start, end) = range.unwrap();
+        let whole = start == 0 && end == meta._size;
+        Ok(ObjectReader {
+            trx: Arc::clone(&self.trx),
+            locks: Arc::clone(&self.objects),
+            cf: name,
+            next: (start / meta._chunk_size) as usize,
+            meta,
+            start,
+            end,
+            whole: if whole { Some(crc32fast::Hasher::new()) } else { None },
+        })
+    }
+
+    pub fn read_object(&self, db: &str, bucket: &str, key: &str) -> Result<(ObjectMeta, Vec<u8>), TrxError> {
+        self.read_range(db, bucket, key, 0, None)
+    }
+
+    pub fn read_range(&self, db: &str, bucket: &str, key: &str, start: u64, end: Option<u64>) -> Result<(ObjectMeta, Vec<u8>), TrxError> {
+        let reader = self.object_reader(db, bucket, key, start, end);
+        if reader.is_err() {
+            return Err(reader.err().unwrap());
+        }
+        let reader = reader.unwrap();
+        let meta = reader.meta.clone();
+        let mut data = Vec::with_capacity(reader.len() as usize);
+        for chunk in reader {
+            if chunk.is_err() {
+                return Err(chunk.err().unwrap());
+            }
+            data.extend_from_slice(&chunk.unwrap());
+        }
+        Ok((meta, data))
+    }
+
+    ///
+    // objects a document holds are removed with the document, not on their own
+    ///
+    pub fn remove_object(&self, db: &str, bucket: &str, key: &str) -> Result<ObjectMeta, TrxError> {
+        self.remove_held_object(db, bucket, key, None)
+    }
+
+    ///
+    // removes the object only while `owner` holds it, `None` when no document may hold it. Checked under
+    // the same lock `claim_object` takes
+    ///
+    pub fn remove_held_object(&self, db: &str, bucket: &str, key: &str, owner: Option<&str>) -> Result<ObjectMeta, TrxError> {
+        let name = Self::bucket_cf(db, bucket);
+        let swap = self.objects.swap.lock().unwrap();
+        let meta = read_meta(&self.trx, name.as_str(), key);
+        if meta.is_none() {
+            return Err(TrxError::ObjectError(format!("object does not exist `{}`", key)));
+        }
+        let meta = meta.unwrap();
+        match (meta._owner.as_deref(), owner) {
+            (Some(held), _) if owner != Some(held) => return Err(TrxError::ObjectError(format!("`{}` belongs to `{}`", key, held))),
+            (None, Some(owner)) => return Err(TrxError::ObjectError(format!("`{}` isn't held by `{}`", key, owner))),
+            _ => {}
+        }
+        let dlt = self.trx.delete(name.as_str(), meta_key(key));
+        if dlt.is_err() {
+            return Err(TrxError::ObjectError(dlt.err().unwrap().to_string()));
+        }
+        drop(swap);
+        retire_chunks(&self.trx, &self.objects, name.as_str(), meta.clone());
+        Ok(meta)
+    }
+
+    ///
+    // records `owner` in the meta, an object already held by someone else stays theirs. Answers whether it
+    // was claimed now, `false` when `owner` already held it
+    ///
+    pub fn claim_object(&self, db: &str, bucket: &str, key: &str, owner: &str) -> Result<bool, TrxError> {
+        // a put in between would have its meta overwritten by the version read here
+        let _swap = self.objects.swap.lock().unwrap();
+        let meta = self.object_meta(db, bucket, key);
+        if meta.is_none() {
+            return Err(TrxError::ObjectError(format!("object does not exist `{}`", key)));
+        }
+        let mut meta = meta.unwrap();
+        match meta._owner.as_deref() {
+            Some(held) if held.eq(owner) => return Ok(false),
+            Some(held) => return Err(TrxError::ObjectError(format!("`{}` belongs to `{}`", key, held))),
+            None => {}
+        }
+        meta._owner = Some(owner.to_string());
+        let put = self.trx.put(Self::bucket_cf(db, bucket).as_str(), meta_key(key), serde_json::to_vec(&meta).unwrap());
+        if put.is_err() {
+            return Err(TrxError::ObjectError(put.err().unwrap().to_string()));
+        }
+        Ok(true)
+    }
+
+    ///
+    // takes `owner` off the meta again, an object gone or held by someone else is left alone
+    ///
+    pub fn release_object(&self, db: &str, bucket: &str, key: &str, owner: &str) -> Result<(), TrxError> {
+        let _swap = self.objects.swap.lock().unwrap();
+        let meta = self.object_meta(db, bucket, key);
+        if meta.as_ref().map_or(true, |m| m._owner.as_deref() != Some(owner)) {
+            return Ok(());
+        }
+        let mut meta = meta.unwrap();
+        meta._owner = None;
+        let put = self.trx.put(Self::bucket_cf(db, bucket).as_str(), meta_key(key), serde_json::to_vec(&meta).unwrap());
+        if put.is_err() {
+            return Err(TrxError::ObjectError(put.err().unwrap().to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn list_objects(&self, db: &str, bucket: &str, prefix: &str, limit: Option<u64>) -> Vec<ObjectMeta> {
+        let name = Self::bucket_cf(db, bucket);
+        if !self.trx.has_cf(name.as_str()) {
+            return vec![];
+        }
+        // without its terminator a packed key is a byte prefix of every longer key
+        let mut start = pack_names(&[META, prefix]);
+        start.pop();
+        let limit = limit.map_or(usize::MAX, |l| l as usize);
+        let mut found = vec![];
+        if limit == 0 {
+            return found;
+        }
+        self.trx.each(name.as_str(), start, |_, m| {
+            let meta = serde_json::from_value::<ObjectMeta>(m);
+            if meta.is_ok() {
+                found.push(meta.unwrap());
+            }
+            found.len() < limit
+        });
+        found
+    }
+
+    pub fn drop_buckets(&self, db: &str) {
+        for bucket in self.bucket_names(db) {
+            let name = Self::bucket_cf(db, bucket.as_str());
+            if self.trx.has_cf(name.as_str()) {
+                let dropped = self.trx.truncate(name.as_str());
+                if dropped.is_err() {
+                    error!("drop bucket `{}` {}", name, dropped.err().unwrap());
+                    continue;
+                }
+            }
+            let _ = self.trx.delete(DB_SYS, Self::bucket_key(db, bucket.as_str()));
+        }
+    }
+
+    pub fn put_obj(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let key = body.get(Types::Key.as_str()).and_then(|k| k.as_str()).unwrap_or_default();
+        let data = STANDARD.decode(body.get(Types::Data.as_str()).and_then(|d| d.as_str()).unwrap_or_default());
+        if data.is_err() {
+            return DbRes { data: None, error: Some(TrxError::ObjectError(data.err().unwrap().to_string())) };
+        }
+        let content_type = body.get(Types::ContentType.as_str()).and_then(|c| c.as_str()).map(|c| c.to_string());
+        let metadata = body.get(Types::Metadata.as_str()).and_then(|m| m.as_object()).cloned().unwrap_or_default();
+        match self.put_object(db, bucket, key, &data.unwrap(), content_type, metadata) {
+            Ok(meta) => DbRes { data: Some(vec![meta.to_response()]), error: None },
+            Err(err) => DbRes { data: None, error: Some(err) }
+        }
+    }
+
+    pub fn get_obj(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let key = body.get(Types::Key.as_str()).and_then(|k| k.as_str()).unwrap_or_default();
+        let range = body.get(Types::Range.as_str()).and_then(|r| r.as_array()).cloned().unwrap_or_default();
+        let start = range.first().and_then(|s| s.as_u64()).unwrap_or(0);
+        let end = range.get(1).and_then(|e| e.as_u64());
+        match self.read_range(db, bucket, key, start, end) {
+            Ok((meta, data)) => {
+                let mut res = meta.to_response();
+                res.as_object_mut().unwrap().insert(Types::Data.as_str().to_string(), Value::from(STANDARD.encode(data)));
+                DbRes { data: Some(vec![res]), error: None }
+            }
+            Err(err) => DbRes { data: None, error: Some(err) }
+        }
+    }
+
+    pub fn delete_obj(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let key = body.get(Types::Key.as_str()).and_then(|k| k.as_str()).unwrap_or_default();
+        match self.remove_object(db, bucket, key) {
+            Ok(meta) => DbRes { data: Some(vec![meta.to_response()]), error: None },
+            Err(err) => DbRes { data: None, error: Some(err) }
+        }
+    }
+
+    pub fn list_obj(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let prefix = body.get(Types::Prefix.as_str()).and_then(|p| p.as_str()).unwrap_or_default();
+        let limit = body.get(Types::Limit.as_str()).and_then(|l| l.as_u64());
+        let objects = self.list_objects(db, bucket, prefix, limit)
+            .iter()
+            .map(|m| m.to_response())
+            .collect_vec();
+        DbRes { data: Some(objects), error: None }
+    }
+}
+
+pub fn object_target(cmd: &JqlCommand) -> (&str, &str, Map<String, Value>) {
+    let db = cmd._database.as_deref().unwrap_or_default();
+    let bucket = cmd._bucket.as_deref().unwrap_or_default();
+    let body = cmd._body.as_ref().and_then(|b| b.as_object()).cloned().unwrap_or_default();
+    (db, bucket, body)
+}
+
+impl MoeDb {
+    pub fn put_object(&self, db: &str, bucket: &str, key: &str, data: &[u8], content_type: Option<&str>, metadata: Map<String, Value>) -> Result<ObjectMeta, MoeDbError> {
+        self.exec.put_object(db, bucket, key, data, content_type.map(|c| c.to_string()), metadata)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn get_object(&self, db: &str, bucket: &str, key: &str) -> Result<(ObjectMeta, Vec<u8>), MoeDbError> {
+        self.exec.read_object(db, bucket, key)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn get_object_range(&self, db: &str, bucket: &str, key: &str, start: u64, end: Option<u64>) -> Result<(ObjectMeta, Vec<u8>), MoeDbError> {
+        self.exec.read_range(db, bucket, key, start, end)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn object_reader(&self, db: &str, bucket: &str, key: &str, start: u64, end: Option<u64>) -> Result<ObjectReader, MoeDbError> {
+        self.exec.object_reader(db, bucket, key, start, end)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn object_writer(&self, db: &str, bucket: &str, key: &str, content_type: Option<&str>, metadata: Map<String, Value>) -> Result<ObjectWriter, MoeDbError> {
+        self.exec.object_writer(db, bucket, key, content_type.map(|c| c.to_string()), metadata)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn delete_object(&self, db: &str, bucket: &str, key: &str) -> Result<ObjectMeta, MoeDbError> {
+        self.exec.remove_object(db, bucket, key)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn list_objects(&self, db: &str, bucket: &str, prefix: &str, limit: Option<u64>) -> Vec<ObjectMeta> {
+        self.exec.list_objects(db, bucket, prefix, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_checksums() {
+        assert_eq!(checksum(b"moedb").len(), 8);
+        assert_ne!(checksum(b"moedb"), checksum(b"moedc"));
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(b"moe");
+        hasher.update(b"db");
+        assert_eq!(format!("{:08x}", hasher.finalize()), checksum(b"moedb"));
+
+        assert!(chunk_key("g1", 1) < chunk_key("g1", 2));
+        let mut start = pack_names(&[META, "img/"]);
+        start.pop();
+        assert!(meta_key("img/a.png").starts_with(&start));
+        assert!(!meta_key("doc/a.png").starts_with(&start));
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(clamp_range(0, None, 10).unwrap(), (0, 10));
+        assert_eq!(clamp_range(4, Some(100), 10).unwrap(), (4, 10));
+        assert_eq!(clamp_range(0, None, 0).unwrap(), (0, 0));
+        assert!(clamp_range(10, None, 10).is_err());
+        assert!(clamp_range(5, Some(3), 10).is_err());
+    }
+
+    #[test]
+    fn generation_pins() {
+        let meta = |generation: &str| ObjectMeta {
+            _key: "a.txt".to_string(),
+            _size: 0,
+            _content_type: None,
+            _metadata: Map::new(),
+            _checksum: checksum(b""),
+            _chunk_size: OBJECT_CHUNK as u64,
+            _chunks: vec![],
+            _generation: generation.to_string(),
+            _updated_at: 1,
+            _owner: None,
+        };
+        let locks = ObjectLocks::default();
+        assert_eq!(locks.retire("b", meta("g0")).unwrap()._generation, "g0");
+
+        locks.pin("b", "g1");
+        locks.pin("b", "g1");
+        assert!(locks.retire("b", meta("g1")).is_none());
+        assert!(locks.unpin("b", "g1").is_none());
+        assert_eq!(locks.unpin("b", "g1").unwrap()._generation, "g1");
+        assert!(locks.pins.lock().unwrap().is_empty());
+
+        // read through without a newer version, nothing to drop
+        locks.pin("b", "g2");
+        assert!(locks.unpin("b", "g2").is_none());
+        assert!(locks.unpin("b", "g2").is_none());
+    }
+
+    #[test]
+    fn meta_response() {
+        let meta = ObjectMeta {
+            _key: "a.txt".to_string(),
+            _size: 5,
+            _content_type: Some("text/plain".to_string()),
+            _metadata: Map::new(),
+            _checksum: checksum(b"hello"),
+            _chunk_size: OBJECT_CHUNK as u64,
+            _chunks: vec![crc32fast::hash(b"hello")],
+            _generation: "g1".to_string(),
+            _updated_at: 1,
+            _owner: Some("users#u1".to_string()),
+        };
+        let res = meta.to_response();
+        assert_eq!(res["_owner"], "users#u1");
+        assert_eq!(res["_key"], "a.txt");
+        assert_eq!(res["_content_type"], "text/plain");
+        assert!(res.get("_chunks").is_none() && res.get("_generation").is_none() && res.get("_metadata").is_none());
+    }
+
+    #[test]
+    fn owned_objects_stay() {
+        let root = std::env::temp_dir().join(format!("moedb-obj-{}", unique_id()));
+        let db = MoeDb::new(crate::env::at(root.to_str().unwrap())).unwrap();
+        let res = db.execute(r#"{ "_action":"create-db", "_database":"files", "_body":{} }"#);
+        assert!(!res.error, "{}", res.message);
+        db.put_object("files", "photos", "lamp.png", b"lamp", None, Map::new()).unwrap();
+        assert!(db.exec.claim_object("files", "photos", "lamp.png", "items/i1").unwrap());
+
+        let refused = db.delete_object("files", "photos", "lamp.png");
+        assert!(refused.err().unwrap().to_string().contains("belongs to `items/i1`"));
+        assert!(db.exec.remove_held_object("files", "photos", "lamp.png", Some("items/i2")).is_err());
+        assert!(db.get_object("files", "photos", "lamp.png").is_ok(), "still there");
+
+        db.exec.release_object("files", "photos", "lamp.png", "items/i1").unwrap();
+        assert!(db.exec.remove_held_object("files", "photos", "lamp.png", Some("items/i1")).is_err(), "released already");
+        db.delete_object("files", "photos", "lamp.png").unwrap();
+        drop(db);
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -0,0 +1,46 @@
+use std::fmt::{Display, Formatter};
+use crate::hdrs::{JqlSchema, SchemaMode};
+
+/// !```
+/// "_mode": "strict"      unknown fields are rejected
+/// "_mode": "flexible"    declared fields are checked, anything else is stored as is (default)
+/// "_mode": "schemaless"  like flexible, but `_fields` may be left out
+/// !```
+
+impl Display for SchemaMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for SchemaMode {
+    fn from(value: &str) -> Self {
+        match value {
+            "strict" => SchemaMode::Strict,
+            "schemaless" => SchemaMode::Schemaless,
+            _ => SchemaMode::Flexible
+        }
+    }
+}
+
+impl SchemaMode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SchemaMode::Strict => "strict",
+            SchemaMode::Flexible => "flexible",
+            SchemaMode::Schemaless => "schemaless"
+        }
+    }
+}
+
+impl PartialEq for SchemaMode {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
+
+impl JqlSchema {
+    pub fn mode(&self) -> SchemaMode {
+        SchemaMode::from(self._mode.as_deref().unwrap_or_default())
+    }
+}
@@ -0,0 +1,285 @@
+use std::path::PathBuf;
+use serde_json::{json, Value};
+use crate::hdrs::{CsvOptions, Query, Response, ShellCommand, Types};
+use crate::var::{SHELL_HISTORY, SHELL_LIMIT};
+
+/// !```
+/// dbs                                  every database
+/// use <db>                             later commands run against <db>
+/// cols [db]                            collections of <db> or the one in use
+/// get <col> [body]                     a `get` on the db in use, the first SHELL_LIMIT documents without a body
+/// backup <target> [mode]               the `backup` action, see bak.rs
+/// import <file>                        JSON Lines export of `MoeDb::export_jsonl`
+/// export <file> [col]                  the db in use, or one collection of it
+/// import-csv <col> <file> [flags]
+/// export-csv <col> <file> [a,b.c] [flags]  the key and declared fields without columns
+/// { ... }                              any JQL, `_database` defaults to the db in use
+/// !```
+/// a line that leaves brackets open, a JQL document or the body of `get`, goes on over the next lines
+/// until they are closed. The csv flags fill `CsvOptions`: `--delimiter <c>` (`tab` for tabs),
+/// `--map <column>=<field>` as often as needed, `--date-format <fmt>`, `--datetime-format <fmt>` and
+/// `--array-delimiter <s>`. Values with spaces go in double quotes. The file commands need the db
+/// opened locally, everything else works against a server as well
+
+pub const HELP: &str = "dbs | use <db> | cols [db] | get <col> [body] | backup <target> [checkpoint|incremental]
+import <file> | export <file> [col] | import-csv <col> <file> [flags] | export-csv <col> <file> [a,b.c] [flags]
+csv flags: --delimiter <c|tab> --map <column>=<field> --date-format <fmt> --datetime-format <fmt> --array-delimiter <s>
+{ jql } | help | exit";
+
+pub fn history_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(SHELL_HISTORY))
+}
+
+///
+// input is done once every bracket outside of strings is closed, a JQL document or a command with a body
+///
+pub fn is_complete(buffer: &str) -> bool {
+    let mut depth = 0i64;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in buffer.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' | '[' => depth += 1,
+            '}' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+///
+// fills in the db in use when the statement doesn't name one, anything that isn't JSON is left to the parser
+///
+pub fn with_database(stmt: &str, current: Option<&str>) -> String {
+    let parsed = serde_json::from_str::<Value>(stmt);
+    if current.is_none() || parsed.is_err() {
+        return stmt.to_string();
+    }
+    let mut parsed = parsed.unwrap();
+    let named = parsed.get("_database").is_some() || parsed.get("_action").and_then(|a| a.as_str()) == Some("db-*");
+    if named || !parsed.is_object() {
+        return stmt.to_string();
+    }
+    parsed.as_object_mut().unwrap().insert("_database".to_string(), Value::from(current.unwrap()));
+    parsed.to_string()
+}
+
+///
+// whitespace separated words, double quotes keep a word with spaces together
+///
+fn words(line: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let (mut quoted, mut started) = (false, false);
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                started = true;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if started {
+                    words.push(std::mem::take(&mut word));
+                }
+                started = false;
+            }
+            c => {
+                word.push(c);
+                started = true;
+            }
+        }
+    }
+    if started {
+        words.push(word);
+    }
+    words
+}
+
+///
+// the words that aren't flags, and the `CsvOptions` the flags ask for
+///
+pub fn csv_flags(words: &[String]) -> Result<(Vec<String>, CsvOptions), String> {
+    let mut opts = CsvOptions::default();
+    let mut rest = vec![];
+    let mut words = words.iter();
+    while let Some(word) = words.next() {
+        if !word.starts_with("--") {
+            rest.push(word.clone());
+            continue;
+        }
+        let value = words.next().ok_or(format!("`{}` needs a value", word))?;
+        match word.as_str() {
+            "--delimiter" => opts.delimiter = match value.as_str() {
+                "tab" | "\\t" => b'\t',
+                v if v.len() == 1 => v.as_bytes()[0],
+                _ => return Err(format!("`--delimiter` is a single character, not `{}`", value))
+            },
+            "--map" => {
+                let (column, field) = value.split_once('=')
+                    .filter(|(c, f)| !c.is_empty() && !f.is_empty())
+                    .ok_or(format!("`--map` takes <column>=<field>, not `{}`", value))?;
+                opts.columns.insert(column.to_string(), field.to_string());
+            }
+            "--date-format" => opts.date_format = Some(value.clone()),
+            "--datetime-format" => opts.datetime_format = Some(value.clone()),
+            "--array-delimiter" => opts.array_delimiter = value.clone(),
+            _ => return Err(format!("unknown flag `{}`", word))
+        }
+    }
+    Ok((rest, opts))
+}
+
+fn csv_command(word: &str, rest: &str) -> ShellCommand {
+    let parsed = csv_flags(&words(rest));
+    if parsed.is_err() {
+        return ShellCommand::Invalid(format!("{} {}", word, parsed.err().unwrap()));
+    }
+    let (args, opts) = parsed.unwrap();
+    match (word, args.as_slice()) {
+        ("import-csv", [col, file]) => ShellCommand::ImportCsv(col.clone(), file.clone(), opts),
+        ("export-csv", [col, file]) | ("export-csv", [col, file, _]) => ShellCommand::ExportCsv(
+            col.clone(),
+            file.clone(),
+            args.get(2).map_or(vec![], |c| c.split(',').map(|c| c.to_string()).collect()),
+            opts
+        ),
+        _ => ShellCommand::Invalid(format!("usage: {} <col> <file>{} [flags]", word, if word.eq("export-csv") { " [a,b.c]" } else { "" }))
+    }
+}
+
+pub fn parse_shell(line: &str, current: Option<&str>) -> ShellCommand {
+    let line = line.trim();
+    if line.starts_with('{') {
+        return ShellCommand::Jql(with_database(line, current));
+    }
+    let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+    let args = rest.split_whitespace().collect::<Vec<&str>>();
+    let need_db = || ShellCommand::Invalid(format!("`{}` needs a database, pick one with `use <db>`", word));
+    match (word, args.as_slice()) {
+        ("help" | "?", _) => ShellCommand::Help,
+        ("exit" | "quit" | "\\q", _) => ShellCommand::Exit,
+        ("dbs", []) => ShellCommand::Jql(Query::databases().to_jql()),
+        ("use", [db]) => ShellCommand::Use(db.to_string()),
+        ("cols", [db]) => ShellCommand::Jql(Query::database(db).collections().to_jql()),
+        ("cols", []) => match current {
+            Some(db) => ShellCommand::Jql(Query::database(db).collections().to_jql()),
+            None => need_db()
+        },
+        ("get", [col, ..]) => {
+            if current.is_none() {
+                return need_db();
+            }
+            let body = rest[col.len()..].trim();
+            let body = match body.is_empty() {
+                true => Ok(json!({ Types::Limit.as_str(): SHELL_LIMIT })),
+                false => serde_json::from_str::<Value>(body)
+            };
+            match body {
+                Ok(body) => ShellCommand::Jql(json!({
+                    "_action": "get",
+                    "_database": current.unwrap(),
+                    "_collection": col,
+                    "_body": body
+                }).to_string()),
+                Err(err) => ShellCommand::Invalid(format!("body of `get` {}", err))
+            }
+        }
+        ("backup", [target]) | ("backup", [target, _]) => {
+            let mode = args.get(1).copied().unwrap_or("checkpoint");
+            ShellCommand::Jql(json!({
+                "_action": "backup",
+                "_body": { Types::Target.as_str(): target, Types::Mode.as_str(): mode }
+            }).to_string())
+        }
+        ("import", [file]) => ShellCommand::ImportJsonl(file.to_string()),
+        ("export", [file]) | ("export", [file, _]) => match current {
+            Some(_) => ShellCommand::ExportJsonl(file.to_string(), args.get(1).map(|c| c.to_string())),
+            None => need_db()
+        },
+        ("import-csv" | "export-csv", _) => match current {
+            Some(_) => csv_command(word, rest),
+            None => need_db()
+        },
+        _ => ShellCommand::Invalid(format!("unknown command `{}`, try `help`", line))
+    }
+}
+
+pub fn render(res: &Response) -> String {
+    if res.error {
+        return format!("error: {}\n({})", res.message, res.time_taken);
+    }
+    match res.data.as_ref() {
+        Some(data) if !data.is_empty() => format!(
+            "{}\n{} result{} in {}",
+            serde_json::to_string_pretty(data).unwrap(),
+            data.len(),
+            if data.len() == 1 { "" } else { "s" },
+            res.time_taken
+        ),
+        _ => format!("ok ({})", res.time_taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_lines() {
+        assert!(is_complete("dbs"));
+        assert!(!is_complete("{ \"_action\": \"get\","));
+        assert!(!is_complete("{ \"_body\": { \"s\": \"}}\""));
+        assert!(is_complete("{ \"_body\": { \"s\": \"}\\\"}\" } }"));
+        assert!(!is_complete("get users {"));
+        assert!(!is_complete("get users { \"_filter\": { \"tags\": [\"a\","));
+        assert!(is_complete("get users {\n  \"_filter\": { \"name\": \"{\" }\n}"));
+        assert!(matches!(parse_shell("get users {\n  \"_id\": \"u1\"\n}\n", Some("shop")), ShellCommand::Jql(_)));
+
+        assert_eq!(parse_shell("dbs", None), ShellCommand::Jql(Query::databases().to_jql()));
+        assert_eq!(parse_shell("use shop", None), ShellCommand::Use("shop".to_string()));
+        assert!(matches!(parse_shell("cols", None), ShellCommand::Invalid(_)));
+        assert_eq!(parse_shell("cols", Some("shop")), ShellCommand::Jql(Query::database("shop").collections().to_jql()));
+        assert_eq!(
+            parse_shell("export-csv users out.csv ids,address.city", Some("shop")),
+            ShellCommand::ExportCsv("users".to_string(), "out.csv".to_string(), vec!["ids".to_string(), "address.city".to_string()], CsvOptions::default())
+        );
+        let flagged = parse_shell(r#"import-csv users in.csv --delimiter ; --map "Customer ID=ids" --datetime-format "%d.%m.%Y %H:%M""#, Some("shop"));
+        let opts = CsvOptions {
+            delimiter: b';',
+            columns: [("Customer ID".to_string(), "ids".to_string())].into_iter().collect(),
+            datetime_format: Some("%d.%m.%Y %H:%M".to_string()),
+            ..CsvOptions::default()
+        };
+        assert_eq!(flagged, ShellCommand::ImportCsv("users".to_string(), "in.csv".to_string(), opts));
+        assert!(matches!(parse_shell("export-csv users out.csv --delimiter", Some("shop")), ShellCommand::Invalid(_)));
+        assert!(matches!(parse_shell("import-csv users in.csv --map ids", Some("shop")), ShellCommand::Invalid(_)));
+        assert!(matches!(parse_shell("import-csv users in.csv --sheet 2", Some("shop")), ShellCommand::Invalid(_)));
+        assert!(matches!(parse_shell("drop everything", Some("shop")), ShellCommand::Invalid(_)));
+
+        let get = parse_shell("get users {\"_id\": \"u1\"}", Some("shop"));
+        match get {
+            ShellCommand::Jql(stmt) => {
+                let stmt = serde_json::from_str::<Value>(&stmt).unwrap();
+                assert_eq!(stmt["_collection"], "users");
+                assert_eq!(stmt["_body"]["_id"], "u1");
+            }
+            other => panic!("{:?}", other)
+        }
+
+        let stmt = with_database(r#"{"_action":"col-*"}"#, Some("shop"));
+        assert_eq!(serde_json::from_str::<Value>(&stmt).unwrap()["_database"], "shop");
+        let listed = r#"{"_action":"db-*"}"#;
+        assert_eq!(with_database(listed, Some("shop")), listed);
+    }
+}
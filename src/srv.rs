@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::iter;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use std::io::Write;
+use axum::body::{Bytes, StreamBody};
+use axum::extract::{BodyStream, DefaultBodyLimit, Path, State};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response as HttpResponse};
+use axum::routing::{get, post, put};
+use axum::Router;
+use futures::channel::mpsc;
+use futures::{SinkExt, stream, StreamExt};
+use log::{error, info, trace};
+use serde_json::{Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+use crate::err::MoeDbError;
+use crate::func::{is_naming_ok, unique_id};
+use crate::hdrs::{ActionType, JqlCommand, LiveEventKind, LiveQuery, MoeDb, Response, Server, Types};
+use crate::var::{MAX_BODY_SIZE, MAX_JQL_SIZE, META_HEADER, STREAM_CHUNK};
+
+/// !```
+/// POST /jql          one JQL document in, one `Response` out (data is streamed in chunks)
+/// POST /jql/stream   newline delimited JQL documents in, newline delimited `Response`s out
+/// GET  /live         websocket, any JQL in, `Response` out. a `get` with `"_live": true` answers
+///                    with an `init` event carrying the `_live_id` and then keeps sending
+///                    `add` / `change` / `remove` events until `{"_cancel":"<_live_id>"}`. events honour
+///                    `_id`, `_from`/`_to`, `_filter` and `_project`, `_skip` and `_limit` are refused
+/// GET    /objects/<db>/<bucket>/<key>   the raw object, streamed, honours `Range: bytes=`
+/// PUT    /objects/<db>/<bucket>/<key>   raw body streamed into the object, `Content-Type` and
+///                                       `x-moedb-meta-<name>` headers become its metadata
+/// DELETE /objects/<db>/<bucket>/<key>
+/// PUT    /uploads/<db>/<bucket>/<upload_id>/<part>   raw body of one part of a multipart upload
+/// !```
+/// a JQL document is limited to `MAX_JQL_SIZE` bytes, on `/jql/stream` each line is. Upload parts are
+/// limited to `MAX_BODY_SIZE`, object bodies are streamed into the bucket
+
+impl Server {
+    pub fn new(db: MoeDb) -> Self {
+        Self {
+            db: Arc::new(db)
+        }
+    }
+
+    pub async fn serve(&self) -> Result<(), MoeDbError> {
+        let listen = self.db.exec.env.listen.clone();
+        let addr = listen.parse::<SocketAddr>();
+        if addr.is_err() {
+            let err = MoeDbError::ServerError(format!("invalid listen address `{}`", listen));
+            error!("{}",err);
+            return Err(err);
+        }
+        let bound = axum::Server::try_bind(&addr.unwrap());
+        if bound.is_err() {
+            let err = MoeDbError::ServerError(bound.err().unwrap().to_string());
+            error!("{}",err);
+            return Err(err);
+        }
+        info!("moedb server listening on {}", listen);
+
+        let app = Self::router(Arc::clone(&self.db));
+        let served = bound.unwrap().serve(app.into_make_service()).await;
+        if served.is_err() {
+            let err = MoeDbError::ServerError(served.err().unwrap().to_string());
+            error!("{}",err);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    pub fn router(db: Arc<MoeDb>) -> Router {
+        Router::new()
+            .route("/jql", post(Self::jql).layer(DefaultBodyLimit::max(MAX_JQL_SIZE)))
+            .route("/jql/stream", post(Self::jql_stream))
+            .route("/live", get(Self::live))
+            .route("/objects/:db/:bucket/*key", get(Self::get_object).put(Self::put_object).delete(Self::delete_object))
+            .route("/uploads/:db/:bucket/:upload/:part", put(Self::put_part))
+            .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+            .with_state(db)
+    }
+
+    async fn jql(State(db): State<Arc<MoeDb>>, body: String) -> HttpResponse {
+        let (res, err) = Self::run(db, body).await;
+        let body = StreamBody::new(stream::iter(Self::chunks(res).map(Ok::<Bytes, Infallible>)));
+        (Self::status(&err), [(header::CONTENT_TYPE, "application/json")], body).into_response()
+    }
+
+    async fn jql_stream(State(db): State<Arc<MoeDb>>, mut body: BodyStream) -> HttpResponse {
+        let (mut tx, rx) = mpsc::channel::<Result<Bytes, Infallible>>(STREAM_CHUNK);
+        tokio::spawn(async move {
+            let mut pending: Vec<u8> = vec![];
+            while let Some(chunk) = body.next().await {
+                if chunk.is_err() {
+                    error!("jql stream {}",chunk.err().unwrap());
+                    return;
+                }
+                pending.extend_from_slice(&chunk.unwrap());
+                while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+                    let line = pending.drain(..=pos).collect::<Vec<u8>>();
+                    if !Self::reply(&db, &mut tx, line).await {
+                        return;
+                    }
+                }
+                // the body limit doesn't reach a streamed body, a line that never ends would grow without bound
+                if pending.len() > MAX_JQL_SIZE {
+                    let res = Response {
+                        time_taken: "0ns".to_string(),
+                        error: true,
+                        message: MoeDbError::QueryError(format!("a statement is limited to {} bytes", MAX_JQL_SIZE)).to_string(),
+                        data: None,
+                    };
+                    let mut out = serde_json::to_vec(&res).unwrap();
+                    out.push(b'\n');
+                    let _ = tx.send(Ok(Bytes::from(out))).await;
+                    return;
+                }
+            }
+            Self::reply(&db, &mut tx, pending).await;
+        });
+        (StatusCode::OK, [(header::CONTENT_TYPE, "application/x-ndjson")], StreamBody::new(rx)).into_response()
+    }
+
+    async fn reply(db: &Arc<MoeDb>, tx: &mut mpsc::Sender<Result<Bytes, Infallible>>, line: Vec<u8>) -> bool {
+        let stmt = String::from_utf8_lossy(line.as_slice()).trim().to_string();
+        if stmt.is_empty() {
+            return true;
+        }
+        let (res, _) = Self::run(Arc::clone(db), stmt).await;
+        let mut out = serde_json::to_vec(&res).unwrap();
+        out.push(b'\n');
+        tx.send(Ok(Bytes::from(out))).await.is_ok()
+    }
+
+    async fn live(State(db): State<Arc<MoeDb>>, ws: WebSocketUpgrade) -> HttpResponse {
+        ws.on_upgrade(move |socket| Self::live_session(db, socket))
+    }
+
+    async fn live_session(db: Arc<MoeDb>, mut socket: WebSocket) {
+        // subscribe before any initial result is read so no write can slip in between
+        let mut feed = db.subscribe();
+        let mut queries: HashMap<String, LiveQuery> = HashMap::new();
+        loop {
+            let mut out = vec![];
+            tokio::select! {
+                msg = socket.recv() => {
+                    match msg {
+                        Some(Ok(Message::Text(text))) => out.push(Self::live_message(&db, &mut queries, text).await),
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue
+                    }
+                }
+                change = feed.recv() => {
+                    match change {
+                        Ok(change) => {
+                            for query in queries.values_mut() {
+                                if let Some(ev) = query.apply(&change) {
+                                    out.push(serde_json::to_string(&ev).unwrap());
+                                }
+                            }
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            let message = Some(format!("missed {} changes, re-issue the query", missed));
+                            for query in queries.values() {
+                                let ev = query.event(LiveEventKind::Error, None, None, message.clone());
+                                out.push(serde_json::to_string(&ev).unwrap());
+                            }
+                        }
+                        Err(RecvError::Closed) => break
+                    }
+                }
+            }
+            for text in out {
+                if socket.send(Message::Text(text)).await.is_err() {
+                    trace!("live session closed with {} queries", queries.len());
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn live_message(db: &Arc<MoeDb>, queries: &mut HashMap<String, LiveQuery>, text: String) -> String {
+        let raw = serde_json::from_str::<Value>(text.as_str()).unwrap_or(Value::Null);
+        if let Some(id) = raw.get("_cancel").and_then(|c| c.as_str()) {
+            return match queries.remove(id) {
+                Some(query) => serde_json::to_string(&query.event(LiveEventKind::Cancel, None, None, None)).unwrap(),
+                None => {
+                    let res = Response {
+                        time_taken: "0ns".to_string(),
+                        error: true,
+                        message: format!("no live query `{}`", id),
+                        data: None,
+                    };
+                    serde_json::to_string(&res).unwrap()
+                }
+            };
+        }
+
+        let cmd = serde_json::from_value::<JqlCommand>(raw.clone()).ok();
+        let is_live = cmd
+            .as_ref()
+            .map_or(false, |c| ActionType::from(c._action.as_str()) == ActionType::Get && c._live.unwrap_or(false));
+        if !is_live {
+            let (res, _) = Self::run(Arc::clone(db), text).await;
+            return serde_json::to_string(&res).unwrap();
+        }
+        // later events can't know what a page would hold
+        let body = raw.get("_body").cloned().unwrap_or(Value::Null);
+        if body.get(Types::Skip.as_str()).is_some() || body.get(Types::Limit.as_str()).is_some() {
+            let res = Response {
+                time_taken: "0ns".to_string(),
+                error: true,
+                message: "`_live` can't be combined with `_skip` or `_limit`".to_string(),
+                data: None,
+            };
+            return serde_json::to_string(&res).unwrap();
+        }
+        // the keys the client holds come from whole documents, `_project` is applied to them here
+        let mut whole = raw;
+        if let Some(body) = whole.get_mut("_body").and_then(|b| b.as_object_mut()) {
+            body.remove(Types::Project.as_str());
+        }
+        let (res, _) = Self::run(Arc::clone(db), whole.to_string()).await;
+        if res.error {
+            return serde_json::to_string(&res).unwrap();
+        }
+
+        let cmd = cmd.unwrap();
+        let ldb = Arc::clone(db);
+        let (database, collection) = (cmd._database.clone().unwrap(), cmd._collection.clone().unwrap());
+        let schema = tokio::task::spawn_blocking(move || ldb.exec.schema(database.as_str(), collection.as_str())).await;
+        if schema.is_err() || schema.as_ref().unwrap().is_none() {
+            let res = Response {
+                time_taken: res.time_taken,
+                error: true,
+                message: "collection schema is not readable".to_string(),
+                data: None,
+            };
+            return serde_json::to_string(&res).unwrap();
+        }
+
+        let docs = res.data.unwrap_or_default();
+        let query = LiveQuery::new(unique_id(), &cmd, &schema.unwrap().unwrap(), &docs);
+        let docs = docs.into_iter().map(|d| query.view(d)).collect();
+        let init = query.event(LiveEventKind::Init, None, Some(docs), None);
+        queries.insert(query.id.clone(), query);
+        serde_json::to_string(&init).unwrap()
+    }
+
+    async fn get_object(State(db): State<Arc<MoeDb>>, Path((database, bucket, key)): Path<(String, String, String)>, headers: HeaderMap) -> HttpResponse {
+        let elp = Instant::now();
+        if is_naming_ok(&Some(database.clone())).is_none() || is_naming_ok(&Some(bucket.clone())).is_none() {
+            return Self::object_res(elp, Err(MoeDbError::QueryError("check database & bucket name".to_string())));
+        }
+        let range = headers.get(header::RANGE).map(|r| r.to_str().unwrap_or_default().to_string());
+        let rdb = Arc::clone(&db);
+        let reader = tokio::task::spawn_blocking(move || {
+            let meta = rdb.exec.object_meta(database.as_str(), bucket.as_str(), key.as_str());
+            if meta.is_none() {
+                return Err(MoeDbError::TransactionError(format!("object does not exist `{}`", key)));
+            }
+            let size = meta.unwrap()._size;
+            let bounds = match range {
+                None => Some((0, size)),
+                Some(r) => Self::parse_range(r.as_str(), size)
+            };
+            if bounds.is_none() {
+                return Ok(Err(size));
+            }
+            let (start, end) = bounds.unwrap();
+            rdb.object_reader(database.as_str(), bucket.as_str(), key.as_str(), start, Some(end)).map(Ok)
+        }).await;
+        let reader = match reader {
+            Ok(Ok(Ok(reader))) => reader,
+            Ok(Ok(Err(size))) => {
+                return (StatusCode::RANGE_NOT_SATISFIABLE, [(header::CONTENT_RANGE, format!("bytes */{}", size))]).into_response();
+            }
+            Ok(Err(err)) => return Self::object_res(elp, Err(err)),
+            Err(er) => return Self::object_res(elp, Err(MoeDbError::ServerError(er.to_string())))
+        };
+
+        let meta = reader.meta.clone();
+        let (start, end) = (reader.start, reader.end);
+        let partial = start > 0 || end < meta._size;
+        let (mut tx, rx) = mpsc::channel::<Result<Bytes, MoeDbError>>(2);
+        tokio::task::spawn_blocking(move || {
+            for chunk in reader {
+                let chunk = chunk
+                    .map(Bytes::from)
+                    .map_err(|e| MoeDbError::TransactionError(e.to_string()));
+                let failed = chunk.is_err();
+                if futures::executor::block_on(tx.send(chunk)).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let mut res = StreamBody::new(rx).into_response();
+        *res.status_mut() = if partial { StatusCode::PARTIAL_CONTENT } else { StatusCode::OK };
+        let headers = res.headers_mut();
+        let content_type = meta._content_type.clone().unwrap_or("application/octet-stream".to_string());
+        headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap_or(header::HeaderValue::from_static("application/octet-stream")));
+        headers.insert(header::CONTENT_LENGTH, header::HeaderValue::from(end - start));
+        headers.insert(header::ACCEPT_RANGES, header::HeaderValue::from_static("bytes"));
+        headers.insert(header::ETAG, format!("\"{}\"", meta._checksum).parse().unwrap());
+        if partial {
+            headers.insert(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end - 1, meta._size).parse().unwrap());
+        }
+        res
+    }
+
+    async fn put_object(State(db): State<Arc<MoeDb>>, Path((database, bucket, key)): Path<(String, String, String)>, headers: HeaderMap, mut body: BodyStream) -> HttpResponse {
+        let elp = Instant::now();
+        if is_naming_ok(&Some(database.clone())).is_none() || is_naming_ok(&Some(bucket.clone())).is_none() {
+            return Self::object_res(elp, Err(MoeDbError::QueryError("check database & bucket name".to_string())));
+        }
+        let content_type = headers.get(header::CONTENT_TYPE).and_then(|c| c.to_str().ok()).map(|c| c.to_string());
+        let mut metadata = Map::new();
+        for (name, value) in headers.iter() {
+            let field = name.as_str().strip_prefix(META_HEADER);
+            if field.is_some() && value.to_str().is_ok() {
+                metadata.insert(field.unwrap().to_string(), Value::from(value.to_str().unwrap()));
+            }
+        }
+        // the body is handed over chunk by chunk, the blocking side writes it into the bucket as it comes
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Result<Bytes, String>>(2);
+        let written = tokio::task::spawn_blocking(move || {
+            let writer = db.object_writer(database.as_str(), bucket.as_str(), key.as_str(), content_type.as_deref(), metadata);
+            if writer.is_err() {
+                return Err(writer.err().unwrap());
+            }
+            let mut writer = writer.unwrap();
+            while let Some(chunk) = rx.blocking_recv() {
+                if chunk.is_err() {
+                    return Err(MoeDbError::ServerError(chunk.err().unwrap()));
+                }
+                let put = writer.write_all(&chunk.unwrap());
+                if put.is_err() {
+                    return Err(MoeDbError::TransactionError(put.err().unwrap().to_string()));
+                }
+            }
+            writer.finish().map_err(|e| MoeDbError::TransactionError(e.to_string()))
+        });
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| e.to_string());
+            let failed = chunk.is_err();
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+        drop(tx);
+        match written.await {
+            Ok(meta) => Self::object_res(elp, meta.map(|m| vec![m.to_response()])),
+            Err(er) => Self::object_res(elp, Err(MoeDbError::ServerError(er.to_string())))
+        }
+    }
+
+    async fn delete_object(State(db): State<Arc<MoeDb>>, Path((database, bucket, key)): Path<(String, String, String)>) -> HttpResponse {
+        let elp = Instant::now();
+        if is_naming_ok(&Some(database.clone())).is_none() || is_naming_ok(&Some(bucket.clone())).is_none() {
+            return Self::object_res(elp, Err(MoeDbError::QueryError("check database & bucket name".to_string())));
+        }
+        let deleted = tokio::task::spawn_blocking(move || db.delete_object(database.as_str(), bucket.as_str(), key.as_str())).await;
+        match deleted {
+            Ok(meta) => Self::object_res(elp, meta.map(|m| vec![m.to_response()])),
+            Err(er) => Self::object_res(elp, Err(MoeDbError::ServerError(er.to_string())))
+        }
+    }
+
+    async fn put_part(State(db): State<Arc<MoeDb>>, Path((database, bucket, upload, part)): Path<(String, String, String, u32)>, body: Bytes) -> HttpResponse {
+        let elp = Instant::now();
+        if is_naming_ok(&Some(database.clone())).is_none() || is_naming_ok(&Some(bucket.clone())).is_none() {
+            return Self::object_res(elp, Err(MoeDbError::QueryError("check database & bucket name".to_string())));
+        }
+        let stored = tokio::task::spawn_blocking(move || db.put_part(database.as_str(), bucket.as_str(), upload.as_str(), part, &body)).await;
+        match stored {
+            Ok(part) => Self::object_res(elp, part.map(|p| vec![serde_json::to_value(p).unwrap()])),
+            Err(er) => Self::object_res(elp, Err(MoeDbError::ServerError(er.to_string())))
+        }
+    }
+
+    fn object_res(elp: Instant, res: Result<Vec<Value>, MoeDbError>) -> HttpResponse {
+        let err = res.as_ref().err().cloned();
+        let response = Response {
+            time_taken: format!("{:?}", elp.elapsed()),
+            error: err.is_some(),
+            message: err.as_ref().map_or("".to_string(), |e| e.to_string()),
+            data: res.ok(),
+        };
+        (Self::status(&err), [(header::CONTENT_TYPE, "application/json")], serde_json::to_string(&response).unwrap()).into_response()
+    }
+
+    ///
+    // one `bytes=` range as `start..end` (end exclusive), `None` when it can't be served.
+    // `bytes=0-99`, `bytes=100-` and the last bytes `bytes=-100`
+    ///
+    pub fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+        let spec = range.trim().strip_prefix("bytes=")?;
+        if spec.contains(',') {
+            return None;
+        }
+        let (from, to) = spec.split_once('-')?;
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() {
+            let last = to.parse::<u64>().ok()?;
+            if last == 0 || size == 0 {
+                return None;
+            }
+            return Some((size - last.min(size), size));
+        }
+        let start = from.parse::<u64>().ok()?;
+        let end = match to.is_empty() {
+            true => size,
+            false => to.parse::<u64>().ok()?.saturating_add(1).min(size)
+        };
+        if start >= size || start >= end {
+            return None;
+        }
+        Some((start, end))
+    }
+
+    /// RocksDB calls block, so every statement goes through tokio's blocking pool
+    pub async fn run(db: Arc<MoeDb>, stmt: String) -> (Response, Option<MoeDbError>) {
+        let elp = Instant::now();
+        let res = tokio::task::spawn_blocking(move || db.execute_with_error(stmt.as_str())).await;
+        match res {
+            Ok(executed) => executed,
+            Err(er) => {
+                let err = MoeDbError::ServerError(er.to_string());
+                error!("{}",err);
+                let response = Response {
+                    time_taken: format!("{:?}", elp.elapsed()),
+                    error: true,
+                    message: err.to_string(),
+                    data: None,
+                };
+                (response, Some(err))
+            }
+        }
+    }
+
+    fn status(err: &Option<MoeDbError>) -> StatusCode {
+        match err {
+            None => StatusCode::OK,
+            Some(MoeDbError::QueryError(_)) => StatusCode::BAD_REQUEST,
+            Some(MoeDbError::TransactionError(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            Some(_) => StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    ///
+    // serializes a `Response` lazily, `STREAM_CHUNK` documents at a time, so the serialized body is never
+    // whole in memory. The documents are, `get_doc` collects (and sorts) them before anything is sent
+    ///
+    pub fn chunks(res: Response) -> impl Iterator<Item = Bytes> + Send {
+        let mut head = format!(
+            r#"{{"time_taken":{},"error":{},"message":{},"data":"#,
+            serde_json::to_string(&res.time_taken).unwrap(),
+            res.error,
+            serde_json::to_string(&res.message).unwrap()
+        );
+        let tail = match res.data.as_ref() {
+            None => {
+                head.push_str("null");
+                "}"
+            }
+            Some(_) => {
+                head.push('[');
+                "]}"
+            }
+        };
+        trace!("streaming response of {} documents", res.data.as_ref().map_or(0, |d| d.len()));
+
+        let mut items = res.data.map(|d| d.into_iter());
+        let mut first = true;
+        let body = iter::from_fn(move || {
+            let items = items.as_mut()?;
+            let mut out = vec![];
+            for item in items.by_ref().take(STREAM_CHUNK) {
+                if !first {
+                    out.push(b',');
+                }
+                first = false;
+                serde_json::to_writer(&mut out, &item).unwrap();
+            }
+            if out.is_empty() {
+                return None;
+            }
+            Some(Bytes::from(out))
+        });
+        iter::once(Bytes::from(head))
+            .chain(body)
+            .chain(iter::once(Bytes::from(tail)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use super::*;
+
+    fn collect(res: Response) -> Response {
+        let raw = Server::chunks(res).fold(vec![], |mut acc, b| {
+            acc.extend_from_slice(&b);
+            acc
+        });
+        serde_json::from_slice::<Response>(raw.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn chunked_response() {
+        let items = (0..(STREAM_CHUNK * 2 + 7))
+            .map(|i| serde_json::json!({ "ids": i, "hello": ["a\"b", "c"] }))
+            .collect::<Vec<Value>>();
+        let res = Response {
+            time_taken: "1ms".to_string(),
+            error: false,
+            message: "".to_string(),
+            data: Some(items.clone()),
+        };
+        let parsed = collect(res);
+        assert!(!parsed.error);
+        assert_eq!(parsed.data.unwrap(), items);
+    }
+
+    #[test]
+    fn byte_ranges() {
+        assert_eq!(Server::parse_range("bytes=0-99", 1000), Some((0, 100)));
+        assert_eq!(Server::parse_range("bytes=900-", 1000), Some((900, 1000)));
+        assert_eq!(Server::parse_range("bytes=-100", 1000), Some((900, 1000)));
+        assert_eq!(Server::parse_range("bytes=-5000", 1000), Some((0, 1000)));
+        assert_eq!(Server::parse_range("bytes=990-2000", 1000), Some((990, 1000)));
+        assert_eq!(Server::parse_range("bytes=1000-", 1000), None);
+        assert_eq!(Server::parse_range("bytes=5-2", 1000), None);
+        assert_eq!(Server::parse_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(Server::parse_range("items=0-1", 1000), None);
+    }
+
+    #[test]
+    fn chunked_error_response() {
+        let res = Response {
+            time_taken: "1ms".to_string(),
+            error: true,
+            message: "invalid query `unknown query`".to_string(),
+            data: None,
+        };
+        let parsed = collect(res);
+        assert!(parsed.error);
+        assert!(parsed.data.is_none());
+        assert_eq!(parsed.message, "invalid query `unknown query`");
+    }
+}
@@ -0,0 +1,32 @@
+use std::fmt::{Display, Formatter};
+use crate::hdrs::SortOrder;
+
+impl Display for SortOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for SortOrder {
+    fn from(value: &str) -> Self {
+        match value {
+            "desc" => SortOrder::Desc,
+            _ => SortOrder::Asc
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc"
+        }
+    }
+}
+
+impl PartialEq for SortOrder {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
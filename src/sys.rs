@@ -1,9 +1,11 @@
 use itertools::Itertools;
 use log::{error, info, warn};
+use serde_json::Value;
 use crate::err::TrxError;
-use crate::hdrs::{DbRes, Exec, JqlCommand, Types};
-use crate::util::{key_merger, ksm_db};
-use crate::var::{DB_PREFIX, DB_SYS};
+use crate::hdrs::{DbRes, Exec, JqlCommand, JqlSchema, TKey, Types};
+use crate::tup::{pack_names, unpack_names};
+use crate::util::key_merger;
+use crate::var::{COL_PREFIX, DB_PREFIX, DB_SYS};
 
 impl Exec {
     pub fn authenticate(&self, username: &str, password: &str) -> bool {
@@ -13,26 +15,21 @@ impl Exec {
     pub fn new_db(&self, cmd: &JqlCommand) -> DbRes {
         let wrp_db = cmd._database.as_ref().unwrap();
         let db = wrp_db.as_str();
-        let wrp_key = key_merger(vec![DB_PREFIX.to_string(), db.to_string()]);
-        if wrp_key.is_some() {
-            let key = wrp_key.unwrap();
-            let exi = self.trx.get(DB_SYS, key.as_bytes().to_vec());
-            return if exi.is_some() {
-                DbRes { data: None, error: Some(TrxError::CreateDbError(format!("db already exists `{}`", db))) }
-            } else {
-                let created = self.trx.put(
-                    DB_SYS,
-                    key.as_bytes().to_vec(),
-                    serde_json::to_string::<bool>(&true).unwrap().as_bytes().to_vec()
-                );
-                if created.is_ok() {
-                    DbRes { data: None, error: None }
-                } else {
-                    DbRes { data: None, error: Some(TrxError::CreateDbError(created.err().unwrap().to_string())) }
-                }
-            }
+        let key = Self::db_key(db);
+        let exi = self.trx.get(DB_SYS, key.clone());
+        if exi.is_some() {
+            return DbRes { data: None, error: Some(TrxError::CreateDbError(format!("db already exists `{}`", db))) };
+        }
+        let created = self.trx.put(
+            DB_SYS,
+            key,
+            serde_json::to_string::<bool>(&true).unwrap().as_bytes().to_vec()
+        );
+        if created.is_ok() {
+            DbRes { data: None, error: None }
+        } else {
+            DbRes { data: None, error: Some(TrxError::CreateDbError(created.err().unwrap().to_string())) }
         }
-        DbRes { data: None, error: Some(TrxError::CreateCollectionError("check database name".to_string())) }
     }
 
     pub fn new_col(&self, cmd: &JqlCommand) -> DbRes {
@@ -50,18 +47,23 @@ impl Exec {
 
         let wrp_key = key_merger(vec![db.to_string(), col.to_string()]);
         if wrp_key.is_some() {
-            let key = wrp_key.unwrap();
-            let exi = self.trx.get(DB_SYS,key.as_bytes().to_vec());
+            let name = wrp_key.unwrap();
+            let key = Self::col_key(db, col);
+            let exi = self.trx.get(DB_SYS, key.clone());
 
             return if exi.is_some() {
                 DbRes { data: None, error: Some(TrxError::CreateCollectionError(format!("collection already exists `{}`", col))) }
             } else {
-                let cf_created = self.trx.create_cf(key.as_str());
+                let storage = serde_json::from_value::<JqlSchema>(wrp_col.clone()).ok().and_then(|s| s._storage);
+                self.trx.tuning.set_cf(name.as_str(), storage.as_ref());
+                let cf_created = self.trx.create_cf(name.as_str());
                 return if cf_created.is_ok() {
+                    let mut schema = wrp_col.clone();
+                    schema.as_object_mut().unwrap().insert(Types::Version.as_str().to_string(), Value::from(1));
                     let created = self.trx.put(
                         DB_SYS,
-                        key.as_bytes().to_vec(),
-                        serde_json::to_string(wrp_col).unwrap().as_bytes().to_vec()
+                        key,
+                        serde_json::to_string(&schema).unwrap().as_bytes().to_vec()
                     );
                     if created.is_ok() {
                         DbRes { data: None, error: None }
@@ -83,6 +85,7 @@ impl Exec {
             if !self.trx.cfs().contains(&key) {
                 return DbRes { data: None, error: None };
             }
+            self.drop_col_attachments(cmd._database.as_ref().unwrap(), cmd._collection.as_ref().unwrap(), key.as_str());
             let dropped = self.trx.truncate(key.as_str());
             return if dropped.is_ok() {
                 DbRes { data: None, error: None }
@@ -100,7 +103,9 @@ impl Exec {
         }
         let mut is_err = false;
         let unwrp_key = wrp_key.unwrap();
-        let key = unwrp_key.as_str().as_bytes().to_vec();
+        let db = cmd._database.as_ref().unwrap();
+        let col = cmd._collection.as_ref().unwrap();
+        let key = Self::col_key(db, col);
         let exi = self.trx.get(DB_SYS, key.clone());
         if exi.is_none() {
             return DbRes { data: None, error: Some(TrxError::DropCollectionError("collection does not exist".to_string())) };
@@ -108,6 +113,7 @@ impl Exec {
         let mut dlt = Ok(());
         let dropped = self.trx.cfs().contains(&unwrp_key);
         if dropped {
+            self.drop_col_attachments(db, col, unwrp_key.as_str());
             dlt = self.trx.delete(DB_SYS,key.clone());
             is_err = dlt.is_err();
         } else {
@@ -117,6 +123,9 @@ impl Exec {
                 is_err = dlt.is_err();
             }
         }
+        if !is_err {
+            let _ = self.trx.delete(DB_SYS, Self::sequence_key(db, col));
+        }
         if is_err {
             DbRes { data: None, error: Some(TrxError::DropCollectionError(dlt.err().unwrap().to_string())) }
         } else {
@@ -127,33 +136,39 @@ impl Exec {
     pub fn drop_db(&self, cmd: &JqlCommand) -> DbRes {
         let mut dropped = false;
         let wrp_db = cmd._database.as_ref().unwrap();
-        let wrp_prefix = key_merger(vec![DB_PREFIX.to_string(),wrp_db.clone().to_string()]).unwrap();
-        let cfs = self.col_list(&cmd)
+        let cols = self.col_list(&cmd)
             .data
             .unwrap()
             .into_iter()
             .map(|p|p.as_str().unwrap().to_string())
             .collect_vec();
 
-        if cfs.is_empty() {
+        if cols.is_empty() {
             warn!("no cfs to drop {:?}",cmd);
             dropped = true;
         }
 
-        for cf in cfs {
-            let chk = self.trx.truncate(cf.as_str());
+        let cfs = self.trx.cfs();
+        for col in cols {
+            let cf = key_merger(vec![wrp_db.clone(), col.clone()]).unwrap();
+            let chk = match cfs.contains(&cf) {
+                true => self.trx.truncate(cf.as_str()),
+                false => Ok(())
+            };
             if chk.is_err() {
                 error!("drop_db {}",chk.err().unwrap());
             } else {
-                let chk = self.trx.delete(DB_SYS, cf.as_str().as_bytes().to_vec());
+                let chk = self.trx.delete(DB_SYS, Self::col_key(wrp_db, col.as_str()));
                 if chk.is_err() {
                     error!("delete from sys {}",chk.err().unwrap());
                 }
+                let _ = self.trx.delete(DB_SYS, Self::sequence_key(wrp_db, col.as_str()));
                 dropped = true;
             }
         }
+        self.drop_buckets(wrp_db);
         if dropped {
-            let dlt = self.trx.delete(DB_SYS,wrp_prefix.as_bytes().to_vec());
+            let dlt = self.trx.delete(DB_SYS, Self::db_key(wrp_db));
             if dlt.is_err() {
                 return DbRes { data: None, error: Some(TrxError::DropDbError(dlt.err().unwrap().to_string())) };
             }
@@ -162,28 +177,49 @@ impl Exec {
     }
 
     pub fn db_list(&self) -> DbRes {
-        let key_prefix = key_merger(vec![DB_PREFIX.to_string(),"".to_string()]).unwrap().as_bytes().to_vec();
-        let dbs = self.trx.through(DB_SYS,key_prefix);
+        let dbs = self.trx.entries(DB_SYS, pack_names(&[DB_PREFIX]));
         let mut items = vec![];
         for db in dbs {
-            info!("{} {:?}",db.0.as_str(),db.1);
-            items.push(serde_json::to_value(ksm_db(db.0).unwrap()).unwrap());
+            let names = unpack_names(&db.0).unwrap_or_default();
+            info!("{:?} {:?}",names,db.1);
+            if names.len() == 2 {
+                items.push(Value::String(names[1].clone()));
+            }
         }
         DbRes { data: Some(items), error: None }
     }
 
     pub fn col_list(&self, cmd: &JqlCommand) -> DbRes {
         let wrp_db = cmd._database.as_ref().unwrap();
-        let prefix = key_merger(vec![wrp_db.clone(),"".to_string()]).unwrap();
-        let cfs = self.trx.cfs()
+        let cols = self.col_names(wrp_db)
             .into_iter()
-            .filter(|p|p.starts_with(&prefix))
-            .map(|p|serde_json::to_value(ksm_db(p)).unwrap())
+            .map(Value::String)
             .collect_vec();
-        DbRes { data: Some(cfs), error: None }
+        DbRes { data: Some(cols), error: None }
+    }
+
+    pub fn col_names(&self, db: &str) -> Vec<String> {
+        self.trx.entries(DB_SYS, pack_names(&[COL_PREFIX, db]))
+            .into_iter()
+            .filter_map(|(key, _)| unpack_names(&key))
+            .filter(|names| names.len() == 3)
+            .map(|mut names| names.remove(2))
+            .collect_vec()
+    }
+
+    pub fn db_key(db: &str) -> TKey {
+        pack_names(&[DB_PREFIX, db])
+    }
+
+    pub fn col_key(db: &str, col: &str) -> TKey {
+        pack_names(&[COL_PREFIX, db, col])
     }
 
-    fn db_col_merged(cmd: &JqlCommand) -> Option<String> {
+    ///
+    // column family of a collection. Names can't hold `#` so the joined form is unambiguous, system keys
+    // and document keys are packed instead
+    ///
+    pub fn db_col_merged(cmd: &JqlCommand) -> Option<String> {
         let wrp_db = cmd._database.as_ref().unwrap();
         let wrp_col = cmd._collection.as_ref().unwrap();
 
@@ -0,0 +1,333 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use chrono::{NaiveDate, NaiveDateTime};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use serde_json::{Map, Value};
+use crate::doc::{get_path, set_path};
+use crate::err::{MoeDbError, TrxError};
+use crate::func::unique_id;
+use crate::hdrs::{ActionType, CsvOptions, DataTypes, Exec, ExportReport, ImportReport, JqlCommand, JqlSchema, MoeDb};
+use crate::var::{CSV_ARRAY_DELIMITER, IMPORT_BATCH};
+
+/// !```
+/// ids,name,born,active,tags
+/// u1,moe,1990-04-01,yes,admin|ops
+/// !```
+/// on import every cell is converted by the `_declare` of the field its column maps to: numbers are
+/// parsed, `true/false/yes/no/1/0` become booleans, arrays are split on `array_delimiter` unless the
+/// cell is a JSON array, `object`, `json`, `geopoint` and `blob` cells hold JSON. Export writes an
+/// array as JSON when splitting wouldn't give its elements back (empty, padded or holding the
+/// delimiter). Dates are read with `date_format` / `datetime_format` when set. Empty cells leave the
+/// field out so `_default` and `_optional` apply. Export runs a `get` and writes the chosen columns
+/// (dotted paths) the same way back, headed by the column mapped to them
+
+const DATE: &str = "%Y-%m-%d";
+const DATETIME: &str = "%Y-%m-%d %H:%M:%S";
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            array_delimiter: CSV_ARRAY_DELIMITER.to_string(),
+            columns: BTreeMap::new(),
+            date_format: None,
+            datetime_format: None,
+        }
+    }
+}
+
+fn element_of(declare: &DataTypes) -> Option<DataTypes> {
+    match declare {
+        DataTypes::ArrayOfString => Some(DataTypes::String),
+        DataTypes::ArrayOfInt => Some(DataTypes::Int),
+        DataTypes::ArrayOfUint => Some(DataTypes::Uint),
+        DataTypes::ArrayOfFloat => Some(DataTypes::Float),
+        DataTypes::ArrayOfBoolean => Some(DataTypes::Boolean),
+        _ => None
+    }
+}
+
+///
+// the column `columns` maps to `field`, so an export reads back with the same options
+///
+pub fn header_of(field: &str, opts: &CsvOptions) -> String {
+    opts.columns
+        .iter()
+        .find(|(_, f)| f.as_str().eq(field))
+        .map_or(field.to_string(), |(c, _)| c.clone())
+}
+
+///
+// one cell as the declared type wants it, undeclared columns stay text
+///
+pub fn convert_cell(declare: Option<&DataTypes>, cell: &str, opts: &CsvOptions) -> Result<Value, String> {
+    let declare = match declare {
+        None => return Ok(Value::from(cell)),
+        Some(declare) => declare
+    };
+    let trimmed = cell.trim();
+    if let Some(element) = element_of(declare) {
+        if trimmed.starts_with('[') {
+            if let Ok(Value::Array(items)) = serde_json::from_str::<Value>(trimmed) {
+                return Ok(Value::Array(items));
+            }
+        }
+        return trimmed
+            .split(opts.array_delimiter.as_str())
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .map(|c| convert_cell(Some(&element), c, opts))
+            .collect::<Result<Vec<Value>, String>>()
+            .map(Value::Array);
+    }
+    match declare {
+        DataTypes::Int => trimmed.parse::<i64>().map(Value::from).map_err(|_| format!("`{}` is not an int", cell)),
+        DataTypes::Uint => trimmed.parse::<u64>().map(Value::from).map_err(|_| format!("`{}` is not a uint", cell)),
+        DataTypes::Float => match trimmed.parse::<f64>() {
+            Ok(f) if f.is_finite() => Ok(Value::from(f)),
+            _ => Err(format!("`{}` is not a float", cell))
+        },
+        DataTypes::Boolean => match trimmed.to_lowercase().as_str() {
+            "true" | "yes" | "y" | "1" => Ok(Value::Bool(true)),
+            "false" | "no" | "n" | "0" => Ok(Value::Bool(false)),
+            _ => Err(format!("`{}` is not a boolean", cell))
+        },
+        DataTypes::Date => match opts.date_format.as_ref() {
+            Some(format) => NaiveDate::parse_from_str(trimmed, format)
+                .map(|d| Value::from(d.format(DATE).to_string()))
+                .map_err(|e| format!("`{}` {}", cell, e)),
+            None => Ok(Value::from(trimmed))
+        },
+        DataTypes::DateTime => {
+            let format = opts.datetime_format.as_deref().unwrap_or(DATETIME);
+            NaiveDateTime::parse_from_str(trimmed, format)
+                .or_else(|e| match opts.datetime_format {
+                    None => NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S"),
+                    Some(_) => Err(e)
+                })
+                .map(|d| Value::from(d.format(DATETIME).to_string()))
+                .map_err(|e| format!("`{}` {}", cell, e))
+        }
+        DataTypes::Object | DataTypes::ArrayOfObject | DataTypes::Json | DataTypes::GeoPoint | DataTypes::Blob => {
+            serde_json::from_str::<Value>(trimmed).map_err(|e| format!("`{}` {}", cell, e))
+        }
+        _ => Ok(Value::from(cell))
+    }
+}
+
+///
+// what `convert_cell` splits back into the same elements
+///
+fn is_joinable(cells: &[String], opts: &CsvOptions) -> bool {
+    !cells.is_empty()
+        && !cells[0].starts_with('[')
+        && cells.iter().all(|c| !c.is_empty() && c.trim().eq(c) && !c.contains(opts.array_delimiter.as_str()))
+}
+
+///
+// the reverse of `convert_cell`, arrays of plain values are joined when that reads back the same, anything else is JSON
+///
+pub fn cell_of(declare: Option<&DataTypes>, value: Option<&Value>, opts: &CsvOptions) -> String {
+    let value = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(value) => value
+    };
+    match (declare, value) {
+        (Some(DataTypes::Date), Value::String(s)) if opts.date_format.is_some() => NaiveDate::parse_from_str(s, DATE)
+            .map_or(s.clone(), |d| d.format(opts.date_format.as_ref().unwrap()).to_string()),
+        (Some(DataTypes::DateTime), Value::String(s)) if opts.datetime_format.is_some() => NaiveDateTime::parse_from_str(s, DATETIME)
+            .map_or(s.clone(), |d| d.format(opts.datetime_format.as_ref().unwrap()).to_string()),
+        (_, Value::String(s)) => s.clone(),
+        (_, Value::Array(items)) if items.iter().all(|i| !i.is_array() && !i.is_object()) => {
+            let cells = items.iter().map(|i| cell_of(None, Some(i), opts)).collect::<Vec<String>>();
+            match is_joinable(&cells, opts) {
+                true => cells.join(opts.array_delimiter.as_str()),
+                false => value.to_string()
+            }
+        }
+        (_, other) => other.to_string()
+    }
+}
+
+pub fn row_doc(schema: &JqlSchema, fields: &[String], record: &StringRecord, opts: &CsvOptions) -> Result<Value, String> {
+    let mut doc = Map::new();
+    for (field, cell) in fields.iter().zip(record.iter()) {
+        if cell.is_empty() {
+            continue;
+        }
+        let declare = schema.declare_of(field.as_str());
+        let value = convert_cell(declare.as_ref(), cell, opts);
+        if value.is_err() {
+            return Err(format!("column `{}` {}", field, value.err().unwrap()));
+        }
+        set_path(&mut doc, field.as_str(), value.unwrap());
+    }
+    Ok(Value::Object(doc))
+}
+
+impl Exec {
+    pub fn import_csv<R: Read>(&self, input: R, db: &str, col: &str, opts: &CsvOptions) -> Result<ImportReport, TrxError> {
+        let schema = self.schema(db, col);
+        if schema.is_none() {
+            return Err(TrxError::ImportError(format!("collection does not exist `{}`", col)));
+        }
+        let schema = schema.unwrap();
+        let mut reader = ReaderBuilder::new().delimiter(opts.delimiter).from_reader(input);
+        let headers = reader.headers().cloned();
+        if headers.is_err() {
+            return Err(TrxError::ImportError(headers.err().unwrap().to_string()));
+        }
+        let fields = headers
+            .unwrap()
+            .iter()
+            .map(|h| opts.columns.get(h).cloned().unwrap_or(h.to_string()))
+            .collect::<Vec<String>>();
+
+        let target = Some((db.to_string(), col.to_string()));
+        let mut report = ImportReport::default();
+        report._collections.push(format!("{}.{}", db, col));
+        let mut batch = vec![];
+        for (idx, record) in reader.records().enumerate() {
+            // the header is line 1
+            let fallback = idx as u64 + 2;
+            if record.is_err() {
+                let err = record.err().unwrap();
+                report.reject(err.position().map_or(fallback, |p| p.line()), err.to_string());
+                continue;
+            }
+            let record = record.unwrap();
+            let number = record.position().map_or(fallback, |p| p.line());
+            let doc = row_doc(&schema, &fields, &record, opts);
+            if doc.is_err() {
+                report.reject(number, doc.err().unwrap());
+                continue;
+            }
+            batch.push((number, doc.unwrap()));
+            if batch.len() >= IMPORT_BATCH {
+                self.import_batch(&target, &mut batch, &mut report);
+            }
+        }
+        self.import_batch(&target, &mut batch, &mut report);
+        Ok(report)
+    }
+
+    ///
+    // `query` is a `get`, without `columns` the key and the declared top level fields are written
+    ///
+    pub fn export_csv<W: Write>(&self, out: W, query: &JqlCommand, columns: &[&str], opts: &CsvOptions) -> Result<ExportReport, TrxError> {
+        if ActionType::from(query._action.as_str()) != ActionType::Get {
+            return Err(TrxError::ExportError("csv exports run a `get`".to_string()));
+        }
+        let docs = self.run(unique_id().as_str(), query.to_jql().as_str());
+        if docs.is_err() {
+            return Err(TrxError::ExportError(docs.err().unwrap().to_string()));
+        }
+        let docs = docs.unwrap().unwrap_or_default();
+        let schema = self.schema(query._database.as_ref().unwrap(), query._collection.as_ref().unwrap());
+        if schema.is_none() {
+            return Err(TrxError::ExportError("collection does not exist".to_string()));
+        }
+        let schema = schema.unwrap();
+        let mut columns = columns.iter().map(|c| c.to_string()).collect::<Vec<String>>();
+        if columns.is_empty() {
+            columns = schema._key.fields().iter().map(|f| f.to_string()).collect();
+            schema._fields
+                .iter()
+                .filter(|f| !schema._key.contains(f._name.as_str()))
+                .for_each(|f| columns.push(f._name.clone()));
+        }
+        let declares = columns.iter().map(|c| schema.declare_of(c.as_str())).collect::<Vec<Option<DataTypes>>>();
+
+        let mut writer = WriterBuilder::new().delimiter(opts.delimiter).from_writer(out);
+        let written = writer.write_record(columns.iter().map(|c| header_of(c.as_str(), opts)));
+        if written.is_err() {
+            return Err(TrxError::ExportError(written.err().unwrap().to_string()));
+        }
+        for doc in docs.iter() {
+            let row = columns
+                .iter()
+                .zip(declares.iter())
+                .map(|(c, d)| cell_of(d.as_ref(), get_path(doc, c.as_str()), opts))
+                .collect::<Vec<String>>();
+            let written = writer.write_record(row);
+            if written.is_err() {
+                return Err(TrxError::ExportError(written.err().unwrap().to_string()));
+            }
+        }
+        let flushed = writer.flush();
+        if flushed.is_err() {
+            return Err(TrxError::ExportError(flushed.err().unwrap().to_string()));
+        }
+        Ok(ExportReport { _collections: 1, _documents: docs.len() as u64 })
+    }
+}
+
+impl MoeDb {
+    pub fn import_csv<R: Read>(&self, input: R, db: &str, col: &str, opts: &CsvOptions) -> Result<ImportReport, MoeDbError> {
+        self.exec.import_csv(input, db, col, opts)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn export_csv<W: Write>(&self, out: W, query: &JqlCommand, columns: &[&str], opts: &CsvOptions) -> Result<ExportReport, MoeDbError> {
+        self.exec.export_csv(out, query, columns, opts)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn typed_cells() {
+        let opts = CsvOptions::default();
+        assert_eq!(convert_cell(Some(&DataTypes::Int), " -4 ", &opts), Ok(json!(-4)));
+        assert!(convert_cell(Some(&DataTypes::Uint), "-4", &opts).is_err());
+        assert_eq!(convert_cell(Some(&DataTypes::Float), "2.5", &opts), Ok(json!(2.5)));
+        assert_eq!(convert_cell(Some(&DataTypes::Boolean), "Yes", &opts), Ok(json!(true)));
+        assert!(convert_cell(Some(&DataTypes::Boolean), "maybe", &opts).is_err());
+        assert_eq!(convert_cell(Some(&DataTypes::ArrayOfInt), "1| 2 |3", &opts), Ok(json!([1, 2, 3])));
+        assert_eq!(convert_cell(Some(&DataTypes::DateTime), "2023-10-01T08:30:00", &opts), Ok(json!("2023-10-01 08:30:00")));
+        assert_eq!(convert_cell(Some(&DataTypes::GeoPoint), r#"{"lat":1,"lon":2}"#, &opts), Ok(json!({ "lat": 1, "lon": 2 })));
+        assert_eq!(convert_cell(None, "007", &opts), Ok(json!("007")));
+
+        let us = CsvOptions { date_format: Some("%m/%d/%Y".to_string()), ..CsvOptions::default() };
+        assert_eq!(convert_cell(Some(&DataTypes::Date), "04/01/1990", &us), Ok(json!("1990-04-01")));
+        assert_eq!(cell_of(Some(&DataTypes::Date), Some(&json!("1990-04-01")), &us), "04/01/1990");
+
+        assert_eq!(cell_of(None, Some(&json!(["a", "b"])), &opts), "a|b");
+        assert_eq!(cell_of(None, Some(&json!({ "x": 1 })), &opts), r#"{"x":1}"#);
+        assert_eq!(cell_of(None, None, &opts), "");
+    }
+
+    #[test]
+    fn array_round_trip() {
+        let opts = CsvOptions::default();
+        let tags = Some(&DataTypes::ArrayOfString);
+        for array in [json!(["a", "b"]), json!(["a|b"]), json!(["a", "", "b"]), json!([" a"]), json!(["[x"]), json!([])] {
+            let cell = cell_of(tags, Some(&array), &opts);
+            assert_eq!(convert_cell(tags, cell.as_str(), &opts), Ok(array.clone()), "{}", cell);
+        }
+        assert_eq!(cell_of(tags, Some(&json!(["a|b", "c"])), &opts), r#"["a|b","c"]"#);
+        assert_eq!(cell_of(Some(&DataTypes::ArrayOfInt), Some(&json!([1, 2])), &opts), "1|2");
+    }
+
+    #[test]
+    fn rows_to_docs() {
+        let users = JqlSchema::builder("users", "ids")
+            .field("age", DataTypes::Uint)
+            .field("tags", DataTypes::ArrayOfString)
+            .build();
+        let fields = vec!["ids".to_string(), "age".to_string(), "tags".to_string(), "address.city".to_string()];
+        let row = StringRecord::from(vec!["u1", "33", "", "Oslo"]);
+        let doc = row_doc(&users, &fields, &row, &CsvOptions::default()).unwrap();
+        assert_eq!(doc, json!({ "ids": "u1", "age": 33, "address": { "city": "Oslo" } }));
+        let bad = StringRecord::from(vec!["u2", "old", "", ""]);
+        assert!(row_doc(&users, &fields, &bad, &CsvOptions::default()).unwrap_err().contains("age"));
+
+        let mapped = CsvOptions { columns: BTreeMap::from([("City".to_string(), "address.city".to_string())]), ..CsvOptions::default() };
+        assert_eq!(header_of("address.city", &mapped), "City");
+        assert_eq!(header_of("ids", &mapped), "ids");
+    }
+}
@@ -2,18 +2,23 @@ use std::str;
 use std::collections::BTreeMap;
 use std::sync::Arc;
 use log::{error, trace};
-use rocksdb::{BoundColumnFamily, DB, DBRawIterator, Error, Options, PrefixRange, ReadOptions};
+use rocksdb::{BoundColumnFamily, ColumnFamilyDescriptor, DB, DBRawIterator, Error, Options, PrefixRange, ReadOptions, WriteBatch};
 use serde_json::Value;
 use crate::env;
 use crate::err::{MoeDbError, TrxError};
-use crate::hdrs::{MoeDbMode, TKey, Trx, TValue};
-use crate::util::{cfg_db, get_cfs, query_log_cf_path};
+use crate::hdrs::{MoeDbMode, TKey, Trx, Tuning, TValue};
+use crate::util::{cfg_cf, cfg_db, get_cfs, query_log_cf_path};
 use crate::var::{DB_CREDS, DB_LOG, DB_SYS};
 
 impl Trx {
 
     pub fn new(env: Arc<env::MoeDb>, with_log: bool) -> Result<Self, MoeDbError> {
-        let ins = Self::init(env.db_path.as_str(), env.log_path.as_str(), with_log);
+        let tuning = Tuning::new(&env.storage);
+        if tuning.is_err() {
+            return Err(tuning.err().unwrap());
+        }
+        let tuning = tuning.unwrap();
+        let ins = Self::init(env.db_path.as_str(), env.log_path.as_str(), with_log, &tuning);
         if ins.is_err() {
             return Err(ins.err().unwrap());
         }
@@ -22,22 +27,29 @@ impl Trx {
         Ok(Self {
             db,
             env,
+            tuning,
         })
     }
 
-    fn init(path: &str, log: &str, open_log: bool) -> Result<(MoeDbMode, Options), MoeDbError> {
+    fn init(path: &str, log: &str, open_log: bool, tuning: &Tuning) -> Result<(MoeDbMode, Options), MoeDbError> {
         let mut sys_cfs = vec![DB_CREDS, DB_SYS];
         let mut db_path = path.to_string();
         if open_log {
             let p = query_log_cf_path(path);
             db_path = p;
             sys_cfs = vec![DB_LOG];
+        } else {
+            tuning.load(path);
         }
-        let (opts, cfs) = Self::opts_cf_pair(db_path.as_str(), log);
-        let ins = DB::open_cf(
+        let (opts, cfs) = Self::opts_cf_pair(db_path.as_str(), log, tuning);
+        let descriptors = cfs
+            .iter()
+            .map(|cf| ColumnFamilyDescriptor::new(cf.as_str(), cfg_cf(cf.as_str(), log, tuning)))
+            .collect::<Vec<ColumnFamilyDescriptor>>();
+        let ins = DB::open_cf_descriptors(
             &opts,
             db_path.as_str(),
-            cfs.clone()
+            descriptors
         );
         if ins.is_err() {
             return Err(MoeDbError::CfError(ins.err().unwrap().to_string()));
@@ -57,14 +69,18 @@ impl Trx {
         Ok((moedb, opts))
     }
 
-    fn opts_cf_pair(path: &str, log: &str) -> (Options, Vec<String>) {
-        let opts = cfg_db(log);
+    fn opts_cf_pair(path: &str, log: &str, tuning: &Tuning) -> (Options, Vec<String>) {
+        let opts = cfg_db(log, tuning);
         let ref_opts = opts.clone();
         (opts, get_cfs(&ref_opts, path))
     }
 
     pub fn cfs(&self) -> Vec<String> {
-        Self::opts_cf_pair(self.env.db_path.as_str(), self.env.log_path.as_str()).1
+        Self::opts_cf_pair(self.env.db_path.as_str(), self.env.log_path.as_str(), &self.tuning).1
+    }
+
+    pub fn has_cf(&self, name: &str) -> bool {
+        self.db.cf_handle(name).is_some()
     }
 
     pub fn cf(&self, name: &str) -> Arc<BoundColumnFamily> {
@@ -82,7 +98,7 @@ impl Trx {
     }
 
     pub fn create_cf(&self, name: &str) -> Result<(), TrxError> {
-        let res = self.db.create_cf(name, &cfg_db(self.env.log_path.as_str()));
+        let res = self.db.create_cf(name, &cfg_cf(name, self.env.log_path.as_str(), &self.tuning));
         if res.is_err() {
             return Err(TrxError::CreateDbError(res.err().unwrap().to_string()));
         }
@@ -102,20 +118,112 @@ impl Trx {
         None
     }
 
+    ///
+    // bytes as stored, for values that aren't json like object chunks
+    ///
+    pub fn get_raw(&self, cf_name: &str, key: TKey) -> Option<TValue> {
+        let cf = self.cf(cf_name);
+        self.db.get_cf(&cf, key).ok().flatten()
+    }
+
     pub fn put(&self, cf_name: &str, key: TKey, value: TValue) -> Result<(), Error> {
         let cf = self.cf(cf_name);
-        self.db.put_cf(&cf, key, value)
+        self.db.put_cf_opt(&cf, key, value, &self.tuning.write_opts())
+    }
+
+    ///
+    // all or nothing, in one write
+    ///
+    pub fn put_all(&self, cf_name: &str, entries: Vec<(TKey, TValue)>) -> Result<(), Error> {
+        let cf = self.cf(cf_name);
+        let mut batch = WriteBatch::default();
+        for (key, value) in entries {
+            batch.put_cf(&cf, key, value);
+        }
+        self.db.write_opt(batch, &self.tuning.write_opts())
     }
 
     pub fn delete(&self, cf_name: &str, key: TKey) -> Result<(), Error> {
         let cf = self.cf(cf_name);
-        self.db.delete_cf(&cf, key)
+        self.db.delete_cf_opt(&cf, key, &self.tuning.write_opts())
+    }
+
+    ///
+    // all or nothing, like `put_all`
+    ///
+    pub fn delete_all(&self, cf_name: &str, keys: Vec<TKey>) -> Result<(), Error> {
+        let cf = self.cf(cf_name);
+        let mut batch = WriteBatch::default();
+        for key in keys {
+            batch.delete_cf(&cf, key);
+        }
+        self.db.write_opt(batch, &self.tuning.write_opts())
+    }
+
+    ///
+    // moves values to new keys in one batch, `(from, to, value)`. The old keys are deleted before the
+    // new ones are written, so a move onto a key another move leaves keeps its value
+    ///
+    pub fn move_all(&self, cf_name: &str, moves: Vec<(TKey, TKey, TValue)>) -> Result<(), Error> {
+        let cf = self.cf(cf_name);
+        let mut batch = WriteBatch::default();
+        for (from, _, _) in moves.iter() {
+            batch.delete_cf(&cf, from);
+        }
+        for (_, to, value) in moves {
+            batch.put_cf(&cf, to, value);
+        }
+        self.db.write_opt(batch, &self.tuning.write_opts())
     }
 
     pub fn truncate(&self, cf_name: &str) -> Result<(), Error> {
         self.db.drop_cf(cf_name)
     }
 
+    ///
+    // raw keys with their documents, for collections whose keys aren't plain text
+    ///
+    pub fn entries(&self, cf_name: &str, prefix: TKey) -> Vec<(TKey, Value)> {
+        Self::collect(self.range_iter(cf_name, prefix))
+    }
+
+    ///
+    // walks like `entries` without collecting, `visit` returns false to stop early
+    ///
+    pub fn each<F: FnMut(&[u8], Value) -> bool>(&self, cf_name: &str, prefix: TKey, mut visit: F) {
+        let mut iter = self.range_iter(cf_name, prefix);
+        iter.seek_to_first();
+        while iter.valid() {
+            let kv = iter.item().unwrap();
+            if !visit(kv.0, serde_json::from_slice::<Value>(kv.1).unwrap()) {
+                break;
+            }
+            iter.next();
+        }
+    }
+
+    pub fn between(&self, cf_name: &str, from: Option<TKey>, to: Option<TKey>) -> Vec<(TKey, Value)> {
+        let mut opts = ReadOptions::default();
+        if from.is_some() {
+            opts.set_iterate_lower_bound(from.unwrap());
+        }
+        if to.is_some() {
+            opts.set_iterate_upper_bound(to.unwrap());
+        }
+        Self::collect(self.db.raw_iterator_cf_opt(&self.cf(cf_name), opts))
+    }
+
+    fn collect(mut iter: DBRawIterator) -> Vec<(TKey, Value)> {
+        let mut res = vec![];
+        iter.seek_to_first();
+        while iter.valid() {
+            let kv = iter.item().unwrap();
+            res.push((kv.0.to_vec(), serde_json::from_slice::<Value>(kv.1).unwrap()));
+            iter.next();
+        }
+        res
+    }
+
     pub fn through(&self, cf_name: &str, prefix: TKey) -> BTreeMap<String, Value> {
         let mut res = BTreeMap::new();
         let mut iter = self.range_iter(cf_name, prefix);
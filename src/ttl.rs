@@ -0,0 +1,176 @@
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use chrono::NaiveDateTime;
+use log::{error, trace};
+use serde_json::Value;
+use crate::hdrs::{ChangeKind, Exec, JqlSchema, Types};
+use crate::tup::epoch;
+use crate::util::key_merger;
+use crate::var::TTL_SWEEP_SECS;
+
+/// !```
+/// "_ttl": 3600                    documents expire an hour after their last upsert
+/// "_expire_field": "valid_until"  documents expire at the value of that field, unix seconds (uint)
+///                                 or a "YYYY-MM-DD HH:MM:SS" datetime in UTC
+/// !```
+/// with both the earlier one wins. Upserts stamp the result as `_expires_at` (unix seconds), from
+/// then on reads skip the document and the sweeper removes it, publishing an `Expire` change
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub fn is_expired(doc: &Value, now: u64) -> bool {
+    doc.get(Types::ExpiresAt.as_str())
+        .and_then(|e| e.as_u64())
+        .map_or(false, |at| at <= now)
+}
+
+///
+// `_expires_at` is bookkeeping, documents leave without it
+///
+pub fn without_expiry(mut doc: Value) -> Value {
+    if doc.is_object() {
+        doc.as_object_mut().unwrap().remove(Types::ExpiresAt.as_str());
+    }
+    doc
+}
+
+impl JqlSchema {
+    pub fn expires(&self) -> bool {
+        self._ttl.is_some() || self._expire_field.is_some()
+    }
+
+    ///
+    // `_expires_at` is ours, whatever a client sent along is replaced
+    ///
+    pub fn stamp_expiry(&self, doc: &Value, now: u64) -> Value {
+        let mut stamped = doc.clone();
+        if !stamped.is_object() {
+            return stamped;
+        }
+        let fields = stamped.as_object_mut().unwrap();
+        fields.remove(Types::ExpiresAt.as_str());
+        let by_ttl = self._ttl.map(|ttl| now.saturating_add(ttl));
+        let by_field = self._expire_field
+            .as_ref()
+            .and_then(|f| fields.get(f.as_str()))
+            .and_then(|v| match v {
+                Value::Number(n) => n.as_u64(),
+                Value::String(s) => NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S")
+                    .ok()
+                    .and_then(|at| u64::try_from(at.signed_duration_since(epoch()).num_seconds()).ok()),
+                _ => None
+            });
+        let at = match (by_ttl, by_field) {
+            (Some(t), Some(f)) => Some(t.min(f)),
+            (t, f) => t.or(f)
+        };
+        if at.is_some() {
+            fields.insert(Types::ExpiresAt.as_str().to_string(), Value::from(at.unwrap()));
+        }
+        stamped
+    }
+}
+
+impl Exec {
+    ///
+    // removes every expired document of every collection that expires, returns how many went
+    ///
+    pub fn sweep_expired(&self) -> usize {
+        let now = now_secs();
+        let mut swept = 0;
+        let dbs = self.db_list().data.unwrap_or_default();
+        for db in dbs.iter().filter_map(|d| d.as_str()) {
+            for col in self.col_names(db).iter().map(|c| c.as_str()) {
+                let schema = self.schema(db, col);
+                let name = key_merger(vec![db.to_string(), col.to_string()]).unwrap();
+                if !schema.as_ref().map_or(false, |s| s.expires()) || !self.trx.has_cf(name.as_str()) {
+                    continue;
+                }
+                let schema = schema.unwrap();
+                let mut expired = vec![];
+                self.trx.each(name.as_str(), vec![], |key, doc| {
+                    if is_expired(&doc, now) {
+                        expired.push(key.to_vec());
+                    }
+                    true
+                });
+                let lock = self.col_lock(name.as_str());
+                for key in expired {
+                    // upserts take the same lock, one that renewed the document since the scan is seen here
+                    let _guard = lock.lock().unwrap();
+                    let current = self.trx.get(name.as_str(), key.clone());
+                    if !current.as_ref().map_or(false, |doc| is_expired(doc, now_secs())) {
+                        continue;
+                    }
+                    let doc = current.unwrap();
+                    let dlt = self.trx.delete(name.as_str(), key.clone());
+                    if dlt.is_err() {
+                        error!("sweeping `{}` {}", name, dlt.err().unwrap());
+                        continue;
+                    }
+                    self.drop_attachments(db, col, &schema, &key, &doc);
+                    self.publish(db, col, &schema, &key, ChangeKind::Expire, None);
+                    swept += 1;
+                }
+            }
+        }
+        swept
+    }
+
+    ///
+    // runs `sweep_expired` and `sweep_uploads` every `TTL_SWEEP_SECS` for as long as the `Exec` is alive
+    ///
+    pub fn start_sweeper(exec: &Arc<Exec>) {
+        let weak: Weak<Exec> = Arc::downgrade(exec);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(TTL_SWEEP_SECS));
+            let exec = weak.upgrade();
+            if exec.is_none() {
+                break;
+            }
+            let exec = exec.unwrap();
+            let swept = exec.sweep_expired();
+            trace!("swept {} expired documents", swept);
+            let swept = exec.sweep_uploads(now_secs());
+            trace!("swept {} stale uploads", swept);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use crate::hdrs::DataTypes;
+    use super::*;
+
+    #[test]
+    fn expiry_stamp() {
+        let sessions = JqlSchema::builder("sessions", "ids")
+            .field("valid", DataTypes::Uint)
+            .ttl(60)
+            .expire_field("valid")
+            .build();
+        let doc = sessions.stamp_expiry(&json!({ "ids": "s1", "valid": 1030, "_expires_at": 1 }), 1000);
+        assert_eq!(doc["_expires_at"], json!(1030));
+        let doc = sessions.stamp_expiry(&json!({ "ids": "s1", "valid": 5000 }), 1000);
+        assert_eq!(doc["_expires_at"], json!(1060));
+        assert!(!is_expired(&doc, 1059));
+        assert!(is_expired(&doc, 1060));
+
+        let dated = JqlSchema::builder("sessions", "ids").expire_field("until").build();
+        let doc = dated.stamp_expiry(&json!({ "ids": "s1", "until": "1970-01-01 00:01:40" }), 0);
+        assert_eq!(doc["_expires_at"], json!(100));
+
+        let forever = JqlSchema::builder("sessions", "ids").build();
+        assert!(!is_expired(&forever.stamp_expiry(&json!({ "ids": "s1" }), 0), u64::MAX));
+
+        let doc = sessions.stamp_expiry(&json!({ "ids": "s1" }), u64::MAX - 1);
+        assert_eq!(doc["_expires_at"], json!(u64::MAX));
+        assert_eq!(without_expiry(doc), json!({ "ids": "s1" }));
+    }
+}
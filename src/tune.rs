@@ -0,0 +1,385 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::RwLock;
+use log::{info, warn};
+use rocksdb::{BlockBasedOptions, Cache, DB, DBCompactionStyle, DBCompressionType, FifoCompactOptions, Options, WriteOptions};
+use crate::env::StorageConfig;
+use crate::err::MoeDbError;
+use crate::hdrs::{CfStorage, JqlSchema, Tuning, WalSync};
+use crate::tup::{pack_names, unpack_names};
+use crate::util::key_merger;
+use crate::var::{BACKGROUND_JOBS, BYTES_PER_SYNC, COL_PREFIX, DB_CREDS, DB_LOG, DB_SYS, RATE_LIMIT_FAIRNESS, RATE_LIMIT_REFILL_US};
+
+/// !```
+/// [storage]
+/// compaction = "universal"          # level | universal
+/// compression = "snappy"            # none | snappy | zlib | bz2 | lz4 | lz4hc | zstd
+/// block_cache_size = 268435456      # one LRU cache every column family shares
+/// write_buffer_size = 67108864
+/// bloom_filter_bits = 10.0
+/// wal_sync = "os"                   # os | bytes | always
+/// bytes_per_sync = 1048576
+/// rate_limit = 52428800             # flushes and compactions, bytes per second
+/// background_jobs = 4
+/// mmap = true
+/// !```
+/// `os` leaves syncing the WAL to the OS, `bytes` syncs it every `bytes_per_sync` and `always` on every
+/// write. A schema's `"_storage": { "_compression": "zstd", "_bloom_filter_bits": 10.0 }` changes the
+/// column family of that collection only. RocksDB needs those options when the db opens, so the
+/// schemas are read from `sys` beforehand, a db without any `_storage` opens like before.
+/// `"_compaction": "fifo"` is only taken by a collection and needs `_max_table_files_size`, past that
+/// size RocksDB deletes the oldest files, i.e. the collection discards its oldest documents. `sys`,
+/// `credentials` and the query log never take any of a collection's options
+
+impl Display for WalSync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl From<&str> for WalSync {
+    fn from(value: &str) -> Self {
+        match value {
+            "bytes" => WalSync::Bytes,
+            "always" => WalSync::Always,
+            _ => WalSync::Os
+        }
+    }
+}
+
+impl WalSync {
+    pub fn as_str(&self) -> &str {
+        match self {
+            WalSync::Os => "os",
+            WalSync::Bytes => "bytes",
+            WalSync::Always => "always"
+        }
+    }
+}
+
+impl PartialEq for WalSync {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str().eq(other.as_str())
+    }
+}
+
+pub fn compaction_style(name: &str) -> Option<DBCompactionStyle> {
+    match name {
+        "level" => Some(DBCompactionStyle::Level),
+        "universal" => Some(DBCompactionStyle::Universal),
+        "fifo" => Some(DBCompactionStyle::Fifo),
+        _ => None
+    }
+}
+
+pub fn compression_type(name: &str) -> Option<DBCompressionType> {
+    match name {
+        "none" => Some(DBCompressionType::None),
+        "snappy" => Some(DBCompressionType::Snappy),
+        "zlib" => Some(DBCompressionType::Zlib),
+        "bz2" => Some(DBCompressionType::Bz2),
+        "lz4" => Some(DBCompressionType::Lz4),
+        "lz4hc" => Some(DBCompressionType::Lz4hc),
+        "zstd" => Some(DBCompressionType::Zstd),
+        _ => None
+    }
+}
+
+///
+// the keys `[storage]` and `_storage` have in common, sizes of 0 are rejected rather than handed to RocksDB
+///
+fn check_cf(compaction: Option<&str>, compression: Option<&str>, sizes: &[(&str, Option<usize>)], bloom: Option<f64>) -> Result<(), String> {
+    if compaction.is_some() && compaction_style(compaction.unwrap()).is_none() {
+        return Err(format!("unknown compaction `{}`, use level or universal", compaction.unwrap()));
+    }
+    if compression.is_some() && compression_type(compression.unwrap()).is_none() {
+        return Err(format!("unknown compression `{}`, use none, snappy, zlib, bz2, lz4, lz4hc or zstd", compression.unwrap()));
+    }
+    for (key, size) in sizes {
+        if *size == Some(0) {
+            return Err(format!("`{}` has to be more than 0", key));
+        }
+    }
+    if bloom.map_or(false, |bits| !(bits > 0.0 && bits.is_finite())) {
+        return Err("bloom filter bits have to be more than 0".to_string());
+    }
+    Ok(())
+}
+
+///
+// a block based table with the cache and bloom filter, when either is set
+///
+fn table(cache: Option<&Cache>, bloom: Option<f64>) -> Option<BlockBasedOptions> {
+    if cache.is_none() && bloom.is_none() {
+        return None;
+    }
+    let mut table = BlockBasedOptions::default();
+    if cache.is_some() {
+        table.set_block_cache(cache.unwrap());
+    }
+    if bloom.is_some() {
+        table.set_bloom_filter(bloom.unwrap(), false);
+    }
+    Some(table)
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            compaction: "universal".to_string(),
+            compression: "snappy".to_string(),
+            block_cache_size: None,
+            write_buffer_size: None,
+            bloom_filter_bits: None,
+            wal_sync: WalSync::Os.to_string(),
+            bytes_per_sync: BYTES_PER_SYNC,
+            rate_limit: None,
+            background_jobs: BACKGROUND_JOBS,
+            mmap: true,
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn check(&self) -> Result<(), String> {
+        if self.compaction.eq("fifo") {
+            return Err("`fifo` compaction discards the oldest data, set it per collection with `_max_table_files_size`".to_string());
+        }
+        check_cf(
+            Some(self.compaction.as_str()),
+            Some(self.compression.as_str()),
+            &[("block_cache_size", self.block_cache_size), ("write_buffer_size", self.write_buffer_size)],
+            self.bloom_filter_bits
+        )?;
+        if !WalSync::from(self.wal_sync.as_str()).as_str().eq(self.wal_sync.as_str()) {
+            return Err(format!("unknown wal_sync `{}`, use os, bytes or always", self.wal_sync));
+        }
+        if self.rate_limit.map_or(false, |rate| rate <= 0) {
+            return Err("`rate_limit` has to be more than 0".to_string());
+        }
+        if self.background_jobs < 1 {
+            return Err("`background_jobs` has to be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn wal(&self) -> WalSync {
+        WalSync::from(self.wal_sync.as_str())
+    }
+}
+
+impl CfStorage {
+    pub fn check(&self) -> Result<(), String> {
+        let fifo = self.is_fifo();
+        if fifo && self._max_table_files_size.map_or(true, |size| size == 0) {
+            return Err("`fifo` compaction needs `_max_table_files_size`, the oldest documents are deleted past it".to_string());
+        }
+        if !fifo && self._max_table_files_size.is_some() {
+            return Err("`_max_table_files_size` only applies to `fifo` compaction".to_string());
+        }
+        check_cf(
+            self._compaction.as_deref().filter(|c| !c.eq(&"fifo")),
+            self._compression.as_deref(),
+            &[("_block_cache_size", self._block_cache_size), ("_write_buffer_size", self._write_buffer_size)],
+            self._bloom_filter_bits
+        )
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        self._compaction.as_deref() == Some("fifo")
+    }
+}
+
+impl Tuning {
+    pub fn new(storage: &StorageConfig) -> Result<Self, MoeDbError> {
+        let mut cache = None;
+        if storage.block_cache_size.is_some() {
+            let created = Cache::new_lru_cache(storage.block_cache_size.unwrap());
+            if created.is_err() {
+                return Err(MoeDbError::ConfigError(created.err().unwrap().to_string()));
+            }
+            cache = Some(created.unwrap());
+        }
+        Ok(Self {
+            storage: storage.clone(),
+            cache,
+            cfs: RwLock::new(BTreeMap::new()),
+        })
+    }
+
+    ///
+    // options of the db and of column families without `_storage`
+    ///
+    pub fn apply(&self, opts: &mut Options) {
+        let storage = &self.storage;
+        opts.set_max_background_jobs(storage.background_jobs);
+        opts.set_bytes_per_sync(storage.bytes_per_sync);
+        // fifo deletes data, never for the whole db
+        let style = compaction_style(storage.compaction.as_str())
+            .filter(|style| !matches!(style, DBCompactionStyle::Fifo))
+            .unwrap_or(DBCompactionStyle::Universal);
+        opts.set_compaction_style(style);
+        opts.set_compression_type(compression_type(storage.compression.as_str()).unwrap_or(DBCompressionType::Snappy));
+        opts.set_allow_mmap_reads(storage.mmap);
+        opts.set_allow_mmap_writes(storage.mmap);
+        if storage.wal() == WalSync::Bytes {
+            opts.set_wal_bytes_per_sync(storage.bytes_per_sync);
+        }
+        if storage.write_buffer_size.is_some() {
+            opts.set_write_buffer_size(storage.write_buffer_size.unwrap());
+        }
+        if storage.rate_limit.is_some() {
+            opts.set_ratelimiter(storage.rate_limit.unwrap(), RATE_LIMIT_REFILL_US, RATE_LIMIT_FAIRNESS);
+        }
+        let table = table(self.cache.as_ref(), storage.bloom_filter_bits);
+        if table.is_some() {
+            opts.set_block_based_table_factory(&table.unwrap());
+        }
+    }
+
+    ///
+    // the `_storage` of the collection behind `name` over what `apply` set
+    ///
+    pub fn apply_cf(&self, name: &str, opts: &mut Options) {
+        if [DB_SYS, DB_CREDS, DB_LOG].contains(&name) {
+            return;
+        }
+        let cfs = self.cfs.read().unwrap();
+        let over = cfs.get(name);
+        if over.is_none() {
+            return;
+        }
+        let (over, own) = over.unwrap();
+        if over.is_fifo() && over._max_table_files_size.is_some() {
+            let mut fifo = FifoCompactOptions::default();
+            fifo.set_max_table_files_size(over._max_table_files_size.unwrap());
+            opts.set_compaction_style(DBCompactionStyle::Fifo);
+            opts.set_fifo_compaction_options(&fifo);
+        } else if over._compaction.is_some() && !over.is_fifo() {
+            opts.set_compaction_style(compaction_style(over._compaction.as_ref().unwrap()).unwrap_or(DBCompactionStyle::Universal));
+        }
+        if over._compression.is_some() {
+            opts.set_compression_type(compression_type(over._compression.as_ref().unwrap()).unwrap_or(DBCompressionType::Snappy));
+        }
+        if over._write_buffer_size.is_some() {
+            opts.set_write_buffer_size(over._write_buffer_size.unwrap());
+        }
+        if over._block_cache_size.is_none() && over._bloom_filter_bits.is_none() {
+            return;
+        }
+        let cache = own.as_ref().or(self.cache.as_ref());
+        let table = table(cache, over._bloom_filter_bits.or(self.storage.bloom_filter_bits));
+        if table.is_some() {
+            opts.set_block_based_table_factory(&table.unwrap());
+        }
+    }
+
+    pub fn write_opts(&self) -> WriteOptions {
+        let mut opts = WriteOptions::default();
+        opts.set_sync(self.storage.wal() == WalSync::Always);
+        opts
+    }
+
+    ///
+    // `None` forgets an earlier `_storage` of a collection created again under the same name. A
+    // `_block_cache_size` gets its cache here, once, every open of the column family and every bulk load
+    // writer of the collection share it
+    ///
+    pub fn set_cf(&self, name: &str, storage: Option<&CfStorage>) {
+        let mut cfs = self.cfs.write().unwrap();
+        let previous = cfs.remove(name);
+        if storage.is_none() {
+            return;
+        }
+        let storage = storage.unwrap();
+        let kept = previous
+            .filter(|(before, _)| before._block_cache_size == storage._block_cache_size)
+            .and_then(|(_, cache)| cache);
+        let cache = kept.or_else(|| storage._block_cache_size.and_then(|size| {
+            let created = Cache::new_lru_cache(size);
+            if created.is_err() {
+                warn!("block cache of `{}` {}", name, created.as_ref().err().unwrap());
+            }
+            created.ok()
+        }));
+        cfs.insert(name.to_string(), (storage.clone(), cache));
+    }
+
+    ///
+    // reads `_storage` of every collection from `sys` of a db that isn't open yet
+    ///
+    pub fn load(&self, path: &str) {
+        if !Path::new(path).join("CURRENT").exists() {
+            return;
+        }
+        let ro = DB::open_cf_for_read_only(&Options::default(), path, [DB_SYS], false);
+        if ro.is_err() {
+            warn!("reading collection storage options {}", ro.err().unwrap());
+            return;
+        }
+        let ro = ro.unwrap();
+        let sys = ro.cf_handle(DB_SYS);
+        if sys.is_none() {
+            return;
+        }
+        let prefix = pack_names(&[COL_PREFIX]);
+        let mut iter = ro.raw_iterator_cf(&sys.unwrap());
+        iter.seek(prefix.as_slice());
+        let mut found = 0;
+        while iter.valid() && iter.key().map_or(false, |k| k.starts_with(prefix.as_slice())) {
+            let names = unpack_names(iter.key().unwrap()).unwrap_or_default();
+            let storage = serde_json::from_slice::<JqlSchema>(iter.value().unwrap())
+                .ok()
+                .and_then(|schema| schema._storage);
+            if names.len() == 3 && storage.is_some() {
+                let name = key_merger(vec![names[1].clone(), names[2].clone()]).unwrap();
+                self.set_cf(name.as_str(), storage.as_ref());
+                found += 1;
+            }
+            iter.next();
+        }
+        if found > 0 {
+            info!("{} collections with their own storage options", found);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_checks() {
+        let storage = StorageConfig::default();
+        assert!(storage.check().is_ok());
+        assert_eq!(storage.wal(), WalSync::Os);
+
+        let zstd = StorageConfig { compression: "zstd".to_string(), wal_sync: "always".to_string(), ..StorageConfig::default() };
+        assert!(zstd.check().is_ok());
+        assert_eq!(zstd.wal(), WalSync::Always);
+
+        let bad = [
+            StorageConfig { compression: "brotli".to_string(), ..StorageConfig::default() },
+            StorageConfig { compaction: "tiered".to_string(), ..StorageConfig::default() },
+            StorageConfig { compaction: "fifo".to_string(), ..StorageConfig::default() },
+            StorageConfig { wal_sync: "never".to_string(), ..StorageConfig::default() },
+            StorageConfig { write_buffer_size: Some(0), ..StorageConfig::default() },
+            StorageConfig { bloom_filter_bits: Some(-1.0), ..StorageConfig::default() },
+            StorageConfig { rate_limit: Some(0), ..StorageConfig::default() },
+        ];
+        for storage in bad.iter() {
+            assert!(storage.check().is_err(), "{:?}", storage);
+        }
+
+        let over: CfStorage = serde_json::from_str(r#"{ "_compression": "lz4", "_bloom_filter_bits": 10.0 }"#).unwrap();
+        assert!(over.check().is_ok());
+        assert!(CfStorage { _compaction: Some("level".to_string()), ..CfStorage::default() }.check().is_ok());
+        assert!(CfStorage { _compression: Some("lzma".to_string()), ..CfStorage::default() }.check().is_err());
+
+        let fifo = CfStorage { _compaction: Some("fifo".to_string()), ..CfStorage::default() };
+        assert!(fifo.check().is_err());
+        assert!(CfStorage { _max_table_files_size: Some(1 << 30), ..fifo.clone() }.check().is_ok());
+        assert!(CfStorage { _max_table_files_size: Some(1 << 30), ..CfStorage::default() }.check().is_err());
+    }
+}
@@ -0,0 +1,216 @@
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+use serde_json::Value;
+use crate::hdrs::{DataTypes, TKey};
+
+/// !```
+/// every part starts with a tag, tags are ordered the way their values compare
+/// 0x01 false | 0x02 true
+/// 0x14 int    8 bytes big endian, sign bit flipped
+/// 0x15 uint   8 bytes big endian, only above i64::MAX so it sorts after every int
+/// 0x20 float  8 bytes big endian, sign bit flipped for positives, all bits for negatives
+/// 0x30 string utf8 with 0x00 escaped as 0x00 0xff, closed by 0x00
+/// 0x40 date   days since 1970-01-01 like an int
+/// 0x41 datetime seconds since 1970-01-01 00:00:00 like an int
+/// 0x42 time   seconds since midnight like an int
+/// !```
+/// a packed prefix of parts is a byte prefix of every key starting with those parts
+
+const FALSE: u8 = 0x01;
+const TRUE: u8 = 0x02;
+const INT: u8 = 0x14;
+const UINT: u8 = 0x15;
+const FLOAT: u8 = 0x20;
+const STRING: u8 = 0x30;
+const DATE: u8 = 0x40;
+const DATETIME: u8 = 0x41;
+const TIME: u8 = 0x42;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const TIME_FORMAT: &str = "%H:%M:%S";
+
+const SIGN: u64 = 1 << 63;
+
+pub fn pack(parts: &[(Option<DataTypes>, &Value)]) -> Option<TKey> {
+    let mut out = vec![];
+    for (declare, value) in parts {
+        let packed = pack_part(declare, value, &mut out);
+        if packed.is_none() {
+            return None;
+        }
+    }
+    Some(out)
+}
+
+fn pack_part(declare: &Option<DataTypes>, value: &Value, out: &mut TKey) -> Option<()> {
+    match (declare, value) {
+        (_, Value::Bool(b)) => out.push(if *b { TRUE } else { FALSE }),
+        // a float field holding `2` has to sort with `1.5`, not before every float
+        (Some(DataTypes::Float), Value::Number(n)) => pack_float(n.as_f64()?, out),
+        (Some(DataTypes::Date), Value::String(s)) => {
+            let days = NaiveDate::parse_from_str(s, DATE_FORMAT).ok()?.signed_duration_since(epoch().date());
+            pack_int(DATE, days.num_days(), out);
+        }
+        (Some(DataTypes::DateTime), Value::String(s)) => {
+            let secs = NaiveDateTime::parse_from_str(s, DATETIME_FORMAT).ok()?.signed_duration_since(epoch());
+            pack_int(DATETIME, secs.num_seconds(), out);
+        }
+        (Some(DataTypes::Time), Value::String(s)) => {
+            let secs = NaiveTime::parse_from_str(s, TIME_FORMAT).ok()?.num_seconds_from_midnight();
+            pack_int(TIME, secs as i64, out);
+        }
+        (_, Value::Number(n)) if n.is_i64() => pack_int(INT, n.as_i64().unwrap(), out),
+        (_, Value::Number(n)) if n.is_u64() => {
+            out.push(UINT);
+            out.extend_from_slice(&n.as_u64().unwrap().to_be_bytes());
+        }
+        (_, Value::Number(n)) => pack_float(n.as_f64()?, out),
+        (_, Value::String(s)) => {
+            out.push(STRING);
+            for b in s.as_bytes() {
+                out.push(*b);
+                if *b == 0x00 {
+                    out.push(0xff);
+                }
+            }
+            out.push(0x00);
+        }
+        _ => return None
+    }
+    Some(())
+}
+
+pub fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1970, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap()
+}
+
+fn pack_int(tag: u8, i: i64, out: &mut TKey) {
+    out.push(tag);
+    out.extend_from_slice(&((i as u64) ^ SIGN).to_be_bytes());
+}
+
+fn pack_float(f: f64, out: &mut TKey) {
+    let bits = f.to_bits();
+    let ordered = if bits & SIGN != 0 { !bits } else { bits ^ SIGN };
+    out.push(FLOAT);
+    out.extend_from_slice(&ordered.to_be_bytes());
+}
+
+pub fn unpack(key: &[u8]) -> Option<Vec<Value>> {
+    let mut parts = vec![];
+    let mut at = 0;
+    while at < key.len() {
+        let tag = key[at];
+        at += 1;
+        let part = match tag {
+            FALSE => Value::Bool(false),
+            TRUE => Value::Bool(true),
+            INT | UINT | FLOAT | DATE | DATETIME | TIME => {
+                let raw = u64::from_be_bytes(key.get(at..at + 8)?.try_into().ok()?);
+                at += 8;
+                let int = (raw ^ SIGN) as i64;
+                match tag {
+                    INT => Value::from(int),
+                    UINT => Value::from(raw),
+                    DATE => Value::from((epoch() + Duration::days(int)).format(DATE_FORMAT).to_string()),
+                    DATETIME => Value::from((epoch() + Duration::seconds(int)).format(DATETIME_FORMAT).to_string()),
+                    TIME => Value::from((epoch() + Duration::seconds(int)).format(TIME_FORMAT).to_string()),
+                    _ => {
+                        let bits = if raw & SIGN != 0 { raw ^ SIGN } else { !raw };
+                        Value::from(f64::from_bits(bits))
+                    }
+                }
+            }
+            STRING => {
+                let mut bytes = vec![];
+                loop {
+                    let b = *key.get(at)?;
+                    at += 1;
+                    if b != 0x00 {
+                        bytes.push(b);
+                    } else if key.get(at) == Some(&0xff) {
+                        bytes.push(0x00);
+                        at += 1;
+                    } else {
+                        break;
+                    }
+                }
+                Value::String(String::from_utf8(bytes).ok()?)
+            }
+            _ => return None
+        };
+        parts.push(part);
+    }
+    Some(parts)
+}
+
+///
+// system keys are plain names, `pack_names(&["collection::", db, col])`
+///
+pub fn pack_names(names: &[&str]) -> TKey {
+    let values = names.iter().map(|n| Value::from(*n)).collect::<Vec<Value>>();
+    pack(&values.iter().map(|v| (None, v)).collect::<Vec<_>>()).unwrap()
+}
+
+pub fn unpack_names(key: &[u8]) -> Option<Vec<String>> {
+    unpack(key)?
+        .into_iter()
+        .map(|v| v.as_str().map(|s| s.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    fn packed(v: &Value) -> TKey {
+        pack(&[(None, v)]).unwrap()
+    }
+
+    #[test]
+    fn order_preserved() {
+        let ints = [json!(-9000), json!(-1), json!(0), json!(7), json!(300), json!(u64::MAX)];
+        for w in ints.windows(2) {
+            assert!(packed(&w[0]) < packed(&w[1]), "{} < {}", w[0], w[1]);
+        }
+        let floats = [json!(-2.5), json!(-0.1), json!(0.0), json!(1.5), json!(10.25)];
+        for w in floats.windows(2) {
+            assert!(packed(&w[0]) < packed(&w[1]), "{} < {}", w[0], w[1]);
+        }
+        let strings = [json!(""), json!("a"), json!("a\u{0}b"), json!("ab"), json!("b")];
+        for w in strings.windows(2) {
+            assert!(packed(&w[0]) < packed(&w[1]), "{} < {}", w[0], w[1]);
+        }
+        let two = pack(&[(Some(DataTypes::Float), &json!(2))]).unwrap();
+        assert!(packed(&json!(1.5)) < two && two < packed(&json!(2.5)));
+
+        let dates = ["1969-12-31", "1970-01-01", "2023-02-28", "2023-10-01"]
+            .map(|d| pack(&[(Some(DataTypes::Date), &json!(d))]).unwrap());
+        for w in dates.windows(2) {
+            assert!(w[0] < w[1]);
+        }
+        let early = pack(&[(Some(DataTypes::Time), &json!("9:05:00"))]).unwrap();
+        let late = pack(&[(Some(DataTypes::Time), &json!("10:00:00"))]).unwrap();
+        assert!(early < late);
+    }
+
+    #[test]
+    fn prefix_and_roundtrip() {
+        let device = json!("dev1");
+        let key = pack(&[(None, &device), (None, &json!(1700000000)), (None, &json!(true))]).unwrap();
+        assert!(key.starts_with(&packed(&device)));
+        assert!(!pack(&[(None, &json!("dev10"))]).unwrap().starts_with(&packed(&device)));
+        assert_eq!(unpack(&key).unwrap(), vec![device, json!(1700000000), json!(true)]);
+        assert!(pack(&[(None, &json!({"a": 1}))]).is_none());
+
+        let at = json!("2023-10-01 12:30:00");
+        let key = pack(&[(Some(DataTypes::DateTime), &at)]).unwrap();
+        assert_eq!(unpack(&key).unwrap(), vec![at]);
+        assert!(pack(&[(Some(DataTypes::Date), &json!("yesterday"))]).is_none());
+
+        let meta = pack_names(&["collection::", "shop#1", "orders"]);
+        assert_eq!(unpack_names(&meta).unwrap(), vec!["collection::", "shop#1", "orders"]);
+        assert!(meta.starts_with(&pack_names(&["collection::", "shop#1"])));
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::Write;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{Map, Value};
+use crate::err::{MoeDbError, TrxError};
+use crate::func::unique_id;
+use crate::hdrs::{DbRes, Exec, JqlCommand, MoeDb, ObjectMeta, ObjectUpload, TKey, Types, UploadPart};
+use crate::obj::{checksum, object_target};
+use crate::tup::{pack, pack_names};
+use crate::ttl::now_secs;
+use crate::var::UPLOAD_EXPIRY_SECS;
+
+/// !```
+/// begin-upload     { "_key": "videos/intro.mp4", "_content_type": "video/mp4", "_metadata": {} }
+/// put-part         { "_upload_id": "<id>", "_part": 1, "_data": "<base64>" }
+/// list-parts       { "_upload_id": "<id>" }
+/// complete-upload  { "_upload_id": "<id>" }
+/// abort-upload     { "_upload_id": "<id>" }
+/// !```
+/// parts are numbered from 1 and can arrive in any order or be sent again, so an interrupted upload
+/// resumes by asking `list-parts` what already made it. `complete-upload` joins the parts in number
+/// order into a regular object and removes them. Uploads that got no part for `UPLOAD_EXPIRY_SECS`
+/// are dropped by the sweeper
+
+const UPLOAD: &str = "upload";
+const PART: &str = "part";
+
+fn upload_key(id: &str) -> TKey {
+    pack_names(&[UPLOAD, id])
+}
+
+///
+// uploads from before `_touched_at` count from their start
+///
+fn is_stale(upload: &ObjectUpload, now: u64) -> bool {
+    upload._started_at.max(upload._touched_at).saturating_add(UPLOAD_EXPIRY_SECS) <= now
+}
+
+fn part_key(id: &str, part: u32) -> TKey {
+    let (tag, id, part) = (Value::from(PART), Value::from(id), Value::from(part));
+    pack(&[(None, &tag), (None, &id), (None, &part)]).unwrap()
+}
+
+impl Exec {
+    pub fn begin_upload(&self, db: &str, bucket: &str, key: &str, content_type: Option<String>, metadata: Map<String, Value>) -> Result<ObjectUpload, TrxError> {
+        let name = self.open_bucket(db, bucket);
+        if name.is_err() {
+            return Err(name.err().unwrap());
+        }
+        let now = now_secs();
+        let upload = ObjectUpload {
+            _upload_id: unique_id(),
+            _key: key.to_string(),
+            _content_type: content_type,
+            _metadata: metadata,
+            _parts: Default::default(),
+            _started_at: now,
+            _touched_at: now,
+        };
+        let put = self.trx.put(name.unwrap().as_str(), upload_key(upload._upload_id.as_str()), serde_json::to_vec(&upload).unwrap());
+        if put.is_err() {
+            return Err(TrxError::UploadError(put.err().unwrap().to_string()));
+        }
+        Ok(upload)
+    }
+
+    pub fn upload_state(&self, db: &str, bucket: &str, id: &str) -> Option<ObjectUpload> {
+        let name = Self::bucket_cf(db, bucket);
+        if !self.trx.has_cf(name.as_str()) {
+            return None;
+        }
+        self.trx.get(name.as_str(), upload_key(id))
+            .and_then(|u| serde_json::from_value::<ObjectUpload>(u).ok())
+    }
+
+    ///
+    // sending a part again replaces it
+    ///
+    pub fn put_part(&self, db: &str, bucket: &str, id: &str, part: u32, data: &[u8]) -> Result<UploadPart, TrxError> {
+        if part == 0 {
+            return Err(TrxError::UploadError("parts are numbered from 1".to_string()));
+        }
+        let name = Self::bucket_cf(db, bucket);
+        if self.upload_state(db, bucket, id).is_none() {
+            return Err(TrxError::UploadError(format!("no upload `{}`", id)));
+        }
+        let put = self.trx.put(name.as_str(), part_key(id, part), data.to_vec());
+        if put.is_err() {
+            return Err(TrxError::UploadError(put.err().unwrap().to_string()));
+        }
+        let uploaded = UploadPart {
+            _size: data.len() as u64,
+            _checksum: checksum(data),
+        };
+        // parts of one upload may arrive concurrently, the record is read and written under the lock
+        let completing = self.uploads.lock().unwrap();
+        if completing.contains(id) {
+            return Err(TrxError::UploadError(format!("upload `{}` is being completed", id)));
+        }
+        let upload = self.upload_state(db, bucket, id);
+        if upload.is_none() {
+            let _ = self.trx.delete(name.as_str(), part_key(id, part));
+            return Err(TrxError::UploadError(format!("no upload `{}`", id)));
+        }
+        let mut upload = upload.unwrap();
+        upload._parts.insert(part, uploaded.clone());
+        upload._touched_at = now_secs();
+        let put = self.trx.put(name.as_str(), upload_key(id), serde_json::to_vec(&upload).unwrap());
+        if put.is_err() {
+            return Err(TrxError::UploadError(put.err().unwrap().to_string()));
+        }
+        Ok(uploaded)
+    }
+
+    ///
+    // the upload is marked as completing under the lock, parts are copied without it so other uploads and the
+    // sweeper go on. Parts sent meanwhile and a second complete or abort of the same upload are refused
+    ///
+    pub fn complete_upload(&self, db: &str, bucket: &str, id: &str) -> Result<ObjectMeta, TrxError> {
+        let upload = {
+            let mut completing = self.uploads.lock().unwrap();
+            let upload = self.upload_state(db, bucket, id);
+            if upload.is_none() {
+                return Err(TrxError::UploadError(format!("no upload `{}`", id)));
+            }
+            let upload = upload.unwrap();
+            if upload._parts.is_empty() {
+                return Err(TrxError::UploadError(format!("upload `{}` has no parts", id)));
+            }
+            if !completing.insert(id.to_string()) {
+                return Err(TrxError::UploadError(format!("upload `{}` is being completed", id)));
+            }
+            upload
+        };
+        let name = Self::bucket_cf(db, bucket);
+        let meta = self.assemble_upload(db, bucket, name.as_str(), &upload);
+        let mut completing = self.uploads.lock().unwrap();
+        if meta.is_ok() {
+            self.drop_upload(name.as_str(), &upload);
+        }
+        completing.remove(id);
+        meta
+    }
+
+    ///
+    // every part is checked against the checksum it was stored with while it is copied into the object
+    ///
+    fn assemble_upload(&self, db: &str, bucket: &str, name: &str, upload: &ObjectUpload) -> Result<ObjectMeta, TrxError> {
+        let id = upload._upload_id.as_str();
+        let writer = self.object_writer(db, bucket, upload._key.as_str(), upload._content_type.clone(), upload._metadata.clone());
+        if writer.is_err() {
+            return Err(writer.err().unwrap());
+        }
+        let mut writer = writer.unwrap();
+        for (part, uploaded) in upload._parts.iter() {
+            let data = self.trx.get_raw(name, part_key(id, *part));
+            if data.is_none() || checksum(data.as_ref().unwrap()) != uploaded._checksum {
+                return Err(TrxError::UploadError(format!("part {} of `{}` is missing or corrupted, send it again", part, id)));
+            }
+            let written = writer.write_all(&data.unwrap());
+            if written.is_err() {
+                return Err(TrxError::UploadError(written.err().unwrap().to_string()));
+            }
+        }
+        writer.finish()
+    }
+
+    pub fn abort_upload(&self, db: &str, bucket: &str, id: &str) -> Result<ObjectUpload, TrxError> {
+        let completing = self.uploads.lock().unwrap();
+        if completing.contains(id) {
+            return Err(TrxError::UploadError(format!("upload `{}` is being completed", id)));
+        }
+        let upload = self.upload_state(db, bucket, id);
+        if upload.is_none() {
+            return Err(TrxError::UploadError(format!("no upload `{}`", id)));
+        }
+        let upload = upload.unwrap();
+        self.drop_upload(Self::bucket_cf(db, bucket).as_str(), &upload);
+        Ok(upload)
+    }
+
+    ///
+    // drops every upload of every bucket that got no part in the `UPLOAD_EXPIRY_SECS` before `now`, returns how many went
+    ///
+    pub fn sweep_uploads(&self, now: u64) -> usize {
+        let completing = self.uploads.lock().unwrap();
+        let mut swept = 0;
+        let dbs = self.db_list().data.unwrap_or_default();
+        for db in dbs.iter().filter_map(|d| d.as_str()) {
+            for bucket in self.bucket_names(db) {
+                let name = Self::bucket_cf(db, bucket.as_str());
+                if !self.trx.has_cf(name.as_str()) {
+                    continue;
+                }
+                let stale = self.trx.entries(name.as_str(), pack_names(&[UPLOAD]))
+                    .into_iter()
+                    .filter_map(|(_, u)| serde_json::from_value::<ObjectUpload>(u).ok())
+                    .filter(|u| is_stale(u, now) && !completing.contains(&u._upload_id))
+                    .collect::<Vec<ObjectUpload>>();
+                for upload in stale.iter() {
+                    self.drop_upload(name.as_str(), upload);
+                    swept += 1;
+                }
+            }
+        }
+        swept
+    }
+
+    fn drop_upload(&self, name: &str, upload: &ObjectUpload) {
+        let id = upload._upload_id.as_str();
+        for part in upload._parts.keys() {
+            let _ = self.trx.delete(name, part_key(id, *part));
+        }
+        let _ = self.trx.delete(name, upload_key(id));
+    }
+
+    pub fn begin_upl(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let key = body.get(Types::Key.as_str()).and_then(|k| k.as_str()).unwrap_or_default();
+        let content_type = body.get(Types::ContentType.as_str()).and_then(|c| c.as_str()).map(|c| c.to_string());
+        let metadata = body.get(Types::Metadata.as_str()).and_then(|m| m.as_object()).cloned().unwrap_or_default();
+        Self::upload_res(self.begin_upload(db, bucket, key, content_type, metadata).map(|u| serde_json::to_value(u).unwrap()))
+    }
+
+    pub fn put_prt(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let id = body.get(Types::UploadId.as_str()).and_then(|u| u.as_str()).unwrap_or_default();
+        let part = body.get(Types::Part.as_str()).and_then(|p| p.as_u64()).unwrap_or_default();
+        let part = u32::try_from(part);
+        if part.is_err() {
+            return DbRes { data: None, error: Some(TrxError::UploadError(format!("parts are numbered up to {}", u32::MAX))) };
+        }
+        let data = STANDARD.decode(body.get(Types::Data.as_str()).and_then(|d| d.as_str()).unwrap_or_default());
+        if data.is_err() {
+            return DbRes { data: None, error: Some(TrxError::UploadError(data.err().unwrap().to_string())) };
+        }
+        Self::upload_res(self.put_part(db, bucket, id, part.unwrap(), &data.unwrap()).map(|p| serde_json::to_value(p).unwrap()))
+    }
+
+    pub fn list_prt(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let id = body.get(Types::UploadId.as_str()).and_then(|u| u.as_str()).unwrap_or_default();
+        let upload = self.upload_state(db, bucket, id)
+            .ok_or(TrxError::UploadError(format!("no upload `{}`", id)));
+        Self::upload_res(upload.map(|u| serde_json::to_value(u).unwrap()))
+    }
+
+    pub fn complete_upl(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let id = body.get(Types::UploadId.as_str()).and_then(|u| u.as_str()).unwrap_or_default();
+        Self::upload_res(self.complete_upload(db, bucket, id).map(|m| m.to_response()))
+    }
+
+    pub fn abort_upl(&self, cmd: &JqlCommand) -> DbRes {
+        let (db, bucket, body) = object_target(cmd);
+        let id = body.get(Types::UploadId.as_str()).and_then(|u| u.as_str()).unwrap_or_default();
+        Self::upload_res(self.abort_upload(db, bucket, id).map(|u| serde_json::to_value(u).unwrap()))
+    }
+
+    fn upload_res(res: Result<Value, TrxError>) -> DbRes {
+        match res {
+            Ok(value) => DbRes { data: Some(vec![value]), error: None },
+            Err(err) => DbRes { data: None, error: Some(err) }
+        }
+    }
+}
+
+impl MoeDb {
+    pub fn begin_upload(&self, db: &str, bucket: &str, key: &str, content_type: Option<&str>, metadata: Map<String, Value>) -> Result<ObjectUpload, MoeDbError> {
+        self.exec.begin_upload(db, bucket, key, content_type.map(|c| c.to_string()), metadata)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn put_part(&self, db: &str, bucket: &str, id: &str, part: u32, data: &[u8]) -> Result<UploadPart, MoeDbError> {
+        self.exec.put_part(db, bucket, id, part, data)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn upload_state(&self, db: &str, bucket: &str, id: &str) -> Option<ObjectUpload> {
+        self.exec.upload_state(db, bucket, id)
+    }
+
+    pub fn complete_upload(&self, db: &str, bucket: &str, id: &str) -> Result<ObjectMeta, MoeDbError> {
+        self.exec.complete_upload(db, bucket, id)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+
+    pub fn abort_upload(&self, db: &str, bucket: &str, id: &str) -> Result<ObjectUpload, MoeDbError> {
+        self.exec.abort_upload(db, bucket, id)
+            .map_err(|e| MoeDbError::TransactionError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_keys() {
+        assert!(part_key("u1", 2) < part_key("u1", 10));
+        assert!(part_key("u1", 10) < part_key("u2", 1));
+        assert_ne!(upload_key("u1"), part_key("u1", 1));
+
+        let mut upload = ObjectUpload {
+            _upload_id: "u1".to_string(),
+            _key: "a.bin".to_string(),
+            _content_type: None,
+            _metadata: Map::new(),
+            _parts: Default::default(),
+            _started_at: 1,
+            _touched_at: 0,
+        };
+        upload._parts.insert(10, UploadPart { _size: 1, _checksum: checksum(b"b") });
+        upload._parts.insert(2, UploadPart { _size: 1, _checksum: checksum(b"a") });
+        let stored = serde_json::to_vec(&upload).unwrap();
+        let read = serde_json::from_slice::<ObjectUpload>(&stored).unwrap();
+        assert_eq!(read._parts.keys().copied().collect::<Vec<u32>>(), vec![2, 10]);
+
+        // the sweep only reads upload records
+        assert!(upload_key("u1").starts_with(&pack_names(&[UPLOAD])));
+        assert!(!part_key("u1", 1).starts_with(&pack_names(&[UPLOAD])));
+        assert!(!is_stale(&upload, UPLOAD_EXPIRY_SECS));
+        assert!(is_stale(&upload, UPLOAD_EXPIRY_SECS + 1));
+        // a part sent later keeps it going
+        upload._touched_at = 100;
+        assert!(!is_stale(&upload, UPLOAD_EXPIRY_SECS + 1));
+        assert!(is_stale(&upload, UPLOAD_EXPIRY_SECS + 100));
+    }
+}
@@ -1,7 +1,11 @@
 use std::path::Path;
 use std::{fs, thread};
-use itertools::Itertools;
-use rocksdb::{DB, DBCompactionStyle, DBCompressionType, DBRecoveryMode, Options};
+use rocksdb::{DB, DBRecoveryMode, Options};
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::Value;
+use crate::hdrs::Tuning;
+use crate::var::{BUCKET_MARKER, MIN_BLOB_SIZE};
 
 pub fn use_available_threads() -> usize {
     thread::available_parallelism().map_or(1, usize::from) * 4
@@ -16,25 +20,40 @@ pub fn get_cfs(opts: &Options, path: &str) -> Vec<String> {
         .collect()
 }
 
-pub fn cfg_db(log: &str) -> Options {
+///
+// what every db and column family starts from, `[storage]` of the config is applied over it
+///
+pub fn cfg_db(log: &str, tuning: &Tuning) -> Options {
     let mut opts = Options::default();
     opts.create_if_missing(true);
     opts.create_missing_column_families(true);
     opts.increase_parallelism(use_available_threads() as i32);
-    opts.set_max_background_jobs(4);
-    opts.set_bytes_per_sync(1048576);
     opts.set_db_log_dir(log);
-    opts.set_compaction_style(DBCompactionStyle::Universal);
-    opts.set_compression_type(DBCompressionType::Snappy);
     opts.set_use_fsync(false);
     opts.set_allow_concurrent_memtable_write(true);
-    opts.set_allow_mmap_reads(true);
-    opts.set_allow_mmap_writes(true);
     opts.set_optimize_filters_for_hits(true);
     opts.set_wal_recovery_mode(DBRecoveryMode::TolerateCorruptedTailRecords);
+    tuning.apply(&mut opts);
     opts
 }
 
+///
+// bucket column families keep object chunks in blob files, a collection's `_storage` goes over `cfg_db`
+///
+pub fn cfg_cf(name: &str, log: &str, tuning: &Tuning) -> Options {
+    let mut opts = cfg_db(log, tuning);
+    if is_bucket_cf(name) {
+        opts.set_enable_blob_files(true);
+        opts.set_min_blob_size(MIN_BLOB_SIZE);
+    }
+    tuning.apply_cf(name, &mut opts);
+    opts
+}
+
+pub fn is_bucket_cf(name: &str) -> bool {
+    name.split('#').nth(1).map_or(false, |m| m.eq(BUCKET_MARKER)) && name.split('#').count() == 3
+}
+
 pub fn query_log_cf_path(log_path: &str) -> String {
     let path = Path::new(log_path);
     let log = path.join("query-log");
@@ -48,23 +67,34 @@ pub fn key_merger(keys: Vec<String>) -> Option<String> {
     Some(keys.join("#"))
 }
 
-pub fn key_splitter(key: String) -> Option<Vec<String>> {
-    if !key.contains("#") {
-        return None;
+///
+// schemas stored before `_optional` was validated as a boolean carry it as `"true"`/`"false"`
+///
+pub fn bool_or_string<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+    where D: Deserializer<'de> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Bool(b)) => Ok(Some(b)),
+        Some(Value::String(s)) => s.parse::<bool>()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("expected `true` or `false`, found `{}`", s))),
+        Some(other) => Err(D::Error::custom(format!("expected a boolean, found `{}`", other))),
     }
-    let keys = key.split("#").into_iter().map(|s|s.to_string()).collect_vec();
-    Some(keys)
 }
 
-pub fn ksm_db(key: String) -> Option<String> {
-    let keys = key_splitter(key);
-    if keys.is_none() {
-        return None;
-    }
-    let res = keys.unwrap();
-    if res.len() != 2 {
-        return None;
+#[cfg(test)]
+mod tests {
+    use crate::hdrs::JqlSchemaFields;
+
+    #[test]
+    fn legacy_optional() {
+        let field = |optional: &str| serde_json::from_str::<JqlSchemaFields>(
+            &format!(r#"{{ "_name":"age", "_declare":"int"{} }}"#, optional));
+        assert_eq!(field(r#", "_optional":true"#).unwrap()._optional, Some(true));
+        assert_eq!(field(r#", "_optional":"true""#).unwrap()._optional, Some(true));
+        assert_eq!(field(r#", "_optional":"false""#).unwrap()._optional, Some(false));
+        assert_eq!(field("").unwrap()._optional, None);
+        assert!(field(r#", "_optional":"yes""#).is_err());
+        assert!(field(r#", "_optional":1"#).is_err());
     }
-    let k = res.get(1).unwrap().clone();
-    Some(k)
 }
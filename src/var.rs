@@ -1,8 +1,4 @@
-pub const ENV_PATH: &str = if cfg!(debug_assertions) {
-    "/Users/julfikar/Documents/Personal.nosync/moedb/tests/moedb.toml"
-} else {
-    "./moedb.toml"
-};
+pub const CONFIG_PATH: &str = "./moedb.toml";
 
 pub const DB_CREDS: &str = "credentials";
 pub const DB_SYS: &str = "sys";
@@ -10,5 +6,41 @@ pub const DB_LOG: &str = "logs";
 
 
 pub const DB_PREFIX: &str = "database::";
+pub const SEQ_PREFIX: &str = "sequence::";
+pub const COL_PREFIX: &str = "collection::";
+pub const BUCKET_PREFIX: &str = "bucket::";
+pub const BUCKET_MARKER: &str = "objects";
+pub const FORMAT_PREFIX: &str = "format::";
+pub const KEY_FORMAT: u64 = 3;
 pub const QUERY_LOG_PREFIX: &str = "query::";
-pub const RES_LOG_PREFIX: &str = "query-result::";
\ No newline at end of file
+pub const RES_LOG_PREFIX: &str = "query-result::";
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7741";
+pub const DEFAULT_WIRE_LISTEN: &str = "127.0.0.1:7742";
+pub const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+pub const MAX_JQL_SIZE: usize = 16 * 1024 * 1024;
+pub const META_HEADER: &str = "x-moedb-meta-";
+pub const STREAM_CHUNK: usize = 512;
+pub const WIRE_IN_FLIGHT: usize = 256;
+pub const FEED_CAPACITY: usize = 4096;
+pub const TTL_SWEEP_SECS: u64 = 30;
+pub const OBJECT_CHUNK: usize = 1024 * 1024;
+pub const UPLOAD_EXPIRY_SECS: u64 = 24 * 60 * 60;
+pub const MIN_BLOB_SIZE: u64 = 4096;
+pub const INLINE_LIMIT: u64 = 64 * 1024;
+pub const BACKUP_MANIFEST: &str = "moedb-backup.json";
+pub const BACKUP_MAIN: &str = "main";
+pub const BACKUP_LOG: &str = "query-log";
+pub const EXPORT_FORMAT: u64 = 1;
+pub const EXPORT_MARKER: &str = "_moedb_export";
+pub const IMPORT_BATCH: usize = 500;
+pub const BACKFILL_BATCH: usize = 1000;
+pub const IMPORT_ERROR_LIMIT: usize = 1000;
+pub const BULK_RUN_BYTES: usize = 64 * 1024 * 1024;
+pub const CSV_ARRAY_DELIMITER: &str = "|";
+pub const SHELL_LIMIT: u64 = 20;
+pub const SHELL_HISTORY: &str = ".moedb_history";
+pub const BYTES_PER_SYNC: u64 = 1048576;
+pub const BACKGROUND_JOBS: i32 = 4;
+pub const RATE_LIMIT_REFILL_US: i64 = 100_000;
+pub const RATE_LIMIT_FAIRNESS: i32 = 10;
\ No newline at end of file
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use log::{error, info, trace};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use crate::err::MoeDbError;
+use crate::hdrs::{MoeDb, Response, Server, WireClient, WireRequest, WireResponse};
+use crate::var::{MAX_BODY_SIZE, WIRE_IN_FLIGHT};
+
+/// !```
+/// every frame is a big endian u32 length followed by that many bytes of bincode
+/// client -> server   WireRequest  { id, stmt }
+/// server -> client   WireResponse { id, time_taken, error, message, data (json bytes) }
+/// responses carry the id of their request and are written in completion order,
+/// so one connection can have up to `WIRE_IN_FLIGHT` requests in flight, the server stops
+/// reading requests until one of them is answered. A response over `MAX_BODY_SIZE` is
+/// answered with an error for its id
+/// !```
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(w: &mut W, payload: &[u8]) -> Result<(), MoeDbError> {
+    if payload.len() > MAX_BODY_SIZE {
+        return Err(MoeDbError::WireError(format!("frame of {} bytes is too large", payload.len())));
+    }
+    let len = (payload.len() as u32).to_be_bytes();
+    let mut written = w.write_all(&len).await;
+    if written.is_ok() {
+        written = w.write_all(payload).await;
+    }
+    if written.is_ok() {
+        written = w.flush().await;
+    }
+    if written.is_err() {
+        return Err(MoeDbError::WireError(written.err().unwrap().to_string()));
+    }
+    Ok(())
+}
+
+///
+// `None` means the other side closed the connection between two frames
+///
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> Result<Option<Vec<u8>>, MoeDbError> {
+    let mut len = [0u8; 4];
+    let head = r.read_exact(&mut len).await;
+    if head.is_err() {
+        let err = head.err().unwrap();
+        if err.kind() == ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(MoeDbError::WireError(err.to_string()));
+    }
+    let size = u32::from_be_bytes(len) as usize;
+    if size > MAX_BODY_SIZE {
+        return Err(MoeDbError::WireError(format!("frame of {} bytes is too large", size)));
+    }
+    let mut buf = vec![0u8; size];
+    let body = r.read_exact(&mut buf).await;
+    if body.is_err() {
+        return Err(MoeDbError::WireError(body.err().unwrap().to_string()));
+    }
+    Ok(Some(buf))
+}
+
+impl WireResponse {
+    ///
+    // bincode can't read back a `serde_json::Value`, so documents travel as json bytes
+    ///
+    pub fn from_response(id: u64, res: Response) -> Self {
+        Self {
+            id,
+            time_taken: res.time_taken,
+            error: res.error,
+            message: res.message,
+            data: res.data.map(|d| serde_json::to_vec(&d).unwrap()),
+        }
+    }
+
+    pub fn into_response(self) -> Result<Response, MoeDbError> {
+        let mut data = None;
+        if self.data.is_some() {
+            let parsed = serde_json::from_slice(self.data.unwrap().as_slice());
+            if parsed.is_err() {
+                return Err(MoeDbError::WireError(parsed.err().unwrap().to_string()));
+            }
+            data = Some(parsed.unwrap());
+        }
+        Ok(Response {
+            time_taken: self.time_taken,
+            error: self.error,
+            message: self.message,
+            data,
+        })
+    }
+}
+
+impl Server {
+    pub async fn serve_wire(&self) -> Result<(), MoeDbError> {
+        let listen = self.db.exec.env.wire_listen.clone();
+        let bound = TcpListener::bind(listen.as_str()).await;
+        if bound.is_err() {
+            let err = MoeDbError::ServerError(bound.err().unwrap().to_string());
+            error!("{}",err);
+            return Err(err);
+        }
+        info!("moedb wire protocol listening on {}", listen);
+
+        let listener = bound.unwrap();
+        loop {
+            let accepted = listener.accept().await;
+            if accepted.is_err() {
+                error!("wire accept {}",accepted.err().unwrap());
+                continue;
+            }
+            let (stream, peer) = accepted.unwrap();
+            let db = Arc::clone(&self.db);
+            tokio::spawn(async move {
+                let served = Self::wire_session(db, stream).await;
+                if served.is_err() {
+                    error!("wire session {} {}", peer, served.err().unwrap());
+                }
+            });
+        }
+    }
+
+    async fn wire_session(db: Arc<MoeDb>, stream: TcpStream) -> Result<(), MoeDbError> {
+        let _ = stream.set_nodelay(true);
+        let (mut reader, mut writer) = stream.into_split();
+        let (tx, mut rx) = mpsc::channel::<WireResponse>(WIRE_IN_FLIGHT);
+        let write_loop = tokio::spawn(async move {
+            while let Some(res) = rx.recv().await {
+                let mut payload = bincode::serialize(&res).unwrap();
+                if payload.len() > MAX_BODY_SIZE {
+                    let too_large = Response {
+                        time_taken: res.time_taken,
+                        error: true,
+                        message: MoeDbError::WireError(format!("result of {} bytes is too large", payload.len())).to_string(),
+                        data: None,
+                    };
+                    payload = bincode::serialize(&WireResponse::from_response(res.id, too_large)).unwrap();
+                }
+                let written = write_frame(&mut writer, payload.as_slice()).await;
+                if written.is_err() {
+                    error!("wire write {}",written.err().unwrap());
+                    break;
+                }
+            }
+        });
+
+        let in_flight = Arc::new(Semaphore::new(WIRE_IN_FLIGHT));
+        let mut failed = None;
+        loop {
+            let frame = read_frame(&mut reader).await;
+            if frame.is_err() {
+                failed = frame.err();
+                break;
+            }
+            let raw = frame.unwrap();
+            if raw.is_none() {
+                break;
+            }
+            let req = bincode::deserialize::<WireRequest>(raw.unwrap().as_slice());
+            if req.is_err() {
+                // without an id there is nobody to answer, the stream can't be trusted anymore
+                failed = Some(MoeDbError::WireError(req.err().unwrap().to_string()));
+                break;
+            }
+            let req = req.unwrap();
+            trace!("wire request {}", req.id);
+            // a client pipelining faster than requests finish waits here
+            let permit = Arc::clone(&in_flight).acquire_owned().await.unwrap();
+            let db = Arc::clone(&db);
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (res, _) = Self::run(db, req.stmt).await;
+                let _ = tx.send(WireResponse::from_response(req.id, res)).await;
+                drop(permit);
+            });
+        }
+
+        // requests still in flight hold a sender, the writer drains them before closing
+        drop(tx);
+        let _ = write_loop.await;
+        match failed {
+            None => Ok(()),
+            Some(err) => Err(err)
+        }
+    }
+}
+
+impl WireClient {
+    pub async fn connect(addr: &str) -> Result<Self, MoeDbError> {
+        let stream = TcpStream::connect(addr).await;
+        if stream.is_err() {
+            return Err(MoeDbError::WireError(stream.err().unwrap().to_string()));
+        }
+        let stream = stream.unwrap();
+        let _ = stream.set_nodelay(true);
+        let (mut reader, writer) = stream.into_split();
+
+        let pending: Arc<Mutex<HashMap<u64, oneshot::Sender<Response>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let dispatch = Arc::clone(&pending);
+        let closing = Arc::clone(&closed);
+        tokio::spawn(async move {
+            loop {
+                let frame = read_frame(&mut reader).await;
+                if frame.is_err() {
+                    error!("wire read {}",frame.err().unwrap());
+                    break;
+                }
+                let raw = frame.unwrap();
+                if raw.is_none() {
+                    break;
+                }
+                let res = bincode::deserialize::<WireResponse>(raw.unwrap().as_slice());
+                if res.is_err() {
+                    error!("wire read {}",res.err().unwrap());
+                    break;
+                }
+                let res = res.unwrap();
+                let id = res.id;
+                let response = match res.into_response() {
+                    Ok(response) => response,
+                    Err(err) => Response {
+                        time_taken: "".to_string(),
+                        error: true,
+                        message: err.to_string(),
+                        data: None,
+                    }
+                };
+                let waiting = dispatch.lock().unwrap().remove(&id);
+                if waiting.is_some() {
+                    let _ = waiting.unwrap().send(response);
+                }
+            }
+            // dropping the senders wakes every caller still waiting on this connection
+            let mut waiting = dispatch.lock().unwrap();
+            closing.store(true, Ordering::SeqCst);
+            waiting.clear();
+        });
+
+        Ok(Self {
+            next: AtomicU64::new(1),
+            writer: tokio::sync::Mutex::new(writer),
+            pending,
+            closed,
+        })
+    }
+
+    pub async fn execute(&self, stmt: &str) -> Result<Response, MoeDbError> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().unwrap();
+            if self.closed.load(Ordering::SeqCst) {
+                return Err(MoeDbError::WireError("connection closed".to_string()));
+            }
+            pending.insert(id, tx);
+        }
+
+        let payload = bincode::serialize(&WireRequest { id, stmt: stmt.to_string() }).unwrap();
+        let written = {
+            let mut writer = self.writer.lock().await;
+            write_frame(&mut *writer, payload.as_slice()).await
+        };
+        if written.is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(written.err().unwrap());
+        }
+
+        match rx.await {
+            Ok(res) => Ok(res),
+            Err(_) => Err(MoeDbError::WireError("connection closed".to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[tokio::test]
+    async fn frame_roundtrip() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        let res = Response {
+            time_taken: "1ms".to_string(),
+            error: false,
+            message: "".to_string(),
+            data: Some(vec![json!({ "ids":"a1", "hello":["x"] })]),
+        };
+        let payload = bincode::serialize(&WireResponse::from_response(7, res.clone())).unwrap();
+        let writing = tokio::spawn(async move {
+            write_frame(&mut a, payload.as_slice()).await.unwrap();
+        });
+        let raw = read_frame(&mut b).await.unwrap().unwrap();
+        writing.await.unwrap();
+
+        let back = bincode::deserialize::<WireResponse>(raw.as_slice()).unwrap();
+        assert_eq!(back.id, 7);
+        assert_eq!(back.into_response().unwrap().data, res.data);
+        assert!(read_frame(&mut b).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn pipelined_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // answers two requests in reverse order, echoing the statement back as data
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut reqs = vec![];
+            for _ in 0..2 {
+                let raw = read_frame(&mut stream).await.unwrap().unwrap();
+                reqs.push(bincode::deserialize::<WireRequest>(raw.as_slice()).unwrap());
+            }
+            for req in reqs.into_iter().rev() {
+                let res = Response {
+                    time_taken: "0ns".to_string(),
+                    error: false,
+                    message: "".to_string(),
+                    data: Some(vec![json!(req.stmt)]),
+                };
+                let payload = bincode::serialize(&WireResponse::from_response(req.id, res)).unwrap();
+                write_frame(&mut stream, payload.as_slice()).await.unwrap();
+            }
+        });
+
+        let client = WireClient::connect(addr.as_str()).await.unwrap();
+        let (first, second) = tokio::join!(client.execute("first"), client.execute("second"));
+        assert_eq!(first.unwrap().data.unwrap()[0], json!("first"));
+        assert_eq!(second.unwrap().data.unwrap()[0], json!("second"));
+
+        let closed = client.execute("third").await;
+        assert!(closed.is_err());
+    }
+}